@@ -0,0 +1,112 @@
+//! The `#[sqlx_helpers::test]` attribute. Use it through the `testing`
+//! feature of sqlx_helpers, which re-exports it and has the runtime support
+//! the generated code calls into.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Error, Expr, ExprLit, FnArg, ItemFn, Lit, Meta, Token, Visibility};
+
+/// Turns an async function into a test running on a tokio runtime, with its
+/// arguments built from the database given by the `DATABASE_URL` env var.
+/// Arguments can be a `Pool`, a `TestTx` rolled back after the test, or,
+/// with `temp_db`, the `TempDb` created for the test.
+///
+/// # Arguments
+/// * `migrations = "dir"` - applies the migrations of the directory first
+/// * `temp_db` - runs the test in a database of its own, using
+///   `DATABASE_URL` as the admin url
+///
+/// Example usage
+/// ```ignore
+/// #[sqlx_helpers::test(migrations = "./migrations")]
+/// async fn inserts_a_user(tx: TestTx) {
+///     query("INSERT INTO users (name) VALUES ('a')")
+///         .execute(&mut *tx.conn().await)
+///         .await
+///         .unwrap();
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn test(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args with Punctuated::<Meta, Token![,]>::parse_terminated);
+    let input = parse_macro_input!(item as ItemFn);
+    match expand(args, input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(args: Punctuated<Meta, Token![,]>, input: ItemFn) -> Result<TokenStream2, Error> {
+    let mut migrations = None;
+    let mut temp_db = false;
+    for meta in &args {
+        match meta {
+            Meta::Path(path) if path.is_ident("temp_db") => temp_db = true,
+            Meta::NameValue(nv) if nv.path.is_ident("migrations") => match &nv.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(dir), ..
+                }) => migrations = Some(dir.value()),
+                other => return Err(Error::new_spanned(other, "expected a string literal")),
+            },
+            other => {
+                return Err(Error::new_spanned(
+                    other,
+                    "expected `migrations = \"...\"` or `temp_db`",
+                ))
+            }
+        }
+    }
+    if input.sig.asyncness.is_none() {
+        return Err(Error::new_spanned(
+            input.sig.fn_token,
+            "the test function must be async",
+        ));
+    }
+    let mut types = Vec::new();
+    for arg in &input.sig.inputs {
+        match arg {
+            FnArg::Typed(arg) => types.push(arg.ty.clone()),
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new_spanned(receiver, "a test can't take self"))
+            }
+        }
+    }
+    let name = &input.sig.ident;
+    let vis = &input.vis;
+    let attrs = &input.attrs;
+    let mut inner = input.clone();
+    inner.sig.ident = format_ident!("__{}_inner", name);
+    inner.attrs.clear();
+    inner.vis = Visibility::Inherited;
+    let inner_name = &inner.sig.ident;
+    let values: Vec<_> = (0..types.len())
+        .map(|i| format_ident!("__arg{}", i))
+        .collect();
+    let migrations = match migrations {
+        Some(dir) => quote!(::core::option::Option::Some(#dir)),
+        None => quote!(::core::option::Option::None),
+    };
+    Ok(quote! {
+        #[::core::prelude::v1::test]
+        #(#attrs)*
+        #vis fn #name() {
+            #inner
+            ::sqlx_helpers::testing::run_test(
+                ::sqlx_helpers::testing::TestSetup {
+                    migrations: #migrations,
+                    temp_db: #temp_db,
+                },
+                |mut resources| async move {
+                    #(
+                        let #values =
+                            <#types as ::sqlx_helpers::testing::TestArg>::from_resources(&mut resources)
+                                .await;
+                    )*
+                    ::sqlx_helpers::testing::TestOutcome::check(#inner_name(#(#values),*).await);
+                },
+            );
+        }
+    })
+}
@@ -0,0 +1,65 @@
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpRequest, ResponseError};
+use futures::future::LocalBoxFuture;
+
+use crate::db_conn::acquire_conn;
+use crate::{DbConn, DbConnRejection, Pool};
+
+/// Wraps the Pool for registration with `App::app_data`, so the same pool
+/// can be shared by the GraphQL schema and the [`DbConn`] extractor.
+///
+/// Example usage
+/// ```ignore
+///  let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+///     .data(db_pool.clone())
+///     .finish();
+///  HttpServer::new(move || {
+///      App::new()
+///          .app_data(pool_data(db_pool.clone()))
+///          .app_data(web::Data::new(schema.clone()))
+///          .route("/webhook", web::post().to(webhook))
+///  })
+/// ```
+pub fn pool_data(pool: Pool) -> web::Data<Pool> {
+    web::Data::new(pool)
+}
+
+impl ResponseError for DbConnRejection {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DbConnRejection::NotConfigured => StatusCode::INTERNAL_SERVER_ERROR,
+            DbConnRejection::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Gets a connection from the Pool registered as app data, either wrapped
+/// by [`pool_data`] or as a plain Pool
+///
+/// Example usage
+/// ```ignore
+///  async fn webhook(mut conn: DbConn, body: String) -> actix_web::Result<HttpResponse> {
+///      query("INSERT INTO webhook_events (body) VALUES ($1)")
+///          .bind(body)
+///          .execute(&mut *conn)
+///          .await
+///          .map_err(actix_web::error::ErrorInternalServerError)?;
+///      Ok(HttpResponse::Ok().finish())
+///  }
+/// ```
+impl FromRequest for DbConn {
+    type Error = DbConnRejection;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let pool = req
+            .app_data::<web::Data<Pool>>()
+            .map(|data| data.get_ref().clone())
+            .or_else(|| req.app_data::<Pool>().cloned());
+        Box::pin(async move {
+            let pool = pool.ok_or(DbConnRejection::NotConfigured)?;
+            acquire_conn(&pool).await
+        })
+    }
+}
@@ -0,0 +1,84 @@
+use async_graphql::{Context, FieldResult, SimpleObject};
+
+use crate::{get_pool, PoolExt, PoolStats};
+
+/// Live statistics of the Pool, ready to be exposed through an admin field
+#[derive(Clone, Debug, SimpleObject)]
+pub struct PoolStatus {
+    /// Maximum number of connections the pool can hold
+    pub max_size: usize,
+    /// Number of connections currently held by the pool
+    pub size: usize,
+    /// Number of connections ready to be handed out
+    pub idle: usize,
+    /// Number of connections currently checked out
+    pub in_use: usize,
+    /// Number of callers waiting for a connection
+    pub waiting: usize,
+    /// Median of the recent acquire wait times in milliseconds
+    pub wait_p50_ms: f64,
+    /// 90th percentile of the recent acquire wait times in milliseconds
+    pub wait_p90_ms: f64,
+    /// 99th percentile of the recent acquire wait times in milliseconds
+    pub wait_p99_ms: f64,
+}
+
+impl From<PoolStats> for PoolStatus {
+    fn from(stats: PoolStats) -> Self {
+        Self {
+            max_size: stats.max_size,
+            size: stats.size,
+            idle: stats.idle,
+            in_use: stats.in_use,
+            waiting: stats.waiting,
+            wait_p50_ms: stats.wait_p50.as_secs_f64() * 1000.0,
+            wait_p90_ms: stats.wait_p90.as_secs_f64() * 1000.0,
+            wait_p99_ms: stats.wait_p99.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+/// Reads the live statistics of the Pool stored in the graphQL context. This
+/// crate does not authenticate the caller; protect the field with a guard.
+///
+/// Returns the PoolStatus or a graphQL error if no Pool is configured
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool object is stored
+///
+/// Example usage
+/// ```ignore
+/// #[Object]
+/// impl AdminQuery {
+///     #[graphql(guard = "AdminGuard")]
+///     async fn pool_status(&self, ctx: &Context<'_>) -> FieldResult<PoolStatus> {
+///         pool_status(ctx)
+///     }
+/// }
+/// ```
+pub fn pool_status(ctx: &Context<'_>) -> FieldResult<PoolStatus> {
+    Ok(get_pool(ctx)?.stats().into())
+}
+
+/// Resizes the Pool stored in the graphQL context (see
+/// [`PoolExt::resize_to`]), for an operator mutation. As with
+/// [`pool_status`], protect the field with a guard.
+///
+/// Returns the PoolStatus after the resize or a graphQL error if no Pool is
+/// configured
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool object is stored
+/// * `max_size` - the new maximum number of connections
+///
+/// Example usage
+/// ```ignore
+/// #[Object]
+/// impl AdminMutation {
+///     #[graphql(guard = "AdminGuard")]
+///     async fn resize_pool(&self, ctx: &Context<'_>, max_size: usize) -> FieldResult<PoolStatus> {
+///         resize_pool(ctx, max_size)
+///     }
+/// }
+/// ```
+pub fn resize_pool(ctx: &Context<'_>, max_size: usize) -> FieldResult<PoolStatus> {
+    Ok(get_pool(ctx)?.resize_to(max_size).into())
+}
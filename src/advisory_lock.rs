@@ -0,0 +1,176 @@
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+use deadpool::managed::Object;
+use sqlx::{query, query_scalar, Error as SqlxError, PgConnection};
+
+use crate::error::sqlstate;
+use crate::{PoolManager, ScopedConnection};
+
+/// The key of an advisory lock. String keys are hashed with 64 bit FNV-1a,
+/// which is stable across builds and platforms, so every instance of the app
+/// maps a name to the same lock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LockKey(pub i64);
+
+impl From<i64> for LockKey {
+    fn from(key: i64) -> Self {
+        LockKey(key)
+    }
+}
+
+/// 64 bit FNV-1a, a hash that doesn't change between builds
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+impl From<&str> for LockKey {
+    fn from(name: &str) -> Self {
+        LockKey(fnv1a(name.as_bytes()) as i64)
+    }
+}
+
+impl From<&String> for LockKey {
+    fn from(name: &String) -> Self {
+        LockKey::from(name.as_str())
+    }
+}
+
+/// A session level advisory lock held on a pooled connection, for
+/// serializing critical sections, e.g. the mutations of one entity, across
+/// all instances of the app. The lock is released with
+/// [`AdvisoryLock::release`] or when the guard is dropped, before the
+/// connection goes back to the pool; a connection whose locks can't be
+/// released is closed, which releases them too.
+///
+/// The guard derefs to the connection, so the critical section can run its
+/// statements on it.
+///
+/// Example usage
+/// ```ignore
+/// let conn = pool.get().await?;
+/// let mut lock = AdvisoryLock::acquire(conn, format!("invoice:{}", id).as_str()).await?;
+/// query("UPDATE invoices SET total = $1 WHERE id = $2")
+///     .bind(total)
+///     .bind(id)
+///     .execute(&mut *lock)
+///     .await?;
+/// lock.release().await?;
+/// ```
+pub struct AdvisoryLock {
+    conn: ScopedConnection,
+    key: LockKey,
+}
+
+impl AdvisoryLock {
+    /// Waits until the lock is free and takes it
+    ///
+    /// Returns the guard or the sqlx error of the lock statement
+    /// # Arguments
+    /// * `conn` - the connection to hold the lock on
+    /// * `key` - the key of the lock, an `i64` or a `&str`
+    pub async fn acquire(
+        conn: Object<PoolManager>,
+        key: impl Into<LockKey>,
+    ) -> Result<Self, SqlxError> {
+        let key = key.into();
+        let mut conn = ScopedConnection::new(conn, "SELECT pg_advisory_unlock_all()");
+        query("SELECT pg_advisory_lock($1)")
+            .bind(key.0)
+            .execute(&mut *conn)
+            .await?;
+        Ok(Self { conn, key })
+    }
+
+    /// Takes the lock if it is free, without waiting
+    ///
+    /// Returns the guard, None when another session holds the lock, or the
+    /// sqlx error of the lock statement
+    /// # Arguments
+    /// * `conn` - the connection to hold the lock on
+    /// * `key` - the key of the lock, an `i64` or a `&str`
+    pub async fn try_acquire(
+        conn: Object<PoolManager>,
+        key: impl Into<LockKey>,
+    ) -> Result<Option<Self>, SqlxError> {
+        let key = key.into();
+        let mut conn = ScopedConnection::new(conn, "SELECT pg_advisory_unlock_all()");
+        let locked = query_scalar::<_, bool>("SELECT pg_try_advisory_lock($1)")
+            .bind(key.0)
+            .fetch_one(&mut *conn)
+            .await?;
+        Ok(locked.then(|| Self { conn, key }))
+    }
+
+    /// Waits up to `timeout` for the lock, using the `lock_timeout` of the
+    /// session, which is restored afterwards
+    ///
+    /// Returns the guard, None when the lock wasn't free in time, or the sqlx
+    /// error of the lock statement
+    /// # Arguments
+    /// * `conn` - the connection to hold the lock on
+    /// * `key` - the key of the lock, an `i64` or a `&str`
+    /// * `timeout` - how long to wait for the lock
+    pub async fn acquire_timeout(
+        conn: Object<PoolManager>,
+        key: impl Into<LockKey>,
+        timeout: Duration,
+    ) -> Result<Option<Self>, SqlxError> {
+        let key = key.into();
+        let mut conn = ScopedConnection::new(conn, "SELECT pg_advisory_unlock_all()");
+        let previous = query_scalar::<_, String>("SELECT current_setting('lock_timeout')")
+            .fetch_one(&mut *conn)
+            .await?;
+        set_lock_timeout(&mut conn, &format!("{}ms", timeout.as_millis().max(1))).await?;
+        let res = query("SELECT pg_advisory_lock($1)")
+            .bind(key.0)
+            .execute(&mut *conn)
+            .await;
+        set_lock_timeout(&mut conn, &previous).await?;
+        match res {
+            Ok(_) => Ok(Some(Self { conn, key })),
+            Err(e) if sqlstate(&e).as_deref() == Some("55P03") => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The key of the lock
+    pub fn key(&self) -> LockKey {
+        self.key
+    }
+
+    /// Releases the lock and returns the connection to the pool
+    ///
+    /// Returns Ok or the sqlx error of the unlock, in which case the
+    /// connection is closed
+    pub async fn release(self) -> Result<(), SqlxError> {
+        self.conn.release().await
+    }
+}
+
+async fn set_lock_timeout(conn: &mut PgConnection, value: &str) -> Result<(), SqlxError> {
+    query("SELECT set_config('lock_timeout', $1, false)")
+        .bind(value)
+        .execute(conn)
+        .await
+        .map(|_| ())
+}
+
+impl Deref for AdvisoryLock {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        &self.conn
+    }
+}
+
+impl DerefMut for AdvisoryLock {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        &mut self.conn
+    }
+}
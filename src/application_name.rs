@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextPrepareRequest,
+};
+use async_graphql::{Context, FieldResult, Request, ServerResult};
+use async_trait::async_trait;
+use deadpool::managed::Object;
+use sqlx::query;
+
+use crate::{match_result, PoolManager};
+
+/// An opt-in async_graphql extension that makes [`crate::get_db_connection`]
+/// set the `application_name` of the connection to `<prefix>:<operation
+/// name>`, so pg_stat_activity shows which GraphQL operation holds a
+/// connection. Anonymous operations keep the application name of the pool.
+///
+/// Once a connection of a pool has been tagged, every connection of that pool
+/// is reset to the application name it connected with when it returns to
+/// the pool, so the tag never leaks to the next borrower.
+///
+/// Example usage
+/// ```ignore
+///  let mgr = PoolManager::new(&database_url)?.application_name("graphql-api");
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     EmptyMutation::default(), EmptySubscription)
+///     .data(PoolConfig::new().build(mgr)?)
+///     .extension(ApplicationNameTagging::new("graphql-api"))
+///     .finish();
+/// ```
+#[derive(Clone)]
+pub struct ApplicationNameTagging {
+    prefix: Arc<str>,
+}
+
+impl ApplicationNameTagging {
+    /// Tags connections with `<prefix>:<operation name>`
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+}
+
+/// The application name for the connections of the current request, stored
+/// in the request data by [`ApplicationNameTagging`]
+#[derive(Clone, Debug)]
+pub struct RequestApplicationName(pub String);
+
+impl ExtensionFactory for ApplicationNameTagging {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ApplicationNameExtension {
+            prefix: self.prefix.clone(),
+        })
+    }
+}
+
+struct ApplicationNameExtension {
+    prefix: Arc<str>,
+}
+
+#[async_trait]
+impl Extension for ApplicationNameExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        let request = match &request.operation_name {
+            Some(operation_name) => {
+                let name = format!("{}:{}", self.prefix, operation_name);
+                request.data(RequestApplicationName(name))
+            }
+            None => request,
+        };
+        next.run(ctx, request).await
+    }
+}
+
+/// Sets the application name of the current request on `conn`, if there is
+/// one
+pub(crate) async fn apply_application_name(
+    ctx: &Context<'_>,
+    conn: &mut Object<PoolManager>,
+) -> FieldResult<()> {
+    if let Some(RequestApplicationName(name)) = ctx.data_opt::<RequestApplicationName>() {
+        // before tagging, so the reset is on even if the statement is
+        // cancelled halfway
+        if let Some(pool) = Object::pool(conn) {
+            pool.manager().reset_application_name_on_release();
+        }
+        let res = query("SELECT set_config('application_name', $1, false)")
+            .bind(name)
+            .execute(&mut **conn)
+            .await;
+        match_result(res, "Failed to set application_name".to_string())?;
+    }
+    Ok(())
+}
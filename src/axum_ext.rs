@@ -0,0 +1,48 @@
+use async_trait::async_trait;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+use crate::db_conn::acquire_conn;
+use crate::{DbConn, DbConnRejection, Pool};
+
+impl IntoResponse for DbConnRejection {
+    fn into_response(self) -> Response {
+        let status = match self {
+            DbConnRejection::NotConfigured => StatusCode::INTERNAL_SERVER_ERROR,
+            DbConnRejection::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// An axum extractor that gets a connection from the Pool in the router
+/// state. The state must provide the Pool through `FromRef`.
+///
+/// Example usage
+/// ```ignore
+///  async fn webhook(mut conn: DbConn, body: String) -> StatusCode {
+///      query("INSERT INTO webhook_events (body) VALUES ($1)")
+///          .bind(body)
+///          .execute(&mut *conn)
+///          .await
+///          .map_or(StatusCode::INTERNAL_SERVER_ERROR, |_| StatusCode::OK)
+///  }
+///
+///  let app = Router::new()
+///      .route("/webhook", post(webhook))
+///      .with_state(db_pool);
+/// ```
+#[async_trait]
+impl<S> FromRequestParts<S> for DbConn
+where
+    Pool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = DbConnRejection;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        acquire_conn(&Pool::from_ref(state)).await
+    }
+}
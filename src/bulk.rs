@@ -0,0 +1,207 @@
+use sqlx::postgres::PgArguments;
+use sqlx::{Arguments, Error as SqlxError, PgConnection};
+
+use crate::SqlValue;
+
+/// The values of one column, bound as a single array
+enum ColumnArray {
+    Bool(Vec<Option<bool>>),
+    Int(Vec<Option<i64>>),
+    Float(Vec<Option<f64>>),
+    Text(Vec<Option<String>>),
+    Bytes(Vec<Option<Vec<u8>>>),
+}
+
+impl ColumnArray {
+    /// Collects the `index`th value of every row, typed after the first non
+    /// null value; a column of only NULLs is a text array
+    fn collect(rows: &[Vec<SqlValue>], index: usize, column: &str) -> Result<Self, SqlxError> {
+        let values = rows.iter().map(|row| &row[index]);
+        let first = values.clone().find(|value| **value != SqlValue::Null);
+        let mismatch = |value: &SqlValue| {
+            SqlxError::Encode(
+                format!(
+                    "Column {} mixes value types: {:?} and {:?}",
+                    column, first, value
+                )
+                .into(),
+            )
+        };
+        macro_rules! collect_as {
+            ($variant:ident) => {
+                values
+                    .map(|value| match value {
+                        SqlValue::Null => Ok(None),
+                        SqlValue::$variant(v) => Ok(Some(v.clone())),
+                        other => Err(mismatch(other)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(ColumnArray::$variant)
+            };
+        }
+        match first {
+            Some(SqlValue::Bool(_)) => collect_as!(Bool),
+            Some(SqlValue::Int(_)) => collect_as!(Int),
+            Some(SqlValue::Float(_)) => collect_as!(Float),
+            Some(SqlValue::Bytes(_)) => collect_as!(Bytes),
+            Some(SqlValue::Text(_)) | Some(SqlValue::Null) | None => collect_as!(Text),
+        }
+    }
+
+    fn array_type(&self) -> &'static str {
+        match self {
+            ColumnArray::Bool(_) => "bool[]",
+            ColumnArray::Int(_) => "int8[]",
+            ColumnArray::Float(_) => "float8[]",
+            ColumnArray::Text(_) => "text[]",
+            ColumnArray::Bytes(_) => "bytea[]",
+        }
+    }
+
+    fn add_to(self, args: &mut PgArguments) {
+        match self {
+            ColumnArray::Bool(v) => args.add(v),
+            ColumnArray::Int(v) => args.add(v),
+            ColumnArray::Float(v) => args.add(v),
+            ColumnArray::Text(v) => args.add(v),
+            ColumnArray::Bytes(v) => args.add(v),
+        }
+    }
+}
+
+/// Inserts `rows` into `table` in one round trip by binding one array per
+/// column and selecting from `UNNEST`, i.e.
+/// `INSERT INTO t (a, b) SELECT * FROM UNNEST($1::int8[], $2::text[])`, so
+/// the statement stays the same whatever the number of rows.
+///
+/// The array type of a column follows its values. Write a column as
+/// `name::type`, e.g. `"created_at::timestamptz"`, for types without an
+/// assignment cast from the bound values, such as timestamps or uuids sent
+/// as text. Table and column names are written into the SQL as given, so
+/// never pass user input as a name.
+///
+/// Returns the number of inserted rows or the sqlx error; rows without one
+/// value per column and columns mixing value types are an `Encode` error
+/// # Arguments
+/// * `conn` - the connection to run the insert on
+/// * `table` - the table to insert into
+/// * `columns` - the columns to fill, optionally with a type
+/// * `rows` - the values of every row, in column order
+///
+/// Example usage
+/// ```ignore
+/// let rows: Vec<Vec<SqlValue>> = events
+///     .iter()
+///     .map(|e| vec![e.kind.as_str().into(), e.at.to_rfc3339().into()])
+///     .collect();
+/// bulk_insert(&mut conn, "events", &["kind", "at::timestamptz"], &rows).await?;
+/// ```
+pub async fn bulk_insert(
+    conn: &mut PgConnection,
+    table: &str,
+    columns: &[&str],
+    rows: &[Vec<SqlValue>],
+) -> Result<u64, SqlxError> {
+    if rows.is_empty() {
+        return Ok(0);
+    }
+    let (sql, args) = insert_statement(table, columns, rows)?;
+    let res = sqlx::query_with(&sql, args).execute(conn).await?;
+    Ok(res.rows_affected())
+}
+
+/// The `INSERT ... SELECT * FROM UNNEST(...)` statement of [`bulk_insert`]
+/// and its array arguments
+fn insert_statement(
+    table: &str,
+    columns: &[&str],
+    rows: &[Vec<SqlValue>],
+) -> Result<(String, PgArguments), SqlxError> {
+    if let Some(row) = rows.iter().find(|row| row.len() != columns.len()) {
+        return Err(SqlxError::Encode(
+            format!(
+                "Row has {} values but the insert fills {} columns",
+                row.len(),
+                columns.len()
+            )
+            .into(),
+        ));
+    }
+    let mut names = Vec::with_capacity(columns.len());
+    let mut params = Vec::with_capacity(columns.len());
+    let mut args = PgArguments::default();
+    for (i, column) in columns.iter().enumerate() {
+        let (name, cast) = match column.split_once("::") {
+            Some((name, cast)) => (name, Some(cast)),
+            None => (*column, None),
+        };
+        let array = ColumnArray::collect(rows, i, name)?;
+        let array_type = match cast {
+            Some(cast) => format!("{}[]", cast),
+            None => array.array_type().to_string(),
+        };
+        names.push(name);
+        params.push(format!("${}::{}", i + 1, array_type));
+        array.add_to(&mut args);
+    }
+    let sql = format!(
+        "INSERT INTO {} ({}) SELECT * FROM UNNEST({})",
+        table,
+        names.join(", "),
+        params.join(", ")
+    );
+    Ok((sql, args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_one_array_per_column() {
+        let rows = vec![
+            vec![1i64.into(), "a".into(), true.into()],
+            vec![2i64.into(), SqlValue::Null, false.into()],
+        ];
+        let (sql, _) = insert_statement("items", &["id", "name", "active"], &rows).unwrap();
+        assert_eq!(
+            sql,
+            "INSERT INTO items (id, name, active) \
+             SELECT * FROM UNNEST($1::int8[], $2::text[], $3::bool[])"
+        );
+    }
+
+    #[test]
+    fn casts_typed_columns() {
+        let rows = vec![vec!["signup".into(), "2024-01-01T00:00:00Z".into()]];
+        let (sql, _) = insert_statement("events", &["kind", "at::timestamptz"], &rows).unwrap();
+        assert_eq!(
+            sql,
+            "INSERT INTO events (kind, at) SELECT * FROM UNNEST($1::text[], $2::timestamptz[])"
+        );
+    }
+
+    #[test]
+    fn null_columns_are_text_arrays() {
+        let rows = vec![vec![SqlValue::Null], vec![SqlValue::Null]];
+        let (sql, _) = insert_statement("notes", &["body"], &rows).unwrap();
+        assert_eq!(
+            sql,
+            "INSERT INTO notes (body) SELECT * FROM UNNEST($1::text[])"
+        );
+    }
+
+    #[test]
+    fn rejects_rows_of_the_wrong_length() {
+        let rows = vec![vec![1i64.into()], vec![2i64.into(), "b".into()]];
+        let res = insert_statement("items", &["id"], &rows);
+        assert!(matches!(res, Err(SqlxError::Encode(_))));
+    }
+
+    #[test]
+    fn rejects_columns_mixing_value_types() {
+        let rows = vec![vec![1i64.into()], vec!["two".into()]];
+        let res = insert_statement("items", &["id"], &rows);
+        assert!(matches!(res, Err(SqlxError::Encode(_))));
+    }
+}
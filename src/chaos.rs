@@ -0,0 +1,79 @@
+use std::io;
+use std::time::Duration;
+
+use sqlx::{Error as SqlxError, Executor, PgConnection};
+
+use crate::manager::random_fraction;
+
+/// A probability profile of faults for a [`crate::PoolManager`] to inject,
+/// so the resilience of resolvers, retries and circuit breakers can be tested
+/// before the database misbehaves for real. Every rate is a share from 0.0
+/// (never) to 1.0 (always); all of them default to 0.0.
+///
+/// Only enable it in test and staging environments.
+///
+/// Example usage
+/// ```ignore
+///  let mgr = PoolManager::new(&database_url)?
+///     .connect_retry(ConnectRetry::default())
+///     .fault_injection(FaultInjection {
+///         connect_failure_rate: 0.2,
+///         latency_rate: 0.1,
+///         acquire_latency: Duration::from_millis(500),
+///         drop_rate: 0.05,
+///     });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FaultInjection {
+    /// Share of connection attempts failing with a refused connection, a
+    /// transient error that [`crate::ConnectRetry`] retries
+    pub connect_failure_rate: f64,
+    /// Share of acquires delayed by `acquire_latency`
+    pub latency_rate: f64,
+    /// The delay added to the acquires picked by `latency_rate`
+    pub acquire_latency: Duration,
+    /// Share of acquires handing out a connection whose server process was
+    /// terminated, so its next statement fails like after a network drop
+    pub drop_rate: f64,
+}
+
+impl FaultInjection {
+    fn hit(rate: f64, seed: u32) -> bool {
+        rate > 0.0 && random_fraction(seed) < rate.clamp(0.0, 1.0)
+    }
+
+    /// Fails the connection attempt according to `connect_failure_rate`
+    pub(crate) fn before_connect(&self) -> Result<(), SqlxError> {
+        if !Self::hit(self.connect_failure_rate, 1) {
+            return Ok(());
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!("injecting a connect failure");
+        Err(SqlxError::Io(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            "fault injection: connection refused",
+        )))
+    }
+
+    /// Delays the acquire according to `latency_rate`, then terminates the
+    /// server process of `conn` according to `drop_rate`
+    pub(crate) async fn on_acquire(&self, conn: &mut PgConnection) {
+        if Self::hit(self.latency_rate, 2) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                latency_ms = self.acquire_latency.as_millis() as u64,
+                "injecting acquire latency"
+            );
+            tokio::time::sleep(self.acquire_latency).await;
+        }
+        if Self::hit(self.drop_rate, 3) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!("injecting a dropped connection");
+            // the statement fails as its own session goes away, which is the
+            // point
+            let _ = conn
+                .execute("SELECT pg_terminate_backend(pg_backend_pid())")
+                .await;
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use sqlx::Error as SqlxError;
+
+/// Consecutive failures after which a [`CircuitBreaker`] opens by default
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+
+/// How long an open [`CircuitBreaker`] fails fast by default
+pub const DEFAULT_OPEN_DURATION: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe is running since the instant
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+/// A circuit breaker for connection creation. After `failure_threshold`
+/// consecutive failed creates or recycles it opens: new connections fail
+/// immediately for `open_duration`, keeping load off a struggling database
+/// and resolver latency bounded. Afterwards a single probe connection is let
+/// through (half open); its success closes the breaker, its failure opens it
+/// again.
+///
+/// Example usage
+/// ```ignore
+///  let mgr = PoolManager::new(&database_url)?
+///     .circuit_breaker(CircuitBreaker::new(5, Duration::from_secs(10)));
+/// ```
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<State>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_OPEN_DURATION)
+    }
+}
+
+impl CircuitBreaker {
+    /// Creates a closed breaker
+    ///
+    /// # Arguments
+    /// * `failure_threshold` - consecutive failures that open the breaker
+    /// * `open_duration` - how long the breaker fails fast once open
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether the breaker currently fails connection attempts fast
+    pub fn is_open(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), State::Open { until } if until > Instant::now())
+    }
+
+    /// Checks whether a connection attempt may go ahead. An open breaker
+    /// whose open duration passed lets this attempt through as the probe.
+    pub(crate) fn allow(&self) -> Result<(), SqlxError> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if until <= now => {
+                *state = State::HalfOpen { since: now };
+                Ok(())
+            }
+            // a probe that never reported back (e.g. cancelled by the create
+            // timeout) is replaced after another open duration
+            State::HalfOpen { since } if since + self.open_duration <= now => {
+                *state = State::HalfOpen { since: now };
+                Ok(())
+            }
+            State::Open { .. } | State::HalfOpen { .. } => Err(SqlxError::Io(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "circuit breaker is open, not connecting to the database",
+            ))),
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        *self.state.lock().unwrap() = State::Closed { failures: 0 };
+    }
+
+    pub(crate) fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            // a failed probe, or a recycle failing while open
+            State::HalfOpen { .. } | State::Open { .. } => self.failure_threshold,
+        };
+        *state = if failures >= self.failure_threshold {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(failures, "circuit breaker opened");
+            State::Open {
+                until: Instant::now() + self.open_duration,
+            }
+        } else {
+            State::Closed { failures }
+        };
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use deadpool::managed::{BuildError, Manager, QueueMode};
+use deadpool::Runtime;
+
+use crate::{Pool, PoolManager};
+
+/// Default number of connections a [`Pool`] built from [`PoolConfig`] can hold
+pub const DEFAULT_MAX_SIZE: usize = 16;
+
+/// A builder for constructing a tuned [`Pool`] without reaching into
+/// deadpool internals.
+///
+/// Example usage
+/// ```ignore
+///  let mgr = PoolManager::new(&database_url)?;
+///  let db_pool = PoolConfig::new()
+///     .max_size(32)
+///     .wait_timeout(Duration::from_secs(5))
+///     .queue_mode(QueueMode::Lifo)
+///     .build(mgr)?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    pub max_size: usize,
+    pub wait_timeout: Option<Duration>,
+    pub create_timeout: Option<Duration>,
+    pub recycle_timeout: Option<Duration>,
+    pub queue_mode: QueueMode,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_SIZE,
+            wait_timeout: None,
+            create_timeout: None,
+            recycle_timeout: None,
+            queue_mode: QueueMode::default(),
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Creates a config with the default max size and no timeouts
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum number of connections held by the pool
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets how long a caller waits for a free connection
+    pub fn wait_timeout(mut self, timeout: Duration) -> Self {
+        self.wait_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long establishing a new connection may take
+    pub fn create_timeout(mut self, timeout: Duration) -> Self {
+        self.create_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long recycling (pinging) an existing connection may take
+    pub fn recycle_timeout(mut self, timeout: Duration) -> Self {
+        self.recycle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets whether idle connections are handed out oldest first (Fifo) or
+    /// most recently used first (Lifo)
+    pub fn queue_mode(mut self, queue_mode: QueueMode) -> Self {
+        self.queue_mode = queue_mode;
+        self
+    }
+
+    /// Builds a [`Pool`] for the given manager using this config
+    ///
+    /// Returns the pool or a deadpool BuildError
+    /// # Arguments
+    /// * `manager` - the pool manager used to create and recycle connections
+    pub fn build(self, manager: PoolManager) -> Result<Pool, BuildError> {
+        self.build_for(manager)
+    }
+
+    /// Builds a pool for any manager, e.g. a [`crate::DbPoolManager`] of
+    /// another sqlx driver
+    ///
+    /// Returns the pool or a deadpool BuildError
+    /// # Arguments
+    /// * `manager` - the pool manager used to create and recycle connections
+    pub fn build_for<M: Manager>(
+        self,
+        manager: M,
+    ) -> Result<deadpool::managed::Pool<M>, BuildError> {
+        deadpool::managed::Pool::builder(manager)
+            .max_size(self.max_size)
+            .wait_timeout(self.wait_timeout)
+            .create_timeout(self.create_timeout)
+            .recycle_timeout(self.recycle_timeout)
+            .queue_mode(self.queue_mode)
+            .runtime(Runtime::Tokio1)
+            .build()
+    }
+}
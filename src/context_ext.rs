@@ -0,0 +1,97 @@
+use std::ops::{Deref, DerefMut};
+
+use async_graphql::{Context, FieldResult};
+use async_trait::async_trait;
+use deadpool::managed::Object;
+use sqlx::{Executor, PgConnection};
+
+use crate::error::map_tx_error;
+use crate::{get_db_connection, PoolManager, ScopedConnection};
+
+/// A connection with an open transaction from [`ContextExt::db_tx`]. The
+/// transaction is rolled back unless [`DbTransaction::commit`] is called,
+/// also when the value is dropped. The transaction is opened with a plain
+/// `BEGIN`, so don't call `begin()` on the connection.
+pub struct DbTransaction {
+    conn: ScopedConnection,
+}
+
+impl DbTransaction {
+    /// Commits the transaction and returns the connection to the pool
+    pub async fn commit(self) -> FieldResult<()> {
+        self.conn
+            .release_with("COMMIT")
+            .await
+            .map_err(|e| map_tx_error(e, "Failed to commit transaction"))
+    }
+
+    /// Rolls the transaction back and returns the connection to the pool
+    pub async fn rollback(self) -> FieldResult<()> {
+        self.conn
+            .release()
+            .await
+            .map_err(|e| map_tx_error(e, "Failed to rollback transaction"))
+    }
+}
+
+impl Deref for DbTransaction {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        &self.conn
+    }
+}
+
+impl DerefMut for DbTransaction {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        &mut self.conn
+    }
+}
+
+/// Extension methods on the graphQL context, so resolvers can write
+/// `ctx.db().await?` instead of calling [`get_db_connection`]
+///
+/// Example usage
+/// ```ignore
+/// use sqlx_helpers::ContextExt;
+///
+/// let mut conn = ctx.db().await?;
+/// let rows = query_as::<_, MyData>("SELECT * FROM my_data")
+///     .fetch_all(&mut *conn)
+///     .await;
+///
+/// let mut tx = ctx.db_tx().await?;
+/// let res = query("INSERT INTO my_data (name) VALUES ($1)")
+///     .bind(name)
+///     .execute(&mut *tx)
+///     .await;
+/// match_result(res, format!("Failed to insert my_data"))?;
+/// tx.commit().await?;
+/// ```
+#[async_trait]
+pub trait ContextExt {
+    /// Gets a connection out of the Pool stored in the context (see
+    /// [`get_db_connection`])
+    async fn db(&self) -> FieldResult<Object<PoolManager>>;
+
+    /// Gets a connection out of the Pool stored in the context and opens a
+    /// transaction on it
+    async fn db_tx(&self) -> FieldResult<DbTransaction>;
+}
+
+#[async_trait]
+impl ContextExt for Context<'_> {
+    async fn db(&self) -> FieldResult<Object<PoolManager>> {
+        get_db_connection(self).await
+    }
+
+    async fn db_tx(&self) -> FieldResult<DbTransaction> {
+        let conn = get_db_connection(self).await?;
+        let mut conn = ScopedConnection::new(conn, "ROLLBACK");
+        (&mut *conn)
+            .execute("BEGIN")
+            .await
+            .map_err(|e| map_tx_error(e, "Failed to begin transaction"))?;
+        Ok(DbTransaction { conn })
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt::Write;
+
+use bytes::Bytes;
+use deadpool::managed::Object;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use sqlx::{Error as SqlxError, PgConnection};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+
+use crate::error::pool_error_to_sqlx;
+use crate::stats::timed_get;
+use crate::{Pool, SqlValue};
+
+/// The size of the chunks sent to the server during a `COPY ... FROM STDIN`
+pub const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Streams the output of `reader` into a `COPY ... FROM STDIN` statement, in
+/// whatever format the statement declares (text, CSV or binary). If reading
+/// fails the copy is aborted, so no rows are imported.
+///
+/// Returns the number of rows imported or the sqlx error
+/// # Arguments
+/// * `conn` - the connection to run the copy on
+/// * `statement` - the full `COPY ... FROM STDIN` statement
+/// * `reader` - the data to import
+///
+/// Example usage
+/// ```ignore
+/// let file = tokio::fs::File::open("orders.csv").await?;
+/// let rows = copy_in(
+///     &mut conn,
+///     "COPY orders (id, customer_id, total) FROM STDIN WITH (FORMAT csv, HEADER)",
+///     file,
+/// )
+/// .await?;
+/// ```
+pub async fn copy_in<R>(
+    conn: &mut PgConnection,
+    statement: &str,
+    mut reader: R,
+) -> Result<u64, SqlxError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut copy = conn.copy_in_raw(statement).await?;
+    let mut buf = vec![0u8; COPY_CHUNK_SIZE];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => {
+                copy.send(&buf[..n]).await?;
+            }
+            Err(e) => {
+                let _ = copy.abort(e.to_string()).await;
+                return Err(SqlxError::Io(e));
+            }
+        }
+    }
+    copy.finish().await
+}
+
+/// Imports `rows` into `columns` of `table` with a CSV encoded
+/// `COPY ... FROM STDIN`, for imports too large for INSERT loops. Every row
+/// must have one value per column, in column order.
+///
+/// Table and column names are written into the SQL as given, so never pass
+/// user input as a name.
+///
+/// Returns the number of rows imported or the sqlx error
+/// # Arguments
+/// * `conn` - the connection to run the copy on
+/// * `table` - the table to import into
+/// * `columns` - the columns to fill
+/// * `rows` - the values of every row
+///
+/// Example usage
+/// ```ignore
+/// let rows = orders
+///     .iter()
+///     .map(|order| vec![order.id.into(), order.customer_id.into(), order.total.into()]);
+/// copy_in_rows(&mut conn, "orders", &["id", "customer_id", "total"], rows).await?;
+/// ```
+pub async fn copy_in_rows<I>(
+    conn: &mut PgConnection,
+    table: &str,
+    columns: &[&str],
+    rows: I,
+) -> Result<u64, SqlxError>
+where
+    I: IntoIterator<Item = Vec<SqlValue>>,
+{
+    let statement = format!(
+        "COPY {} ({}) FROM STDIN WITH (FORMAT csv)",
+        table,
+        columns.join(", ")
+    );
+    let mut copy = conn.copy_in_raw(&statement).await?;
+    let mut buf = String::with_capacity(COPY_CHUNK_SIZE);
+    for row in rows {
+        if row.len() != columns.len() {
+            let msg = format!(
+                "Row has {} values but the copy fills {} columns",
+                row.len(),
+                columns.len()
+            );
+            let _ = copy.abort(msg.clone()).await;
+            return Err(SqlxError::Protocol(msg));
+        }
+        encode_csv_row(&mut buf, &row);
+        if buf.len() >= COPY_CHUNK_SIZE {
+            copy.send(buf.as_bytes()).await?;
+            buf.clear();
+        }
+    }
+    if !buf.is_empty() {
+        copy.send(buf.as_bytes()).await?;
+    }
+    copy.finish().await
+}
+
+/// Appends one CSV line; NULL is an unquoted empty field and every other
+/// text value is quoted, so empty strings stay distinct from NULL
+fn encode_csv_row(buf: &mut String, row: &[SqlValue]) {
+    for (i, value) in row.iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        match value {
+            SqlValue::Null => {}
+            SqlValue::Bool(v) => buf.push(if *v { 't' } else { 'f' }),
+            SqlValue::Int(v) => {
+                let _ = write!(buf, "{}", v);
+            }
+            SqlValue::Float(v) => {
+                let _ = write!(buf, "{}", v);
+            }
+            SqlValue::Text(v) => {
+                buf.push('"');
+                buf.push_str(&v.replace('"', "\"\""));
+                buf.push('"');
+            }
+            SqlValue::Bytes(v) => {
+                buf.push_str("\"\\x");
+                for byte in v {
+                    let _ = write!(buf, "{:02x}", byte);
+                }
+                buf.push('"');
+            }
+        }
+    }
+    buf.push('\n');
+}
+
+/// The data format of a `COPY ... TO STDOUT` export
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CopyFormat {
+    /// Tab separated text
+    Text,
+    /// CSV with a header line
+    Csv,
+    /// The binary COPY format
+    Binary,
+}
+
+impl CopyFormat {
+    fn options(self) -> &'static str {
+        match self {
+            CopyFormat::Text => "FORMAT text",
+            CopyFormat::Csv => "FORMAT csv, HEADER",
+            CopyFormat::Binary => "FORMAT binary",
+        }
+    }
+}
+
+fn copy_out_statement(query: &str, format: CopyFormat) -> String {
+    format!("COPY ({}) TO STDOUT WITH ({})", query, format.options())
+}
+
+/// Exports the rows of `query` with `COPY (...) TO STDOUT` and yields the
+/// encoded data in chunks as the server sends it. The connection stays
+/// borrowed until the stream is dropped; use [`copy_out_pooled`] to hand the
+/// stream to an HTTP response.
+///
+/// Returns the stream of chunks or the sqlx error of starting the copy
+/// # Arguments
+/// * `conn` - the connection to run the copy on
+/// * `query` - the SELECT whose rows are exported, without binds
+/// * `format` - the format of the exported data
+///
+/// Example usage
+/// ```ignore
+/// let mut chunks = copy_out(&mut conn, "SELECT * FROM orders", CopyFormat::Csv).await?;
+/// while let Some(chunk) = chunks.next().await {
+///     file.write_all(&chunk?).await?;
+/// }
+/// ```
+pub async fn copy_out<'c>(
+    conn: &'c mut PgConnection,
+    query: &str,
+    format: CopyFormat,
+) -> Result<BoxStream<'c, Result<Bytes, SqlxError>>, SqlxError> {
+    conn.copy_out_raw(&copy_out_statement(query, format)).await
+}
+
+/// Like [`copy_out`], but on a connection of its own from `pool` that is
+/// held by a spawned task until the export is done, so the stream is
+/// `'static` and can be used as the body of an HTTP response served next to
+/// the GraphQL endpoint. Failures to get the connection or start the copy
+/// are the first item of the stream.
+///
+/// Returns the stream of chunks
+/// # Arguments
+/// * `pool` - the pool to get the connection from
+/// * `query` - the SELECT whose rows are exported, without binds
+/// * `format` - the format of the exported data
+///
+/// Example usage
+/// ```ignore
+/// async fn export(State(pool): State<Pool>) -> impl IntoResponse {
+///     let chunks = copy_out_pooled(&pool, "SELECT * FROM orders", CopyFormat::Csv);
+///     Body::wrap_stream(chunks)
+/// }
+/// ```
+pub fn copy_out_pooled(
+    pool: &Pool,
+    query: &str,
+    format: CopyFormat,
+) -> impl Stream<Item = Result<Bytes, SqlxError>> + Send + 'static {
+    let pool = pool.clone();
+    let statement = copy_out_statement(query, format);
+    // a small buffer applies backpressure to the copy when the client is slow
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut conn = match timed_get(&pool).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                let _ = tx.send(Err(pool_error_to_sqlx(e))).await;
+                return;
+            }
+        };
+        let mut chunks = match conn.copy_out_raw(&statement).await {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        let mut abandoned = false;
+        while let Some(chunk) = chunks.next().await {
+            if tx.send(chunk).await.is_err() {
+                abandoned = true;
+                break;
+            }
+        }
+        drop(chunks);
+        if abandoned {
+            // the receiver is gone mid copy, so the connection is closed
+            // rather than returned to the pool with the copy still running
+            drop(Object::take(conn));
+        }
+    });
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use sqlx::postgres::PgConnectOptions;
+use sqlx::Error as SqlxError;
+
+/// A username and password for new connections
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    /// Creates credentials from a username and password
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    pub(crate) fn apply(&self, options: PgConnectOptions) -> PgConnectOptions {
+        options.username(&self.username).password(&self.password)
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// A source of credentials that is asked for fresh ones every time the
+/// [`crate::PoolManager`] creates a connection, so rotated passwords and
+/// short lived tokens are picked up without rebuilding the pool. Existing
+/// connections stay open with the credentials they were created with.
+///
+/// Example usage
+/// ```ignore
+///  struct FileCredentials(PathBuf);
+///
+///  #[async_trait]
+///  impl CredentialsProvider for FileCredentials {
+///      async fn fetch(&self) -> Result<Credentials, sqlx::Error> {
+///          let password = tokio::fs::read_to_string(&self.0).await?;
+///          Ok(Credentials::new("app", password.trim()))
+///      }
+///  }
+///
+///  let mgr = PoolManager::new("postgres://db.internal/app")?
+///     .credentials_provider(FileCredentials("/run/secrets/db".into()));
+/// ```
+#[async_trait]
+pub trait CredentialsProvider: Send + Sync {
+    /// Returns the credentials for the next connection
+    async fn fetch(&self) -> Result<Credentials, SqlxError>;
+
+    /// Called when the server rejected the fetched credentials, before they
+    /// are fetched again. Providers that cache should drop the cached value.
+    fn invalidate(&self) {}
+}
@@ -0,0 +1,167 @@
+#[cfg(feature = "async-graphql")]
+use std::sync::Arc;
+
+#[cfg(feature = "async-graphql")]
+use async_graphql::{Context, FieldResult};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use sqlx::{query_scalar_with, query_with, Error as SqlxError};
+
+use crate::error::pool_error_to_sqlx;
+use crate::stats::timed_get;
+use crate::{to_arguments, Pool, SqlValue};
+
+/// The database as seen by resolver logic: statements and queries built from
+/// SQL and [`SqlValue`] binds. The Pool implements it by running them on a
+/// pooled connection; `testing::FakeDb` implements it in memory, so the
+/// logic can be unit tested without Postgres.
+///
+/// Rows come back as JSON objects keyed by column name; use
+/// `fetch_all` and friends to decode them into structs
+/// deriving `Deserialize`.
+///
+/// Example usage
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct User {
+///     id: i64,
+///     name: String,
+/// }
+///
+/// async fn active_users(db: &dyn DbAccess) -> Result<Vec<User>, sqlx::Error> {
+///     db.fetch_all("SELECT id, name FROM users WHERE active = $1", &[true.into()])
+///         .await
+/// }
+///
+/// // production
+/// let users = active_users(&db_pool).await?;
+/// ```
+#[async_trait]
+pub trait DbAccess: Send + Sync {
+    /// Runs a statement
+    ///
+    /// Returns the number of rows affected or the sqlx error
+    /// # Arguments
+    /// * `sql` - the statement, with `$1`, `$2`, ... placeholders
+    /// * `binds` - the values of the placeholders
+    async fn execute(&self, sql: &str, binds: &[SqlValue]) -> Result<u64, SqlxError>;
+
+    /// Runs a query, or a statement with a `RETURNING` clause
+    ///
+    /// Returns every row as a JSON object keyed by column name, or the sqlx
+    /// error
+    /// # Arguments
+    /// * `sql` - the query, with `$1`, `$2`, ... placeholders
+    /// * `binds` - the values of the placeholders
+    async fn fetch_json(&self, sql: &str, binds: &[SqlValue]) -> Result<Vec<Value>, SqlxError>;
+}
+
+impl<'d> dyn DbAccess + 'd {
+    /// Runs a query and decodes every row into a `T`
+    ///
+    /// Returns the rows, the sqlx error of the query, or a `Decode` error
+    /// when a row doesn't match `T`
+    pub async fn fetch_all<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        binds: &[SqlValue],
+    ) -> Result<Vec<T>, SqlxError> {
+        self.fetch_json(sql, binds)
+            .await?
+            .into_iter()
+            .map(decode_row)
+            .collect()
+    }
+
+    /// Runs a query and decodes its first row into a `T`
+    ///
+    /// Returns the row, None when the query returned no row, the sqlx error
+    /// of the query, or a `Decode` error when the row doesn't match `T`
+    pub async fn fetch_optional<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        binds: &[SqlValue],
+    ) -> Result<Option<T>, SqlxError> {
+        self.fetch_json(sql, binds)
+            .await?
+            .into_iter()
+            .next()
+            .map(decode_row)
+            .transpose()
+    }
+
+    /// Like `fetch_optional`, with
+    /// `RowNotFound` when the query returned no row
+    pub async fn fetch_one<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        binds: &[SqlValue],
+    ) -> Result<T, SqlxError> {
+        self.fetch_optional(sql, binds)
+            .await?
+            .ok_or(SqlxError::RowNotFound)
+    }
+}
+
+fn decode_row<T: DeserializeOwned>(row: Value) -> Result<T, SqlxError> {
+    serde_json::from_value(row).map_err(|e| SqlxError::Decode(Box::new(e)))
+}
+
+#[async_trait]
+impl DbAccess for Pool {
+    async fn execute(&self, sql: &str, binds: &[SqlValue]) -> Result<u64, SqlxError> {
+        #[cfg(feature = "async-graphql")]
+        crate::n_plus_one::record_sql(sql);
+        let mut conn = timed_get(self).await.map_err(pool_error_to_sqlx)?;
+        let res = query_with(sql, to_arguments(binds))
+            .execute(&mut *conn)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    async fn fetch_json(&self, sql: &str, binds: &[SqlValue]) -> Result<Vec<Value>, SqlxError> {
+        #[cfg(feature = "async-graphql")]
+        crate::n_plus_one::record_sql(sql);
+        let mut conn = timed_get(self).await.map_err(pool_error_to_sqlx)?;
+        // a CTE rather than a subquery, so INSERT ... RETURNING works too
+        let sql = format!("WITH t AS ({}) SELECT to_jsonb(t)::text FROM t", sql);
+        let rows = query_scalar_with::<_, String, _>(&sql, to_arguments(binds))
+            .fetch_all(&mut *conn)
+            .await?;
+        rows.iter()
+            .map(|row| serde_json::from_str(row).map_err(|e| SqlxError::Decode(Box::new(e))))
+            .collect()
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// Looks up the `Arc<dyn DbAccess>` stored in the graphQL context, which is
+/// the Pool in production and a fake in unit tests
+///
+/// Returns the DbAccess or a graphQL error with the `POOL_NOT_CONFIGURED`
+/// code if the schema was built without one
+/// # Arguments
+/// * `ctx` - graphQL context where the DbAccess is stored
+///
+/// Example usage
+/// ```ignore
+/// let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+///     .data(Arc::new(db_pool.clone()) as Arc<dyn DbAccess>)
+///     .finish();
+///
+/// // in a resolver
+/// let users: Vec<User> = get_db_access(ctx)?
+///     .fetch_all("SELECT id, name FROM users", &[])
+///     .await?;
+/// ```
+pub fn get_db_access<'a>(ctx: &Context<'a>) -> FieldResult<&'a dyn DbAccess> {
+    ctx.data_opt::<Arc<dyn DbAccess>>()
+        .map(|db| db.as_ref())
+        .ok_or_else(|| {
+            crate::error::coded_error(
+                "Database access is not configured in the graphQL context".to_string(),
+                crate::error::POOL_NOT_CONFIGURED,
+            )
+        })
+}
@@ -0,0 +1,67 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use deadpool::managed::Object;
+use sqlx::PgConnection;
+
+use crate::stats::timed_get;
+use crate::{Pool, PoolManager};
+
+/// A pooled connection handed to HTTP handlers by the web framework
+/// integrations (the `axum`, `actix` and `warp` features), for plain routes
+/// such as webhooks served next to the GraphQL endpoint.
+pub struct DbConn(pub Object<PoolManager>);
+
+impl DbConn {
+    /// Returns the pooled connection object
+    pub fn into_inner(self) -> Object<PoolManager> {
+        self.0
+    }
+}
+
+impl Deref for DbConn {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        &self.0
+    }
+}
+
+impl DerefMut for DbConn {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        &mut self.0
+    }
+}
+
+/// Why a [`DbConn`] could not be handed to a handler. Unavailable responds
+/// with `503 Service Unavailable`, NotConfigured with `500 Internal Server
+/// Error`.
+#[derive(Debug)]
+pub enum DbConnRejection {
+    /// The application has no Pool registered
+    NotConfigured,
+    /// No connection could be acquired from the Pool
+    Unavailable(String),
+}
+
+impl fmt::Display for DbConnRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConfigured => f.write_str("Database pool is not configured"),
+            Self::Unavailable(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for DbConnRejection {}
+
+/// Gets a connection from the given pool, mapping failures the same way as
+/// [`crate::get_db_connection`] does
+pub(crate) async fn acquire_conn(pool: &Pool) -> Result<DbConn, DbConnRejection> {
+    timed_get(pool).await.map(DbConn).map_err(|e| {
+        DbConnRejection::Unavailable(format!(
+            "Database connectivity error: {:?}",
+            crate::error::redact(&e.to_string())
+        ))
+    })
+}
@@ -0,0 +1,335 @@
+//! Introspection of what the database is doing for this app, for admin
+//! GraphQL fields and ops tooling. As with `pool_status`, protect fields
+//! exposing these with a guard: they show SQL text.
+//!
+//! Example usage
+//! ```ignore
+//! #[Object]
+//! impl AdminQuery {
+//!     #[graphql(guard = "AdminGuard")]
+//!     async fn active_sessions(&self, ctx: &Context<'_>) -> FieldResult<Vec<ActiveSession>> {
+//!         let mut conn = get_db_connection(ctx).await?;
+//!         let sessions = diagnostics::active_sessions(&mut conn).await;
+//!         match_result(sessions, "Failed to read the active sessions".to_string())
+//!     }
+//! }
+//! ```
+
+#[cfg(feature = "async-graphql")]
+use async_graphql::SimpleObject;
+use serde_json::Value;
+use sqlx::{query_as, query_with, Connection, Error as SqlxError, PgConnection, Row};
+
+use crate::{to_arguments, SqlValue};
+
+/// A server process of pg_stat_activity serving this app
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "async-graphql", derive(SimpleObject))]
+pub struct ActiveSession {
+    /// The process id of the backend, for `pg_cancel_backend`
+    pub pid: i32,
+    /// The application name of the session, with the operation name when
+    /// [`crate::ApplicationNameTagging`] is used
+    pub application_name: String,
+    /// `active`, `idle`, `idle in transaction`, ...
+    pub state: Option<String>,
+    /// The running statement, or the last one when the session is idle
+    pub query: String,
+    /// The class of what the session waits for, e.g. `Lock` or `IO`
+    pub wait_event_type: Option<String>,
+    /// What the session waits for, e.g. `transactionid`
+    pub wait_event: Option<String>,
+    /// How long the current or last statement has run, in milliseconds
+    pub query_duration_ms: Option<f64>,
+    /// How long the open transaction has run, in milliseconds
+    pub transaction_duration_ms: Option<f64>,
+}
+
+type SessionRow = (
+    i32,
+    String,
+    Option<String>,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<f64>,
+    Option<f64>,
+);
+
+/// Lists the other sessions of this app, i.e. whose application name is the
+/// one of `conn` or that name followed by `:<operation name>`, the longest
+/// running statement first
+///
+/// Returns the sessions or the sqlx error of the query
+/// # Arguments
+/// * `conn` - a connection of the pool to inspect
+pub async fn active_sessions(conn: &mut PgConnection) -> Result<Vec<ActiveSession>, SqlxError> {
+    let rows: Vec<SessionRow> = query_as(
+        "WITH app AS (SELECT split_part(current_setting('application_name'), ':', 1) AS name) \
+         SELECT pid, application_name, state, coalesce(query, ''), wait_event_type, wait_event, \
+         (extract(epoch FROM clock_timestamp() - query_start) * 1000)::float8, \
+         (extract(epoch FROM clock_timestamp() - xact_start) * 1000)::float8 \
+         FROM pg_stat_activity, app \
+         WHERE pid <> pg_backend_pid() AND backend_type = 'client backend' \
+         AND (application_name = app.name \
+         OR left(application_name, length(app.name) + 1) = app.name || ':') \
+         ORDER BY query_start NULLS LAST",
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(
+                pid,
+                application_name,
+                state,
+                query,
+                wait_event_type,
+                wait_event,
+                query_duration_ms,
+                transaction_duration_ms,
+            )| ActiveSession {
+                pid,
+                application_name,
+                state,
+                query,
+                wait_event_type,
+                wait_event,
+                query_duration_ms,
+                transaction_duration_ms,
+            },
+        )
+        .collect())
+}
+
+/// How [`top_statements`] ranks the statements
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::Enum))]
+pub enum StatementOrder {
+    /// The statements the database spent the most time on in total
+    TotalTime,
+    /// The slowest statements on average
+    MeanTime,
+}
+
+/// A statement tracked by `pg_stat_statements`
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "async-graphql", derive(SimpleObject))]
+pub struct TopStatement {
+    /// The normalized statement text
+    pub query: String,
+    /// Number of executions
+    pub calls: i64,
+    /// Time spent in all executions, in milliseconds
+    pub total_time_ms: f64,
+    /// Average time of an execution, in milliseconds
+    pub mean_time_ms: f64,
+    /// Number of rows returned or affected by all executions
+    pub rows: i64,
+}
+
+/// Reads the top statements of the current database from
+/// `pg_stat_statements`. The extension is optional: without it, or when it
+/// is installed but not loaded through `shared_preload_libraries`, there is
+/// nothing to report and None is returned instead of an error.
+///
+/// Returns the statements, None when `pg_stat_statements` is unavailable, or
+/// the sqlx error of the query
+/// # Arguments
+/// * `conn` - the connection to run the queries on
+/// * `order` - how to rank the statements
+/// * `limit` - how many statements to return
+pub async fn top_statements(
+    conn: &mut PgConnection,
+    order: StatementOrder,
+    limit: i64,
+) -> Result<Option<Vec<TopStatement>>, SqlxError> {
+    let extension: Option<(String, String)> = query_as(
+        "SELECT extnamespace::regnamespace::text, extversion \
+         FROM pg_extension WHERE extname = 'pg_stat_statements'",
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let (schema, version) = match extension {
+        Some(extension) => extension,
+        None => return Ok(None),
+    };
+    // the time columns were renamed in version 1.8 (Postgres 13)
+    let mut parts = version
+        .split('.')
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let version = (parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+    let (total, mean) = if version >= (1, 8) {
+        ("total_exec_time", "mean_exec_time")
+    } else {
+        ("total_time", "mean_time")
+    };
+    let order_by = match order {
+        StatementOrder::TotalTime => total,
+        StatementOrder::MeanTime => mean,
+    };
+    // regnamespace::text already quotes the schema name when needed
+    let sql = format!(
+        "SELECT query, calls, {}, {}, rows FROM {}.pg_stat_statements \
+         WHERE dbid = (SELECT oid FROM pg_database WHERE datname = current_database()) \
+         ORDER BY {} DESC LIMIT $1",
+        total, mean, schema, order_by
+    );
+    let res = query_as::<_, (String, i64, f64, f64, i64)>(&sql)
+        .bind(limit)
+        .fetch_all(&mut *conn)
+        .await;
+    let rows = match res {
+        Ok(rows) => rows,
+        // object_not_in_prerequisite_state: the library isn't preloaded
+        Err(e) if crate::error::sqlstate(&e).as_deref() == Some("55000") => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(
+        rows.into_iter()
+            .map(
+                |(query, calls, total_time_ms, mean_time_ms, rows)| TopStatement {
+                    query,
+                    calls,
+                    total_time_ms,
+                    mean_time_ms,
+                    rows,
+                },
+            )
+            .collect(),
+    ))
+}
+
+/// A node of a [`QueryPlan`], with the fields most useful to spot a bad
+/// plan; every other field of the node is in `details`
+#[derive(Clone, Debug)]
+pub struct PlanNode {
+    /// e.g. `Seq Scan`, `Index Scan`, `Hash Join`
+    pub node_type: String,
+    /// The table scanned by the node
+    pub relation_name: Option<String>,
+    /// The index used by the node
+    pub index_name: Option<String>,
+    /// The estimated total cost of the node
+    pub total_cost: f64,
+    /// The number of rows the planner estimated per loop
+    pub estimated_rows: f64,
+    /// The number of rows actually produced per loop
+    pub actual_rows: f64,
+    /// How many times the node ran
+    pub loops: f64,
+    /// The time spent in the node and its children per loop, in milliseconds
+    pub actual_total_time_ms: f64,
+    /// The input nodes
+    pub children: Vec<PlanNode>,
+    /// The node as returned by Postgres
+    pub details: Value,
+}
+
+impl PlanNode {
+    fn from_json(node: &Value) -> Result<Self, SqlxError> {
+        let text = |key: &str| node.get(key).and_then(Value::as_str).map(str::to_string);
+        let number = |key: &str| node.get(key).and_then(Value::as_f64).unwrap_or_default();
+        let children = match node.get("Plans").and_then(Value::as_array) {
+            Some(plans) => plans
+                .iter()
+                .map(PlanNode::from_json)
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            node_type: text("Node Type").ok_or_else(|| plan_error("a plan node has no type"))?,
+            relation_name: text("Relation Name"),
+            index_name: text("Index Name"),
+            total_cost: number("Total Cost"),
+            estimated_rows: number("Plan Rows"),
+            actual_rows: number("Actual Rows"),
+            loops: number("Actual Loops"),
+            actual_total_time_ms: number("Actual Total Time"),
+            children,
+            details: node.clone(),
+        })
+    }
+}
+
+/// The plan of a statement as executed by [`explain`]
+#[derive(Clone, Debug)]
+pub struct QueryPlan {
+    /// The time spent planning, in milliseconds
+    pub planning_time_ms: f64,
+    /// The time spent executing, in milliseconds
+    pub execution_time_ms: f64,
+    /// The top node of the plan
+    pub root: PlanNode,
+    /// The output of `EXPLAIN` as returned by Postgres
+    pub raw: Value,
+}
+
+fn plan_error(message: &str) -> SqlxError {
+    SqlxError::Decode(format!("Unexpected EXPLAIN output: {}", message).into())
+}
+
+/// Runs `EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON)` on a statement, for
+/// inspecting the plans of resolver queries during development. ANALYZE
+/// executes the statement, so it runs in a transaction that is rolled back,
+/// and writing statements can be explained too. Only available in debug
+/// builds: release builds get a `Configuration` error, so a forgotten call
+/// can't run statements twice in production.
+///
+/// Returns the parsed plan or the sqlx error of the statement
+/// # Arguments
+/// * `conn` - the connection to run the statement on
+/// * `sql` - the statement, with `$1`, `$2`, ... placeholders
+/// * `binds` - the values of the placeholders
+///
+/// Example usage
+/// ```ignore
+/// let plan = diagnostics::explain(
+///     &mut conn,
+///     "SELECT * FROM orders WHERE customer_id = $1",
+///     &[customer_id.into()],
+/// )
+/// .await?;
+/// log::debug!("{} in {} ms", plan.root.node_type, plan.execution_time_ms);
+/// ```
+pub async fn explain(
+    conn: &mut PgConnection,
+    sql: &str,
+    binds: &[SqlValue],
+) -> Result<QueryPlan, SqlxError> {
+    if !cfg!(debug_assertions) {
+        return Err(SqlxError::Configuration(
+            "explain is only available in debug builds".into(),
+        ));
+    }
+    let mut tx = conn.begin().await?;
+    let row = query_with(
+        &format!("EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON) {}", sql),
+        to_arguments(binds),
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.rollback().await?;
+    // the column is of type json, whose wire format is plain text
+    let text: String = row.try_get_unchecked(0)?;
+    let raw: Value = serde_json::from_str(&text).map_err(|e| SqlxError::Decode(Box::new(e)))?;
+    let explained = raw
+        .get(0)
+        .ok_or_else(|| plan_error("no plan was returned"))?;
+    let root = explained
+        .get("Plan")
+        .ok_or_else(|| plan_error("the plan is missing"))?;
+    Ok(QueryPlan {
+        planning_time_ms: explained
+            .get("Planning Time")
+            .and_then(Value::as_f64)
+            .unwrap_or_default(),
+        execution_time_ms: explained
+            .get("Execution Time")
+            .and_then(Value::as_f64)
+            .unwrap_or_default(),
+        root: PlanNode::from_json(root)?,
+        raw,
+    })
+}
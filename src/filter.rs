@@ -0,0 +1,499 @@
+use sqlx::postgres::PgArguments;
+
+use crate::{to_arguments, SqlValue};
+
+/// A comparison operator of a [`Condition`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Like,
+    ILike,
+}
+
+impl CompareOp {
+    fn operator(self) -> &'static str {
+        match self {
+            CompareOp::Eq => "=",
+            CompareOp::Ne => "<>",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+            CompareOp::Like => "LIKE",
+            CompareOp::ILike => "ILIKE",
+        }
+    }
+}
+
+/// A predicate of a WHERE clause. Values are always bound as placeholders;
+/// column names are written into the SQL as given, so map the fields of a
+/// filter input to column names in code and never pass user input as a
+/// column name.
+///
+/// Write a column as `name::type`, e.g. `"created_at::timestamptz"`, to cast
+/// its placeholders, which is needed to compare timestamp, date or uuid
+/// columns with values sent as text.
+///
+/// Example usage
+/// ```ignore
+/// #[derive(InputObject)]
+/// struct OrderFilter {
+///     status: Option<String>,
+///     min_total: Option<i64>,
+///     customer_ids: Option<Vec<i64>>,
+/// }
+///
+/// let condition = Condition::all(vec![
+///     filter.status.map(|s| Condition::eq("status", s)),
+///     filter.min_total.map(|t| Condition::ge("total", t)),
+///     filter.customer_ids.map(|ids| Condition::is_in("customer_id", ids)),
+/// ].into_iter().flatten())
+/// .or(Condition::eq("priority", true));
+/// let not_archived = !Condition::eq("status", "archived");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    Compare {
+        column: String,
+        op: CompareOp,
+        value: SqlValue,
+    },
+    In {
+        column: String,
+        values: Vec<SqlValue>,
+    },
+    IsNull(String),
+    IsNotNull(String),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    /// `column <op> value`
+    pub fn compare(column: &str, op: CompareOp, value: impl Into<SqlValue>) -> Self {
+        Condition::Compare {
+            column: column.to_string(),
+            op,
+            value: value.into(),
+        }
+    }
+
+    /// `column = value`, or `column IS NULL` for a NULL value
+    pub fn eq(column: &str, value: impl Into<SqlValue>) -> Self {
+        Self::compare(column, CompareOp::Eq, value)
+    }
+
+    /// `column <> value`, or `column IS NOT NULL` for a NULL value
+    pub fn ne(column: &str, value: impl Into<SqlValue>) -> Self {
+        Self::compare(column, CompareOp::Ne, value)
+    }
+
+    pub fn lt(column: &str, value: impl Into<SqlValue>) -> Self {
+        Self::compare(column, CompareOp::Lt, value)
+    }
+
+    pub fn le(column: &str, value: impl Into<SqlValue>) -> Self {
+        Self::compare(column, CompareOp::Le, value)
+    }
+
+    pub fn gt(column: &str, value: impl Into<SqlValue>) -> Self {
+        Self::compare(column, CompareOp::Gt, value)
+    }
+
+    pub fn ge(column: &str, value: impl Into<SqlValue>) -> Self {
+        Self::compare(column, CompareOp::Ge, value)
+    }
+
+    pub fn like(column: &str, pattern: impl Into<SqlValue>) -> Self {
+        Self::compare(column, CompareOp::Like, pattern)
+    }
+
+    pub fn ilike(column: &str, pattern: impl Into<SqlValue>) -> Self {
+        Self::compare(column, CompareOp::ILike, pattern)
+    }
+
+    /// `column IN (...)`; always false for an empty list
+    pub fn is_in<V: Into<SqlValue>>(column: &str, values: impl IntoIterator<Item = V>) -> Self {
+        Condition::In {
+            column: column.to_string(),
+            values: values.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn is_null(column: &str) -> Self {
+        Condition::IsNull(column.to_string())
+    }
+
+    pub fn is_not_null(column: &str) -> Self {
+        Condition::IsNotNull(column.to_string())
+    }
+
+    /// All of `conditions`; always true when empty
+    pub fn all(conditions: impl IntoIterator<Item = Condition>) -> Self {
+        Condition::And(conditions.into_iter().collect())
+    }
+
+    /// Any of `conditions`; always false when empty
+    pub fn any(conditions: impl IntoIterator<Item = Condition>) -> Self {
+        Condition::Or(conditions.into_iter().collect())
+    }
+
+    pub fn and(self, other: Condition) -> Self {
+        match self {
+            Condition::And(mut conditions) => {
+                conditions.push(other);
+                Condition::And(conditions)
+            }
+            condition => Condition::And(vec![condition, other]),
+        }
+    }
+
+    pub fn or(self, other: Condition) -> Self {
+        match self {
+            Condition::Or(mut conditions) => {
+                conditions.push(other);
+                Condition::Or(conditions)
+            }
+            condition => Condition::Or(vec![condition, other]),
+        }
+    }
+
+    /// Writes the condition to `sql`, pushing its values to `binds` and
+    /// numbering the placeholders after the values already in `binds`
+    fn render(&self, sql: &mut String, binds: &mut Vec<SqlValue>, first_param: usize) {
+        let param = |value: &SqlValue, cast: Option<&str>, binds: &mut Vec<SqlValue>| {
+            binds.push(value.clone());
+            let number = first_param + binds.len() - 1;
+            match cast {
+                Some(cast) => format!("${}::{}", number, cast),
+                None => format!("${}", number),
+            }
+        };
+        match self {
+            Condition::Compare {
+                column,
+                op: CompareOp::Eq,
+                value: SqlValue::Null,
+            } => sql.push_str(&format!("{} IS NULL", split_cast(column).0)),
+            Condition::Compare {
+                column,
+                op: CompareOp::Ne,
+                value: SqlValue::Null,
+            } => sql.push_str(&format!("{} IS NOT NULL", split_cast(column).0)),
+            Condition::Compare { column, op, value } => {
+                let (name, cast) = split_cast(column);
+                let placeholder = param(value, cast, binds);
+                sql.push_str(&format!("{} {} {}", name, op.operator(), placeholder));
+            }
+            Condition::In { values, .. } if values.is_empty() => sql.push_str("FALSE"),
+            Condition::In { column, values } => {
+                let (name, cast) = split_cast(column);
+                let placeholders: Vec<String> = values
+                    .iter()
+                    .map(|value| param(value, cast, binds))
+                    .collect();
+                sql.push_str(&format!("{} IN ({})", name, placeholders.join(", ")));
+            }
+            Condition::IsNull(column) => sql.push_str(&format!("{} IS NULL", split_cast(column).0)),
+            Condition::IsNotNull(column) => {
+                sql.push_str(&format!("{} IS NOT NULL", split_cast(column).0))
+            }
+            Condition::And(conditions) => {
+                render_all(conditions, " AND ", "TRUE", sql, binds, first_param)
+            }
+            Condition::Or(conditions) => {
+                render_all(conditions, " OR ", "FALSE", sql, binds, first_param)
+            }
+            Condition::Not(condition) => {
+                sql.push_str("NOT (");
+                condition.render(sql, binds, first_param);
+                sql.push(')');
+            }
+        }
+    }
+}
+
+impl std::ops::Not for Condition {
+    type Output = Condition;
+
+    /// `NOT (condition)`
+    fn not(self) -> Condition {
+        Condition::Not(Box::new(self))
+    }
+}
+
+/// Splits `name::type` into the column name and the cast of its placeholders
+fn split_cast(column: &str) -> (&str, Option<&str>) {
+    match column.split_once("::") {
+        Some((name, cast)) => (name, Some(cast)),
+        None => (column, None),
+    }
+}
+
+fn render_all(
+    conditions: &[Condition],
+    separator: &str,
+    empty: &str,
+    sql: &mut String,
+    binds: &mut Vec<SqlValue>,
+    first_param: usize,
+) {
+    if conditions.is_empty() {
+        sql.push_str(empty);
+        return;
+    }
+    sql.push('(');
+    for (i, condition) in conditions.iter().enumerate() {
+        if i > 0 {
+            sql.push_str(separator);
+        }
+        condition.render(sql, binds, first_param);
+    }
+    sql.push(')');
+}
+
+/// Collects the conditions of a WHERE clause, all of which must hold, and
+/// renders them with numbered placeholders and their bind values
+///
+/// Example usage
+/// ```ignore
+/// let filter = Filter::new()
+///     .first_param(2)
+///     .and_opt(input.status.map(|s| Condition::eq("status", s)))
+///     .and_opt(input.search.map(|q| Condition::ilike("title", format!("%{}%", q))))
+///     .build();
+/// let sql = format!("SELECT * FROM posts WHERE author_id = $1 {}", filter.to_sql_and());
+/// let mut args = PgArguments::default();
+/// args.add(author_id);
+/// for value in &filter.binds {
+///     value.add_to(&mut args);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Filter {
+    conditions: Vec<Condition>,
+    first_param: usize,
+    deleted_at: Option<String>,
+    include_deleted: bool,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            conditions: Vec::new(),
+            first_param: 1,
+            deleted_at: None,
+            include_deleted: false,
+        }
+    }
+}
+
+/// The fragment produced by [`Filter::build`]
+#[derive(Clone, Debug)]
+pub struct FilterQuery {
+    /// The condition without `WHERE`, absent when the filter is empty
+    pub condition: Option<String>,
+    /// The values of the placeholders, in placeholder order
+    pub binds: Vec<SqlValue>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a condition that must hold
+    pub fn and(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    /// Adds a condition if there is one, for optional fields of filter inputs
+    pub fn and_opt(self, condition: Option<Condition>) -> Self {
+        match condition {
+            Some(condition) => self.and(condition),
+            None => self,
+        }
+    }
+
+    /// Sets the number of the first placeholder, for queries that already
+    /// use `$1..$n` for other binds
+    pub fn first_param(mut self, first_param: usize) -> Self {
+        self.first_param = first_param;
+        self
+    }
+
+    /// Excludes soft deleted rows, i.e. adds `column IS NULL` unless
+    /// [`Filter::include_deleted`] is set (see [`crate::soft_delete`])
+    ///
+    /// # Arguments
+    /// * `column` - the deletion timestamp, usually [`crate::DELETED_AT_COLUMN`]
+    ///   or an aliased `p.deleted_at`
+    pub fn soft_delete(mut self, column: &str) -> Self {
+        self.deleted_at = Some(column.to_string());
+        self
+    }
+
+    /// Whether soft deleted rows are kept, e.g. for an `includeDeleted`
+    /// argument of admin queries
+    pub fn include_deleted(mut self, include_deleted: bool) -> Self {
+        self.include_deleted = include_deleted;
+        self
+    }
+
+    pub fn build(mut self) -> FilterQuery {
+        if let Some(column) = self.deleted_at.take().filter(|_| !self.include_deleted) {
+            self.conditions.push(Condition::IsNull(column));
+        }
+        if self.conditions.is_empty() {
+            return FilterQuery {
+                condition: None,
+                binds: Vec::new(),
+            };
+        }
+        let mut sql = String::new();
+        let mut binds = Vec::new();
+        for (i, condition) in self.conditions.iter().enumerate() {
+            if i > 0 {
+                sql.push_str(" AND ");
+            }
+            condition.render(&mut sql, &mut binds, self.first_param);
+        }
+        FilterQuery {
+            condition: Some(sql),
+            binds,
+        }
+    }
+}
+
+impl FilterQuery {
+    /// `WHERE <condition>`, or nothing for an empty filter
+    pub fn to_sql(&self) -> String {
+        match &self.condition {
+            Some(condition) => format!("WHERE {}", condition),
+            None => String::new(),
+        }
+    }
+
+    /// `AND <condition>`, or nothing for an empty filter, for queries with a
+    /// WHERE
+    pub fn to_sql_and(&self) -> String {
+        match &self.condition {
+            Some(condition) => format!("AND ({})", condition),
+            None => String::new(),
+        }
+    }
+
+    /// The values as sqlx arguments, for queries without other binds
+    pub fn arguments(&self) -> PgArguments {
+        to_arguments(&self.binds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_placeholders_after_first_param() {
+        let filter = Filter::new()
+            .first_param(2)
+            .and(Condition::eq("status", "open"))
+            .and(Condition::is_in("customer_id", [1i64, 2, 3]))
+            .and(Condition::eq("closed_at", SqlValue::Null))
+            .build();
+        assert_eq!(
+            filter.to_sql_and(),
+            "AND (status = $2 AND customer_id IN ($3, $4, $5) AND closed_at IS NULL)"
+        );
+        assert_eq!(
+            filter.binds,
+            vec![
+                SqlValue::Text("open".to_string()),
+                SqlValue::Int(1),
+                SqlValue::Int(2),
+                SqlValue::Int(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_nested_conditions_in_order() {
+        let filter = Filter::new()
+            .and(Condition::any(vec![
+                Condition::ge("total", 100i64),
+                Condition::all(vec![
+                    Condition::eq("priority", true),
+                    !Condition::like("title", "%draft%"),
+                ]),
+            ]))
+            .and(Condition::ne("owner_id", SqlValue::Null))
+            .build();
+        assert_eq!(
+            filter.to_sql(),
+            "WHERE (total >= $1 OR (priority = $2 AND NOT (title LIKE $3))) \
+             AND owner_id IS NOT NULL"
+        );
+        assert_eq!(filter.binds.len(), 3);
+    }
+
+    #[test]
+    fn casts_placeholders_of_typed_columns() {
+        let filter = Filter::new()
+            .and(Condition::ge(
+                "created_at::timestamptz",
+                "2024-01-01T00:00:00Z",
+            ))
+            .and(Condition::is_in("owner_id::uuid", ["a", "b"]))
+            .and(Condition::eq("deleted_at::timestamptz", SqlValue::Null))
+            .build();
+        assert_eq!(
+            filter.to_sql(),
+            "WHERE created_at >= $1::timestamptz AND owner_id IN ($2::uuid, $3::uuid) \
+             AND deleted_at IS NULL"
+        );
+    }
+
+    #[test]
+    fn renders_empty_lists_as_constants() {
+        let filter = Filter::new()
+            .and(Condition::is_in("id", Vec::<i64>::new()))
+            .and(Condition::all(Vec::new()))
+            .and(Condition::any(Vec::new()))
+            .build();
+        assert_eq!(
+            filter.condition.as_deref(),
+            Some("FALSE AND TRUE AND FALSE")
+        );
+        assert!(filter.binds.is_empty());
+    }
+
+    #[test]
+    fn empty_filter_renders_nothing() {
+        let filter = Filter::new().build();
+        assert_eq!(filter.condition, None);
+        assert_eq!(filter.to_sql(), "");
+        assert_eq!(filter.to_sql_and(), "");
+    }
+
+    #[test]
+    fn excludes_soft_deleted_rows_unless_included() {
+        let filter = Filter::new()
+            .and(Condition::eq("author_id", 1i64))
+            .soft_delete("p.deleted_at");
+        assert_eq!(
+            filter.clone().build().to_sql(),
+            "WHERE author_id = $1 AND p.deleted_at IS NULL"
+        );
+        assert_eq!(
+            filter.include_deleted(true).build().to_sql(),
+            "WHERE author_id = $1"
+        );
+    }
+}
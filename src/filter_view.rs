@@ -0,0 +1,237 @@
+use async_graphql::InputObject;
+
+use crate::{Condition, Filter, FilterQuery};
+
+/// A GraphQL filter input that translates into a [`Condition`], usually
+/// declared with [`crate::filter_view`]
+pub trait FilterView {
+    /// The condition matching the rows the filter asks for
+    fn to_condition(&self) -> Condition;
+
+    /// The WHERE fragment and binds of the filter
+    ///
+    /// # Arguments
+    /// * `first_param` - the number of the first placeholder, 1 unless the
+    ///   query already uses `$1..$n` for other binds
+    fn to_filter(&self, first_param: usize) -> FilterQuery {
+        Filter::new()
+            .first_param(first_param)
+            .and(self.to_condition())
+            .build()
+    }
+}
+
+/// The operators allowed on one column of a filter view. Implement it for
+/// your own input objects to filter other column types.
+pub trait FieldFilter {
+    /// The condition on `column` for every operator that is set
+    fn to_condition(&self, column: &str) -> Condition;
+}
+
+/// Escapes the wildcards of a LIKE pattern
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn null_condition(column: &str, is_null: Option<bool>) -> Option<Condition> {
+    is_null.map(|is_null| {
+        if is_null {
+            Condition::is_null(column)
+        } else {
+            Condition::is_not_null(column)
+        }
+    })
+}
+
+/// Operators of text columns; `contains` and `startsWith` match case
+/// insensitively
+#[derive(InputObject, Clone, Debug, Default)]
+pub struct StringFilter {
+    pub eq: Option<String>,
+    pub ne: Option<String>,
+    #[graphql(name = "in")]
+    pub in_: Option<Vec<String>>,
+    pub contains: Option<String>,
+    pub starts_with: Option<String>,
+    pub is_null: Option<bool>,
+}
+
+impl FieldFilter for StringFilter {
+    fn to_condition(&self, column: &str) -> Condition {
+        let mut conditions = Vec::new();
+        if let Some(v) = &self.eq {
+            conditions.push(Condition::eq(column, v.as_str()));
+        }
+        if let Some(v) = &self.ne {
+            conditions.push(Condition::ne(column, v.as_str()));
+        }
+        if let Some(v) = &self.in_ {
+            conditions.push(Condition::is_in(column, v.iter().map(String::as_str)));
+        }
+        if let Some(v) = &self.contains {
+            conditions.push(Condition::ilike(column, format!("%{}%", escape_like(v))));
+        }
+        if let Some(v) = &self.starts_with {
+            conditions.push(Condition::ilike(column, format!("{}%", escape_like(v))));
+        }
+        conditions.extend(null_condition(column, self.is_null));
+        Condition::all(conditions)
+    }
+}
+
+/// Operators of integer columns
+#[derive(InputObject, Clone, Debug, Default)]
+pub struct IntFilter {
+    pub eq: Option<i64>,
+    pub ne: Option<i64>,
+    pub lt: Option<i64>,
+    pub le: Option<i64>,
+    pub gt: Option<i64>,
+    pub ge: Option<i64>,
+    #[graphql(name = "in")]
+    pub in_: Option<Vec<i64>>,
+    pub is_null: Option<bool>,
+}
+
+impl FieldFilter for IntFilter {
+    fn to_condition(&self, column: &str) -> Condition {
+        let comparisons = [
+            (self.eq, Condition::eq as fn(&str, i64) -> Condition),
+            (self.ne, Condition::ne),
+            (self.lt, Condition::lt),
+            (self.le, Condition::le),
+            (self.gt, Condition::gt),
+            (self.ge, Condition::ge),
+        ];
+        let mut conditions: Vec<Condition> = comparisons
+            .iter()
+            .filter_map(|(value, condition)| value.map(|v| condition(column, v)))
+            .collect();
+        if let Some(v) = &self.in_ {
+            conditions.push(Condition::is_in(column, v.iter().copied()));
+        }
+        conditions.extend(null_condition(column, self.is_null));
+        Condition::all(conditions)
+    }
+}
+
+/// Operators of floating point columns
+#[derive(InputObject, Clone, Debug, Default)]
+pub struct FloatFilter {
+    pub lt: Option<f64>,
+    pub le: Option<f64>,
+    pub gt: Option<f64>,
+    pub ge: Option<f64>,
+    pub is_null: Option<bool>,
+}
+
+impl FieldFilter for FloatFilter {
+    fn to_condition(&self, column: &str) -> Condition {
+        let comparisons = [
+            (self.lt, Condition::lt as fn(&str, f64) -> Condition),
+            (self.le, Condition::le),
+            (self.gt, Condition::gt),
+            (self.ge, Condition::ge),
+        ];
+        let mut conditions: Vec<Condition> = comparisons
+            .iter()
+            .filter_map(|(value, condition)| value.map(|v| condition(column, v)))
+            .collect();
+        conditions.extend(null_condition(column, self.is_null));
+        Condition::all(conditions)
+    }
+}
+
+/// Operators of boolean columns
+#[derive(InputObject, Clone, Debug, Default)]
+pub struct BoolFilter {
+    pub eq: Option<bool>,
+    pub is_null: Option<bool>,
+}
+
+impl FieldFilter for BoolFilter {
+    fn to_condition(&self, column: &str) -> Condition {
+        let mut conditions = Vec::new();
+        if let Some(v) = self.eq {
+            conditions.push(Condition::eq(column, v));
+        }
+        conditions.extend(null_condition(column, self.is_null));
+        Condition::all(conditions)
+    }
+}
+
+/// Declares a filterable view: a GraphQL input object with one optional
+/// [`FieldFilter`] per column plus `and`, `or` and `not` to combine filters,
+/// and its [`FilterView`] implementation mapping every field to the column
+/// expression given after `=>`. The column type of a field decides which
+/// operators clients can use on it. Needs `async_graphql` as a dependency of
+/// the calling crate.
+///
+/// Example usage
+/// ```ignore
+/// sqlx_helpers::filter_view! {
+///     /// Filters the posts of the `posts` query
+///     pub struct PostFilter {
+///         title: StringFilter => "p.title",
+///         views: IntFilter => "p.views",
+///         published: BoolFilter => "p.published",
+///     }
+/// }
+///
+/// async fn posts(&self, ctx: &Context<'_>, filter: Option<PostFilter>) -> FieldResult<Vec<Post>> {
+///     let filter = filter.unwrap_or_default().to_filter(1);
+///     let sql = format!("SELECT p.* FROM posts p {}", filter.to_sql());
+///     let mut conn = get_db_connection(ctx).await?;
+///     let rows = query_as_with::<_, Post, _>(&sql, filter.arguments())
+///         .fetch_all(&mut *conn)
+///         .await;
+///     match_result(rows, "Failed to get posts".to_string())
+/// }
+/// ```
+#[macro_export]
+macro_rules! filter_view {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $( $(#[$field_meta:meta])* $field:ident : $filter:ty => $column:expr ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(async_graphql::InputObject, Clone, Debug, Default)]
+        $vis struct $name {
+            $( $(#[$field_meta])* pub $field: Option<$filter>, )*
+            /// Every filter of the list must match
+            pub and: Option<Vec<$name>>,
+            /// At least one filter of the list must match
+            pub or: Option<Vec<$name>>,
+            /// The filter must not match
+            pub not: Option<Box<$name>>,
+        }
+
+        impl $crate::FilterView for $name {
+            fn to_condition(&self) -> $crate::Condition {
+                let mut conditions = Vec::new();
+                $(
+                    if let Some(filter) = &self.$field {
+                        conditions.push($crate::FieldFilter::to_condition(filter, $column));
+                    }
+                )*
+                if let Some(filters) = &self.and {
+                    conditions.extend(filters.iter().map($crate::FilterView::to_condition));
+                }
+                if let Some(filters) = &self.or {
+                    conditions.push($crate::Condition::any(
+                        filters.iter().map($crate::FilterView::to_condition),
+                    ));
+                }
+                if let Some(filter) = &self.not {
+                    conditions.push(!$crate::FilterView::to_condition(filter.as_ref()));
+                }
+                $crate::Condition::all(conditions)
+            }
+        }
+    };
+}
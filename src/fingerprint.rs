@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Default number of distinct fingerprints tracked by
+/// [`StatementMetrics`]; statements past it are counted under
+/// [`OTHER_FINGERPRINT`]
+pub const DEFAULT_MAX_FINGERPRINTS: usize = 500;
+
+/// The fingerprint statements are counted under once
+/// [`StatementMetrics`] tracks its maximum number of fingerprints
+pub const OTHER_FINGERPRINT: &str = "other";
+
+/// The upper bounds of the latency buckets of [`StatementStats`]
+pub const LATENCY_BUCKETS: [Duration; 11] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+/// Normalizes SQL into a fingerprint shared by every execution of the same
+/// statement: comments (e.g. the tags of [`crate::sql_comment`]) are removed,
+/// literals and bind placeholders become `?`, lists of them such as
+/// `IN (?, ?, ?)` or multi-row `VALUES` collapse into one, unquoted words
+/// are lowercased and whitespace is normalized.
+///
+/// Example usage
+/// ```ignore
+///  assert_eq!(
+///      fingerprint("SELECT * FROM users WHERE id IN ($1, $2) AND name = 'a' /* route='x' */"),
+///      "select * from users where id in (?) and name = ?"
+///  );
+/// ```
+pub fn fingerprint(sql: &str) -> String {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '-' && next == Some('-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '\'' {
+            i = skip_quoted(&chars, i, '\'');
+            push_token(&mut tokens, "?".to_string());
+        } else if c == '"' {
+            let end = skip_quoted(&chars, i, '"');
+            push_token(&mut tokens, chars[i..end.min(chars.len())].iter().collect());
+            i = end;
+        } else if c == '$' && next.is_some_and(|n| n.is_ascii_digit()) {
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            push_token(&mut tokens, "?".to_string());
+        } else if c == '$' {
+            match skip_dollar_quoted(&chars, i) {
+                Some(end) => {
+                    i = end;
+                    push_token(&mut tokens, "?".to_string());
+                }
+                None => {
+                    i += 1;
+                    push_token(&mut tokens, "$".to_string());
+                }
+            }
+        } else if c.is_ascii_digit() || (c == '.' && next.is_some_and(|n| n.is_ascii_digit())) {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            push_token(&mut tokens, "?".to_string());
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+            {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            push_token(&mut tokens, word.to_lowercase());
+        } else if is_operator(c) {
+            let start = i;
+            while i < chars.len() && is_operator(chars[i]) {
+                i += 1;
+            }
+            push_token(&mut tokens, chars[start..i].iter().collect());
+        } else {
+            i += 1;
+            push_token(&mut tokens, c.to_string());
+        }
+    }
+    let mut out = String::with_capacity(sql.len());
+    let mut previous: Option<&str> = None;
+    for token in &tokens {
+        let glued =
+            matches!(token.as_str(), "," | ")" | "." | ";") || matches!(previous, Some("(" | "."));
+        if previous.is_some() && !glued {
+            out.push(' ');
+        }
+        out.push_str(token);
+        previous = Some(token);
+    }
+    out
+}
+
+fn is_operator(c: char) -> bool {
+    "+-*/<>=~!@#%^&|:".contains(c)
+}
+
+/// The index after the quoted text starting at `start`, where a doubled
+/// quote is an escaped one
+fn skip_quoted(chars: &[char], start: usize, quote: char) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    i
+}
+
+/// The index after the `$tag$ ... $tag$` string starting at `start`, or None
+/// when there is none
+fn skip_dollar_quoted(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+        i += 1;
+    }
+    if chars.get(i) != Some(&'$') {
+        return None;
+    }
+    let tag = &chars[start..=i];
+    (i + 1..chars.len())
+        .find(|j| chars[*j..].starts_with(tag))
+        .map(|j| j + tag.len())
+}
+
+/// Pushes a token, collapsing `?, ?` into `?` and `(?), (?)` into `(?)`
+fn push_token(tokens: &mut Vec<String>, token: String) {
+    tokens.push(token);
+    loop {
+        let tail: Vec<&str> = tokens.iter().rev().take(7).map(String::as_str).collect();
+        if tail.starts_with(&["?", ",", "?"]) {
+            tokens.truncate(tokens.len() - 2);
+        } else if tail == [")", "?", "(", ",", ")", "?", "("] {
+            tokens.truncate(tokens.len() - 4);
+        } else {
+            break;
+        }
+    }
+}
+
+/// The aggregated executions of one fingerprint
+#[derive(Clone, Debug)]
+pub struct StatementStats {
+    /// The normalized statement, see [`fingerprint`]
+    pub fingerprint: String,
+    /// Number of executions
+    pub calls: u64,
+    /// Number of failed executions
+    pub errors: u64,
+    /// Time spent in all executions
+    pub total: Duration,
+    /// The slowest execution
+    pub max: Duration,
+    /// Number of executions per latency bucket: the count at index `i` took
+    /// at most `LATENCY_BUCKETS[i]`, the last count is for slower ones
+    pub buckets: [u64; LATENCY_BUCKETS.len() + 1],
+}
+
+impl StatementStats {
+    fn new(fingerprint: String) -> Self {
+        Self {
+            fingerprint,
+            calls: 0,
+            errors: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+            buckets: [0; LATENCY_BUCKETS.len() + 1],
+        }
+    }
+
+    /// The average duration of an execution
+    pub fn mean(&self) -> Duration {
+        match u32::try_from(self.calls) {
+            Ok(0) => Duration::ZERO,
+            Ok(calls) => self.total / calls,
+            Err(_) => self.total.div_f64(self.calls as f64),
+        }
+    }
+
+    /// The share of failed executions, from 0.0 to 1.0
+    pub fn error_rate(&self) -> f64 {
+        if self.calls == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.calls as f64
+        }
+    }
+}
+
+/// Counts executions, errors and latencies per statement fingerprint, like
+/// a lightweight `pg_stat_statements` kept by the app. Clones share the same
+/// statistics, so keep one instance, e.g. in the graphQL context, and read
+/// it with [`StatementMetrics::snapshot`] from an admin field. With the
+/// `prometheus` feature every execution is also recorded in the
+/// `statement_duration_seconds` histogram and `statement_errors_total`
+/// counter, labelled by fingerprint.
+///
+/// Example usage
+/// ```ignore
+///  let statements = StatementMetrics::new();
+///  let sql = "SELECT * FROM my_data WHERE owner_id = $1";
+///  let rows = statements
+///     .run(sql, query_as::<_, MyData>(sql).bind(owner_id).fetch_all(&mut *conn))
+///     .await?;
+///
+///  for stats in statements.snapshot().iter().take(10) {
+///      log::info!("{} calls, {:?} mean: {}", stats.calls, stats.mean(), stats.fingerprint);
+///  }
+/// ```
+#[derive(Clone)]
+pub struct StatementMetrics {
+    stats: Arc<Mutex<HashMap<String, StatementStats>>>,
+    max_fingerprints: usize,
+}
+
+impl Default for StatementMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatementMetrics {
+    pub fn new() -> Self {
+        Self {
+            stats: Arc::new(Mutex::new(HashMap::new())),
+            max_fingerprints: DEFAULT_MAX_FINGERPRINTS,
+        }
+    }
+
+    /// Sets how many distinct fingerprints are tracked, which bounds the
+    /// memory use and the number of prometheus series
+    pub fn max_fingerprints(mut self, max_fingerprints: usize) -> Self {
+        self.max_fingerprints = max_fingerprints;
+        self
+    }
+
+    /// Awaits the query future and records its duration and outcome under
+    /// the fingerprint of `sql`
+    ///
+    /// Returns the output of the query future
+    /// # Arguments
+    /// * `sql` - the SQL text of the query
+    /// * `fut` - the query future, e.g. `query(sql).fetch_all(conn)`
+    pub async fn run<T, E, F>(&self, sql: &str, fut: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let started = Instant::now();
+        let output = fut.await;
+        self.record(sql, started.elapsed(), output.is_ok());
+        output
+    }
+
+    /// Records an execution of `sql` timed by the caller
+    pub fn record(&self, sql: &str, elapsed: Duration, succeeded: bool) {
+        #[cfg(feature = "async-graphql")]
+        crate::n_plus_one::record_sql(sql);
+        let fingerprint = fingerprint(sql);
+        let mut stats = self.stats.lock().unwrap();
+        let key = if stats.contains_key(&fingerprint) || stats.len() < self.max_fingerprints {
+            fingerprint
+        } else {
+            OTHER_FINGERPRINT.to_string()
+        };
+        #[cfg(feature = "prometheus")]
+        crate::metrics::observe_statement(&key, elapsed, succeeded);
+        let entry = stats
+            .entry(key)
+            .or_insert_with_key(|key| StatementStats::new(key.clone()));
+        entry.calls += 1;
+        if !succeeded {
+            entry.errors += 1;
+        }
+        entry.total += elapsed;
+        entry.max = entry.max.max(elapsed);
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| elapsed <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        entry.buckets[bucket] += 1;
+    }
+
+    /// The statistics of every fingerprint, the most time consuming first
+    pub fn snapshot(&self) -> Vec<StatementStats> {
+        let mut snapshot: Vec<StatementStats> =
+            self.stats.lock().unwrap().values().cloned().collect();
+        snapshot.sort_by(|a, b| b.total.cmp(&a.total));
+        snapshot
+    }
+
+    /// Forgets all statistics, e.g. after a deploy
+    pub fn reset(&self) {
+        self.stats.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_comments() {
+        assert_eq!(
+            fingerprint("SELECT id -- the key\nFROM users /* route='x' */ WHERE id = 42"),
+            "select id from users where id = ?"
+        );
+    }
+
+    #[test]
+    fn replaces_quoted_strings() {
+        assert_eq!(
+            fingerprint("SELECT * FROM t WHERE a = 'it''s' AND b = 'x'"),
+            "select * from t where a = ? and b = ?"
+        );
+    }
+
+    #[test]
+    fn keeps_quoted_identifiers() {
+        assert_eq!(
+            fingerprint("SELECT \"UserId\" FROM \"Users\" WHERE Name = 'Ada'"),
+            "select \"UserId\" from \"Users\" where name = ?"
+        );
+    }
+
+    #[test]
+    fn replaces_dollar_quoted_strings() {
+        assert_eq!(
+            fingerprint("SELECT $$a 'quoted' -- not a comment$$ FROM t WHERE b = $fn$x$fn$"),
+            "select ? from t where b = ?"
+        );
+    }
+
+    #[test]
+    fn collapses_in_lists() {
+        let expected = "select * from t where id in (?)";
+        assert_eq!(
+            fingerprint("SELECT * FROM t WHERE id IN (1, 2, 3, 4)"),
+            expected
+        );
+        assert_eq!(
+            fingerprint("SELECT * FROM t WHERE id IN ($1, $2)"),
+            expected
+        );
+        assert_eq!(fingerprint("SELECT * FROM t WHERE id IN ($1)"), expected);
+    }
+
+    #[test]
+    fn collapses_multi_row_values() {
+        let expected = "insert into t (a, b) values (?)";
+        assert_eq!(
+            fingerprint("INSERT INTO t (a, b) VALUES ($1, $2), ($3, $4), ($5, $6)"),
+            expected
+        );
+        assert_eq!(
+            fingerprint("INSERT INTO t (a, b) VALUES (1, 'x')"),
+            expected
+        );
+    }
+
+    #[test]
+    fn normalizes_whitespace_and_case() {
+        assert_eq!(
+            fingerprint("  select   *\n\tFROM users u WHERE u.name = $1 ;"),
+            "select * from users u where u.name = ?;"
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use crate::error::redact;
+use crate::stats::timed_get;
+use crate::{Pool, PoolExt, PoolStats};
+
+/// How long [`check_health`] waits for a connection and the answer to
+/// `SELECT 1` before reporting the pool unhealthy
+pub const DEFAULT_HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The result of a [`check_health`] probe
+#[derive(Clone, Debug)]
+pub struct HealthReport {
+    /// Whether a connection could be acquired and answered `SELECT 1`
+    pub healthy: bool,
+    /// Time spent acquiring the connection and running `SELECT 1`
+    pub latency: Duration,
+    /// Share of the pool's max size that is checked out, from 0.0 to 1.0
+    pub saturation: f64,
+    /// The pool state at the time of the probe
+    pub stats: PoolStats,
+    /// The failure of an unhealthy probe, with credentials redacted
+    pub error: Option<String>,
+}
+
+/// Acquires a connection, runs `SELECT 1` and reports the latency and pool
+/// saturation, suitable for Kubernetes readiness and liveness probes. A probe
+/// taking longer than [`DEFAULT_HEALTH_TIMEOUT`], e.g. on an exhausted pool,
+/// is reported unhealthy.
+///
+/// Returns a HealthReport; failures are reported in it rather than as an Err
+/// # Arguments
+/// * `pool` - the pool to probe
+///
+/// Example usage
+/// ```ignore
+///  // in the handler of your /ready endpoint
+///  let report = check_health(&db_pool).await;
+///  if report.healthy && report.saturation < 0.9 { 200 } else { 503 }
+/// ```
+pub async fn check_health(pool: &Pool) -> HealthReport {
+    check_health_with_timeout(pool, DEFAULT_HEALTH_TIMEOUT).await
+}
+
+/// Like [`check_health`], reporting the pool unhealthy when the probe takes
+/// longer than `timeout`; keep it below the timeout of the probe itself
+///
+/// # Arguments
+/// * `pool` - the pool to probe
+/// * `timeout` - how long to wait for a connection and `SELECT 1`
+pub async fn check_health_with_timeout(pool: &Pool, timeout: Duration) -> HealthReport {
+    let started = Instant::now();
+    let probe = async {
+        let mut conn = timed_get(pool).await.map_err(|e| redact(&e.to_string()))?;
+        sqlx::query("SELECT 1")
+            .execute(&mut *conn)
+            .await
+            .map(|_| ())
+            .map_err(|e| redact(&e.to_string()))
+    };
+    let res = match tokio::time::timeout(timeout, probe).await {
+        Ok(res) => res,
+        Err(_) => Err(format!(
+            "Health probe timed out after {} ms",
+            timeout.as_millis()
+        )),
+    };
+    let latency = started.elapsed();
+    let stats = pool.stats();
+    let saturation = if stats.max_size == 0 {
+        1.0
+    } else {
+        stats.in_use as f64 / stats.max_size as f64
+    };
+    HealthReport {
+        healthy: res.is_ok(),
+        latency,
+        saturation,
+        stats,
+        error: res.err(),
+    }
+}
@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use sqlx::{Error as SqlxError, PgConnection};
+
+/// An async callback run on a connection at a point of its lifecycle. An
+/// error discards the connection.
+pub type ConnectionHook =
+    dyn for<'c> Fn(&'c mut PgConnection) -> BoxFuture<'c, Result<(), SqlxError>> + Send + Sync;
+
+/// The lifecycle hooks registered on a [`crate::PoolManager`], run in
+/// registration order
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub(crate) after_create: Vec<Arc<ConnectionHook>>,
+    pub(crate) before_acquire: Vec<Arc<ConnectionHook>>,
+    pub(crate) after_release: Vec<Arc<ConnectionHook>>,
+}
+
+/// Runs `hooks` on `conn`, stopping at the first error
+pub(crate) async fn run_hooks(
+    hooks: &[Arc<ConnectionHook>],
+    conn: &mut PgConnection,
+) -> Result<(), SqlxError> {
+    for hook in hooks {
+        hook(conn).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,578 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error::Error as StdError;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+
+use deadpool::managed::Object;
+use futures::future::{join_all, BoxFuture};
+use futures::FutureExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::{query, query_as, query_scalar, Error as SqlxError, PgConnection};
+use tokio::task::JoinHandle;
+
+use crate::stats::timed_get;
+use crate::{Pool, PoolManager, RetryPolicy};
+
+/// The table holding the jobs
+pub const JOBS_TABLE: &str = "jobs";
+
+/// The DDL of the jobs table, to add to the migrations of the app
+pub const JOBS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS jobs (
+    id bigserial PRIMARY KEY,
+    queue text NOT NULL DEFAULT 'default',
+    kind text NOT NULL,
+    payload jsonb NOT NULL,
+    status text NOT NULL DEFAULT 'pending',
+    attempts integer NOT NULL DEFAULT 0,
+    max_attempts integer NOT NULL DEFAULT 5,
+    last_error text,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    run_at timestamptz NOT NULL DEFAULT now(),
+    locked_at timestamptz,
+    finished_at timestamptz
+);
+CREATE INDEX IF NOT EXISTS jobs_pending_idx ON jobs (queue, run_at) WHERE status IN ('pending', 'running');";
+
+/// The queue of jobs enqueued without [`NewJob::queue`]
+pub const DEFAULT_QUEUE: &str = "default";
+
+/// The attempts of jobs enqueued without [`NewJob::max_attempts`]
+pub const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+
+/// The user-facing error of a job handler
+pub type JobError = Box<dyn StdError + Send + Sync>;
+
+/// A job to insert with [`enqueue`]
+#[derive(Clone, Debug)]
+pub struct NewJob {
+    pub queue: String,
+    pub kind: String,
+    pub payload: String,
+    /// The number of runs before the job is dead-lettered
+    pub max_attempts: i32,
+    /// How long after the insert the job may run first
+    pub delay: Duration,
+}
+
+impl NewJob {
+    /// Creates a job of the default queue
+    ///
+    /// Returns the job, or the error of serializing `payload` to JSON
+    /// # Arguments
+    /// * `kind` - selects the handler of the job
+    /// * `payload` - the input of the handler
+    pub fn new<P: Serialize>(kind: &str, payload: &P) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            queue: DEFAULT_QUEUE.to_string(),
+            kind: kind.to_string(),
+            payload: serde_json::to_string(payload)?,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            delay: Duration::ZERO,
+        })
+    }
+
+    /// Puts the job on another queue, served by its own workers
+    pub fn queue(mut self, queue: &str) -> Self {
+        self.queue = queue.to_string();
+        self
+    }
+
+    /// Sets the number of runs, including the first one, before a failing
+    /// job is moved to the dead-letter state
+    pub fn max_attempts(mut self, max_attempts: i32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Schedules the first run of the job `delay` after the insert
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+/// A job claimed by a [`Worker`]
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub id: i64,
+    pub queue: String,
+    pub kind: String,
+    /// The JSON payload, as text
+    pub payload: String,
+    /// The number of times the job was claimed, including this one
+    pub attempts: i32,
+    pub max_attempts: i32,
+}
+
+impl Job {
+    /// Deserializes the payload into `T`
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_str(&self.payload)
+    }
+}
+
+/// Inserts a job, usually inside the transaction of the mutation deferring
+/// the work, so the job only exists when the mutation commits
+///
+/// Returns the id of the job or the sqlx error of the insert
+/// # Arguments
+/// * `conn` - the connection, typically of a transaction
+/// * `job` - the job to insert
+///
+/// Example usage
+/// ```ignore
+/// let mut tx = ctx.db_tx().await?;
+/// // ... the mutation itself
+/// let job = NewJob::new("send_welcome_email", &json!({ "user_id": user_id }))?;
+/// let res = enqueue(&mut tx, &job).await;
+/// match_result(res, "Failed to enqueue job".to_string())?;
+/// tx.commit().await?;
+/// ```
+pub async fn enqueue(conn: &mut PgConnection, job: &NewJob) -> Result<i64, SqlxError> {
+    let sql = format!(
+        "INSERT INTO {} (queue, kind, payload, max_attempts, run_at) \
+         VALUES ($1, $2, $3::jsonb, $4, now() + $5 * interval '1 millisecond') RETURNING id",
+        JOBS_TABLE
+    );
+    query_scalar::<_, i64>(&sql)
+        .bind(&job.queue)
+        .bind(&job.kind)
+        .bind(&job.payload)
+        .bind(job.max_attempts)
+        .bind(job.delay.as_millis() as f64)
+        .fetch_one(conn)
+        .await
+}
+
+type Handler =
+    dyn for<'c> Fn(&'c mut PgConnection, Job) -> BoxFuture<'c, Result<(), JobError>> + Send + Sync;
+
+/// Polls a queue of the jobs table and runs the handler registered for the
+/// kind of every job, each on its own connection from the pool. Jobs are
+/// claimed with `FOR UPDATE SKIP LOCKED`, so any number of workers, on any
+/// number of instances, can serve the same queue. While the jobs of a poll
+/// run, the connection that claimed them stays checked out to refresh their
+/// locks, so a worker needs up to `batch_size + 1` connections. A job whose
+/// worker died is claimed again after the lock timeout, and the outcome of a
+/// run whose job was claimed again in the meantime is discarded.
+///
+/// Delivery is at least once: a job is run again when its worker dies, or
+/// loses the database, after the handler did its work but before the job was
+/// marked done. Make handlers idempotent, e.g. with a unique key on what they
+/// write.
+///
+/// A handler that panics fails its job like an error does. A failed job is
+/// scheduled again with an exponential backoff until it has run
+/// `max_attempts` times, and is then moved to the `dead` state, where it
+/// stays until it is requeued with [`requeue`].
+///
+/// Example usage
+/// ```ignore
+///  let worker = Worker::new(db_pool.clone())
+///      .handler("send_welcome_email", |conn, job| Box::pin(async move {
+///          let input: WelcomeEmail = job.payload()?;
+///          send_welcome_email(conn, input).await?;
+///          Ok(())
+///      }))
+///      .start();
+/// ```
+pub struct Worker {
+    pool: Pool,
+    queue: String,
+    handlers: HashMap<String, Arc<Handler>>,
+    poll_interval: Duration,
+    batch_size: i64,
+    lock_timeout: Duration,
+    backoff: RetryPolicy,
+}
+
+impl Worker {
+    /// Creates a worker of the default queue, polling every second for up to
+    /// 10 jobs at a time
+    pub fn new(pool: Pool) -> Self {
+        Self {
+            pool,
+            queue: DEFAULT_QUEUE.to_string(),
+            handlers: HashMap::new(),
+            poll_interval: Duration::from_secs(1),
+            batch_size: 10,
+            lock_timeout: Duration::from_secs(300),
+            backoff: RetryPolicy {
+                max_attempts: DEFAULT_MAX_ATTEMPTS as u32,
+                base_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(3600),
+            },
+        }
+    }
+
+    /// Sets the queue to serve
+    pub fn queue(mut self, queue: &str) -> Self {
+        self.queue = queue.to_string();
+        self
+    }
+
+    /// Registers the handler of the jobs of `kind`
+    pub fn handler<F>(mut self, kind: &str, handler: F) -> Self
+    where
+        F: for<'c> Fn(&'c mut PgConnection, Job) -> BoxFuture<'c, Result<(), JobError>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.handlers.insert(kind.to_string(), Arc::new(handler));
+        self
+    }
+
+    /// Sets the time between two polls when the queue is empty
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Sets the number of jobs claimed and run concurrently per poll; keep it
+    /// below the pool size, as every job needs a connection of its own next
+    /// to the one refreshing the locks
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size as i64;
+        self
+    }
+
+    /// Sets after how long a running job whose lock wasn't refreshed is
+    /// considered abandoned by its worker and claimed again; the lock is
+    /// refreshed every third of it
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    /// Sets the delays between the runs of a failing job; the attempts are
+    /// set per job with [`NewJob::max_attempts`], so `max_attempts` of the
+    /// policy is ignored
+    pub fn backoff(mut self, backoff: RetryPolicy) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Starts polling on a background task
+    pub fn start(self) -> WorkerHandle {
+        WorkerHandle {
+            task: tokio::spawn(self.run()),
+        }
+    }
+
+    async fn run(self) {
+        loop {
+            if self.pool.is_closed() || self.pool.manager().is_draining() {
+                return;
+            }
+            let claimed = match self.poll().await {
+                Ok(claimed) => claimed,
+                Err(e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        queue = %self.queue,
+                        error = %crate::error::redact(&e.to_string()),
+                        "failed to claim jobs"
+                    );
+                    #[cfg(not(feature = "tracing"))]
+                    log::warn!(
+                        "failed to claim jobs of queue {}: {}",
+                        self.queue,
+                        crate::error::redact(&e.to_string())
+                    );
+                    0
+                }
+            };
+            // keep draining a busy queue, wait when it is empty
+            if claimed < self.batch_size as usize {
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        }
+    }
+
+    /// Claims a batch of jobs and runs them, returning the number of jobs
+    async fn poll(&self) -> Result<usize, SqlxError> {
+        let mut conn = timed_get(&self.pool)
+            .await
+            .map_err(crate::error::pool_error_to_sqlx)?;
+        let lock_timeout = self.lock_timeout.as_millis() as f64;
+        // abandoned jobs without attempts left would otherwise stay running
+        let sql = format!(
+            "UPDATE {} SET status = 'dead', last_error = 'abandoned by its worker', \
+             finished_at = now(), locked_at = NULL WHERE queue = $1 AND status = 'running' \
+             AND locked_at < now() - $2 * interval '1 millisecond' AND attempts >= max_attempts",
+            JOBS_TABLE
+        );
+        query(&sql)
+            .bind(&self.queue)
+            .bind(lock_timeout)
+            .execute(&mut *conn)
+            .await?;
+        let sql = format!(
+            "UPDATE {table} SET status = 'running', attempts = attempts + 1, locked_at = now() \
+             WHERE id IN (SELECT id FROM {table} WHERE queue = $1 AND run_at <= now() \
+             AND (status = 'pending' OR (status = 'running' AND locked_at < now() - $2 * interval '1 millisecond')) \
+             ORDER BY run_at, id LIMIT $3 FOR UPDATE SKIP LOCKED) \
+             RETURNING id, queue, kind, payload::text, attempts, max_attempts",
+            table = JOBS_TABLE
+        );
+        let rows = query_as::<_, (i64, String, String, String, i32, i32)>(&sql)
+            .bind(&self.queue)
+            .bind(lock_timeout)
+            .bind(self.batch_size)
+            .fetch_all(&mut *conn)
+            .await?;
+        let jobs: Vec<Job> = rows
+            .into_iter()
+            .map(|(id, queue, kind, payload, attempts, max_attempts)| Job {
+                id,
+                queue,
+                kind,
+                payload,
+                attempts,
+                max_attempts,
+            })
+            .collect();
+        let claimed = jobs.len();
+        if claimed == 0 {
+            return Ok(0);
+        }
+        let ids: Vec<i64> = jobs.iter().map(|job| job.id).collect();
+        let attempts: Vec<i32> = jobs.iter().map(|job| job.attempts).collect();
+        let run = join_all(jobs.into_iter().map(|job| self.run_job(job)));
+        tokio::select! {
+            _ = run => {}
+            never = self.heartbeat(Some(conn), &ids, &attempts) => match never {},
+        }
+        Ok(claimed)
+    }
+
+    async fn run_job(&self, job: Job) {
+        let id = job.id;
+        let attempts = job.attempts;
+        let dead = job.attempts >= job.max_attempts;
+        let mut conn = match timed_get(&self.pool).await {
+            Ok(conn) => conn,
+            // the job is claimed again after the lock timeout
+            Err(_) => return,
+        };
+        let res = match self.handlers.get(&job.kind) {
+            Some(handler) => {
+                let outcome = AssertUnwindSafe(handler(&mut *conn, job))
+                    .catch_unwind()
+                    .await;
+                match outcome {
+                    Ok(res) => res,
+                    Err(panic) => {
+                        // the handler may have left the connection inside a
+                        // transaction
+                        drop(Object::take(conn));
+                        conn = match timed_get(&self.pool).await {
+                            Ok(conn) => conn,
+                            Err(_) => return,
+                        };
+                        Err(format!("job handler panicked: {}", panic_message(&*panic)).into())
+                    }
+                }
+            }
+            None => Err(format!("no handler for job kind {}", job.kind).into()),
+        };
+        let res = match &res {
+            Ok(()) => {
+                let sql = format!(
+                    "UPDATE {} SET status = 'done', finished_at = now(), locked_at = NULL \
+                     WHERE id = $1 AND attempts = $2 AND status = 'running'",
+                    JOBS_TABLE
+                );
+                query(&sql)
+                    .bind(id)
+                    .bind(attempts)
+                    .execute(&mut *conn)
+                    .await
+            }
+            Err(e) => {
+                let error = crate::error::redact(&e.to_string());
+                #[cfg(feature = "tracing")]
+                tracing::warn!(job = id, error = %error, "job failed");
+                #[cfg(not(feature = "tracing"))]
+                log::warn!("job {} failed: {}", id, error);
+                self.fail(&mut conn, id, attempts, dead, &error).await
+            }
+        };
+        match res {
+            Ok(done) if done.rows_affected() == 0 => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(job = id, "job was claimed again, discarding its outcome");
+                #[cfg(not(feature = "tracing"))]
+                log::warn!("job {} was claimed again, discarding its outcome", id);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    job = id,
+                    error = %crate::error::redact(&e.to_string()),
+                    "failed to record the outcome of a job"
+                );
+                #[cfg(not(feature = "tracing"))]
+                log::warn!(
+                    "failed to record the outcome of job {}: {}",
+                    id,
+                    crate::error::redact(&e.to_string())
+                );
+            }
+        }
+    }
+
+    /// Refreshes the locks of the jobs of a poll every third of the lock
+    /// timeout, until the future is dropped once they have all run. It uses
+    /// the connection that claimed them, so it doesn't compete with the
+    /// handlers for the pool. The attempt numbers guard against refreshing
+    /// runs claimed again in the meantime, and finished runs are not
+    /// `running` anymore.
+    async fn heartbeat(
+        &self,
+        mut conn: Option<Object<PoolManager>>,
+        ids: &[i64],
+        attempts: &[i32],
+    ) -> Infallible {
+        let interval = (self.lock_timeout / 3).max(Duration::from_millis(100));
+        let sql = format!(
+            "UPDATE {table} SET locked_at = now() FROM UNNEST($1::int8[], $2::int4[]) \
+             AS claimed (id, attempts) WHERE {table}.id = claimed.id \
+             AND {table}.attempts = claimed.attempts AND {table}.status = 'running'",
+            table = JOBS_TABLE
+        );
+        loop {
+            tokio::time::sleep(interval).await;
+            if conn.is_none() {
+                // a missed refresh is retried at the next one
+                conn = timed_get(&self.pool).await.ok();
+            }
+            let Some(held) = conn.as_mut() else {
+                continue;
+            };
+            let res = query(&sql)
+                .bind(ids)
+                .bind(attempts)
+                .execute(&mut **held)
+                .await;
+            if res.is_err() {
+                // the connection may be broken, use another one next time
+                if let Some(broken) = conn.take() {
+                    drop(Object::take(broken));
+                }
+            }
+        }
+    }
+
+    /// Records the failure of a job, scheduling its next run or moving it to
+    /// the dead-letter state when it has no attempts left
+    async fn fail(
+        &self,
+        conn: &mut PgConnection,
+        id: i64,
+        attempts: i32,
+        dead: bool,
+        error: &str,
+    ) -> Result<sqlx::postgres::PgQueryResult, SqlxError> {
+        if dead {
+            let sql = format!(
+                "UPDATE {} SET status = 'dead', last_error = $2, finished_at = now(), \
+                 locked_at = NULL WHERE id = $1 AND attempts = $3 AND status = 'running'",
+                JOBS_TABLE
+            );
+            return query(&sql)
+                .bind(id)
+                .bind(error)
+                .bind(attempts)
+                .execute(conn)
+                .await;
+        }
+        let delay = self.backoff.delay_for(attempts.max(1) as u32);
+        let sql = format!(
+            "UPDATE {} SET status = 'pending', last_error = $2, locked_at = NULL, \
+             run_at = now() + $3 * interval '1 millisecond' \
+             WHERE id = $1 AND attempts = $4 AND status = 'running'",
+            JOBS_TABLE
+        );
+        query(&sql)
+            .bind(id)
+            .bind(error)
+            .bind(delay.as_millis() as f64)
+            .bind(attempts)
+            .execute(conn)
+            .await
+    }
+}
+
+/// The message of a panic payload, which is a `&str` or a `String` unless
+/// the panic was raised with `std::panic::panic_any`
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
+/// The background task of a started [`Worker`]. The task stops when the
+/// value is dropped or the pool is closed; jobs it was running are claimed
+/// again after the lock timeout.
+pub struct WorkerHandle {
+    task: JoinHandle<()>,
+}
+
+impl WorkerHandle {
+    /// Stops the worker
+    pub fn shutdown(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for WorkerHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Moves a dead-lettered job back to the queue with fresh attempts, e.g.
+/// after the bug that made it fail was fixed
+///
+/// Returns whether the job was dead and got requeued, or the sqlx error of
+/// the update
+/// # Arguments
+/// * `conn` - the connection to run the update on
+/// * `id` - the id of the job
+pub async fn requeue(conn: &mut PgConnection, id: i64) -> Result<bool, SqlxError> {
+    let sql = format!(
+        "UPDATE {} SET status = 'pending', attempts = 0, run_at = now(), finished_at = NULL \
+         WHERE id = $1 AND status = 'dead'",
+        JOBS_TABLE
+    );
+    let res = query(&sql).bind(id).execute(conn).await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Moves every dead-lettered job of `queue` back to the queue with fresh
+/// attempts
+///
+/// Returns the number of requeued jobs or the sqlx error of the update
+/// # Arguments
+/// * `conn` - the connection to run the update on
+/// * `queue` - the queue of the jobs
+pub async fn requeue_dead(conn: &mut PgConnection, queue: &str) -> Result<u64, SqlxError> {
+    let sql = format!(
+        "UPDATE {} SET status = 'pending', attempts = 0, run_at = now(), finished_at = NULL \
+         WHERE queue = $1 AND status = 'dead'",
+        JOBS_TABLE
+    );
+    let res = query(&sql).bind(queue).execute(conn).await?;
+    Ok(res.rows_affected())
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use sqlx::{Error as SqlxError, Executor, PgConnection};
+use tokio::task::JoinHandle;
+
+use crate::stats::timed_get;
+use crate::{AdvisoryLock, LockKey, Pool};
+
+/// A background task started by [`leader_task`]. The task stops, and gives
+/// up the lock if it holds it, when the value is dropped or the pool is
+/// closed.
+pub struct LeaderTask {
+    task: JoinHandle<()>,
+}
+
+impl LeaderTask {
+    /// Stops the task
+    pub fn shutdown(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for LeaderTask {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Starts a task that runs `job` every `interval` on the one replica of the
+/// fleet holding the advisory lock `name`. The other replicas try to take the
+/// lock on every tick, so one of them takes over within an interval when the
+/// leader stops or loses its connection.
+///
+/// `job` runs on the connection holding the lock. When it fails, the error
+/// is logged and the connection checked; a broken connection has lost the
+/// lock, so the task gives up the leadership and contends again.
+///
+/// Returns the handle of the task
+/// # Arguments
+/// * `pool` - the pool to get the connection holding the lock from
+/// * `name` - the name of the lock, the same on every replica
+/// * `interval` - the time between two runs of the job, and between two
+///   attempts to take the lock
+/// * `job` - a closure running the periodic work, e.g. a cleanup
+///
+/// Example usage
+/// ```ignore
+///  let cleanup = leader_task(
+///      db_pool.clone(),
+///      "expire-sessions",
+///      Duration::from_secs(60),
+///      |conn| Box::pin(async move {
+///          query("DELETE FROM sessions WHERE expires_at < now()")
+///              .execute(conn)
+///              .await
+///              .map(|_| ())
+///      }),
+///  );
+/// ```
+pub fn leader_task<F>(pool: Pool, name: &str, interval: Duration, job: F) -> LeaderTask
+where
+    F: for<'c> FnMut(&'c mut PgConnection) -> BoxFuture<'c, Result<(), SqlxError>> + Send + 'static,
+{
+    let key = LockKey::from(name);
+    LeaderTask {
+        task: tokio::spawn(run_leader(pool, key, interval, job)),
+    }
+}
+
+async fn run_leader<F>(pool: Pool, key: LockKey, interval: Duration, mut job: F)
+where
+    F: for<'c> FnMut(&'c mut PgConnection) -> BoxFuture<'c, Result<(), SqlxError>> + Send,
+{
+    let mut interval = tokio::time::interval(interval);
+    let mut lock: Option<AdvisoryLock> = None;
+    loop {
+        interval.tick().await;
+        if pool.is_closed() || pool.manager().is_draining() {
+            return;
+        }
+        let held = match lock.as_mut() {
+            Some(held) => held,
+            None => match try_lead(&pool, key).await {
+                Some(held) => lock.insert(held),
+                None => continue,
+            },
+        };
+        let res = job(&mut *held).await;
+        #[cfg(feature = "tracing")]
+        if let Err(e) = &res {
+            tracing::error!(
+                lock = key.0,
+                error = %crate::error::redact(&e.to_string()),
+                "leader job failed"
+            );
+        }
+        if res.is_err() && (&mut **held).execute("SELECT 1").await.is_err() {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(lock = key.0, "lost the leader connection");
+            lock = None;
+        }
+    }
+}
+
+/// Takes the lock without waiting, None when another replica leads or no
+/// connection could be had
+async fn try_lead(pool: &Pool, key: LockKey) -> Option<AdvisoryLock> {
+    let conn = timed_get(pool).await.ok()?;
+    let res = AdvisoryLock::try_acquire(conn, key).await;
+    #[cfg(feature = "tracing")]
+    match &res {
+        Ok(Some(_)) => tracing::info!(lock = key.0, "became the leader"),
+        Ok(None) => {}
+        Err(e) => tracing::warn!(
+            lock = key.0,
+            error = %crate::error::redact(&e.to_string()),
+            "failed to take the leader lock"
+        ),
+    }
+    res.ok().flatten()
+}
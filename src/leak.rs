@@ -0,0 +1,182 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "async-graphql")]
+use async_graphql::{Context, FieldResult};
+use deadpool::managed::Object;
+use sqlx::PgConnection;
+
+#[cfg(feature = "async-graphql")]
+use crate::{error, get_pool};
+use crate::{Pool, PoolManager};
+
+/// How long a connection may be held by default before it is reported
+pub const DEFAULT_LEAK_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Leak detection settings of a [`PoolManager`], used by the connections
+/// handed out as [`TrackedConnection`]
+#[derive(Clone, Debug)]
+pub struct LeakDetection {
+    /// Holding a connection longer than this is reported
+    pub threshold: Duration,
+    /// Whether a connection held past the threshold is closed on return
+    /// instead of being reused
+    pub force_recycle: bool,
+}
+
+impl Default for LeakDetection {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_LEAK_THRESHOLD,
+            force_recycle: false,
+        }
+    }
+}
+
+impl LeakDetection {
+    /// Reports connections held longer than `threshold`
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            force_recycle: false,
+        }
+    }
+
+    /// Closes connections held past the threshold when they are returned
+    pub fn force_recycle(mut self, force_recycle: bool) -> Self {
+        self.force_recycle = force_recycle;
+        self
+    }
+}
+
+/// A pooled connection that knows when and by whom it was acquired. A
+/// warning is logged while it is held past the leak threshold and again when
+/// it is returned, naming the label given at acquisition (the GraphQL path
+/// for [`get_tracked_connection`]).
+pub struct TrackedConnection {
+    conn: Option<Object<PoolManager>>,
+    acquired_at: Instant,
+    label: Arc<str>,
+    released: Arc<AtomicBool>,
+    settings: LeakDetection,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl TrackedConnection {
+    /// Starts tracking `conn`, with the pool's leak detection settings or the
+    /// default threshold
+    pub(crate) fn new(conn: Object<PoolManager>, pool: &Pool, label: &str) -> Self {
+        let settings = pool.manager().leak_settings().cloned().unwrap_or_default();
+        let label: Arc<str> = label.into();
+        let released = Arc::new(AtomicBool::new(false));
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let (label, released) = (label.clone(), released.clone());
+            let threshold = settings.threshold;
+            #[cfg(feature = "tracing")]
+            let span = tracing::Span::current();
+            handle.spawn(async move {
+                tokio::time::sleep(threshold).await;
+                if !released.load(Ordering::Relaxed) {
+                    #[cfg(feature = "tracing")]
+                    span.in_scope(|| {
+                        tracing::warn!(
+                            label = &*label,
+                            threshold_ms = threshold.as_millis() as u64,
+                            "connection held past the leak threshold"
+                        )
+                    });
+                    #[cfg(not(feature = "tracing"))]
+                    log::warn!(
+                        "connection acquired by {} held for more than {:?}",
+                        label,
+                        threshold
+                    );
+                }
+            });
+        }
+        Self {
+            conn: Some(conn),
+            acquired_at: Instant::now(),
+            label,
+            released,
+            settings,
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::current(),
+        }
+    }
+
+    /// How long the connection has been held
+    pub fn held_for(&self) -> Duration {
+        self.acquired_at.elapsed()
+    }
+}
+
+impl Deref for TrackedConnection {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        // only None while dropping
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for TrackedConnection {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        self.released.store(true, Ordering::Relaxed);
+        let held = self.held_for();
+        let conn = self.conn.take();
+        if held <= self.settings.threshold {
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        self.span.in_scope(|| {
+            tracing::warn!(
+                label = &*self.label,
+                held_ms = held.as_millis() as u64,
+                force_recycle = self.settings.force_recycle,
+                "returned connection held past the leak threshold"
+            )
+        });
+        #[cfg(not(feature = "tracing"))]
+        log::warn!(
+            "connection acquired by {} returned after {:?}",
+            self.label,
+            held
+        );
+        if self.settings.force_recycle {
+            if let Some(conn) = conn {
+                drop(Object::take(conn));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// Like [`crate::get_db_connection`], but returns a [`TrackedConnection`]
+/// labelled with the path of the resolver, so connections held across slow
+/// calls show up in the logs
+///
+/// Returns a Result with the tracked connection or a graphQL error
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool object is stored
+pub async fn get_tracked_connection(ctx: &Context<'_>) -> FieldResult<TrackedConnection> {
+    let pool = get_pool(ctx)?;
+    let conn = crate::stats::timed_get(pool)
+        .await
+        .map_err(error::map_pool_error)?;
+    let label = ctx
+        .path_node
+        .as_ref()
+        .map(|node| node.to_string())
+        .unwrap_or_else(|| "graphql".to_string());
+    Ok(TrackedConnection::new(conn, pool, &label))
+}
@@ -1,68 +1,338 @@
 //! This is a niche library of convenience types, structs, and
 //! functions for projects with sqlx, postgres, async_graphql.
+//!
+//! The GraphQL helpers are behind the default `async-graphql` feature. With
+//! `default-features = false` the pooling, transaction and error
+//! classification parts can be used by services that don't serve GraphQL.
 
+#[cfg(feature = "async-graphql")]
 extern crate async_graphql;
-use async_graphql::{Context, FieldError, FieldResult};
-use async_trait::async_trait;
-use deadpool::managed::{Manager, Object, RecycleResult};
-use sqlx::{Connection, Error as SqlxError, PgConnection};
+#[cfg(feature = "async-graphql")]
+use async_graphql::{Context, FieldResult};
+#[cfg(feature = "async-graphql")]
+use deadpool::managed::Object;
+#[cfg(feature = "async-graphql")]
+use sqlx::Error as SqlxError;
+
+#[cfg(feature = "actix")]
+mod actix_ext;
+#[cfg(feature = "async-graphql")]
+mod admin;
+mod advisory_lock;
+#[cfg(feature = "async-graphql")]
+mod application_name;
+#[cfg(feature = "axum")]
+mod axum_ext;
+mod bulk;
+mod chaos;
+mod circuit;
+mod config;
+#[cfg(feature = "async-graphql")]
+mod context_ext;
+mod copy;
+mod credentials;
+mod db_access;
+#[cfg(any(feature = "actix", feature = "axum", feature = "warp"))]
+mod db_conn;
+pub mod diagnostics;
+mod drivers;
+pub mod error;
+mod filter;
+#[cfg(feature = "async-graphql")]
+mod filter_view;
+mod fingerprint;
+mod health;
+mod hooks;
+mod jobs;
+mod leader;
+mod leak;
+#[cfg(feature = "async-graphql")]
+mod listen;
+#[cfg(feature = "async-graphql")]
+mod loader;
+#[cfg(feature = "async-graphql")]
+mod locking;
+mod maintenance;
+mod manager;
+#[cfg(feature = "prometheus")]
+pub mod metrics;
+mod migrate;
+#[cfg(feature = "async-graphql")]
+mod n_plus_one;
+#[cfg(feature = "async-graphql")]
+mod order_by;
+pub mod outbox;
+#[cfg(feature = "async-graphql")]
+mod pagination;
+mod pool_ext;
+mod query_tags;
+#[cfg(feature = "rds-iam")]
+mod rds_iam;
+mod registry;
+#[cfg(feature = "async-graphql")]
+mod request_connection;
+#[cfg(feature = "async-graphql")]
+mod request_transaction;
+mod retry;
+#[cfg(feature = "async-graphql")]
+mod rls;
+#[cfg(feature = "async-graphql")]
+mod role;
+#[cfg(feature = "async-graphql")]
+mod savepoint;
+mod scoped;
+mod secrets;
+mod seed;
+mod session;
+mod slow_query;
+mod soft_delete;
+mod split_pool;
+mod sql;
+mod stats;
+#[cfg(feature = "async-graphql")]
+mod stream;
+mod tenant;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "async-graphql")]
+mod timeout;
+mod tls;
+mod transaction;
+mod upsert;
+#[cfg(feature = "async-graphql")]
+mod versioned;
+#[cfg(feature = "warp")]
+mod warp_ext;
+
+#[cfg(feature = "actix")]
+pub use actix_ext::pool_data;
+#[cfg(feature = "async-graphql")]
+pub use admin::{pool_status, resize_pool, PoolStatus};
+pub use advisory_lock::{AdvisoryLock, LockKey};
+#[cfg(feature = "async-graphql")]
+pub use application_name::{ApplicationNameTagging, RequestApplicationName};
+pub use bulk::bulk_insert;
+pub use chaos::FaultInjection;
+pub use circuit::{CircuitBreaker, DEFAULT_FAILURE_THRESHOLD, DEFAULT_OPEN_DURATION};
+pub use config::{PoolConfig, DEFAULT_MAX_SIZE};
+#[cfg(feature = "async-graphql")]
+pub use context_ext::{ContextExt, DbTransaction};
+pub use copy::{copy_in, copy_in_rows, copy_out, copy_out_pooled, CopyFormat, COPY_CHUNK_SIZE};
+pub use credentials::{Credentials, CredentialsProvider};
+#[cfg(feature = "async-graphql")]
+pub use db_access::get_db_access;
+pub use db_access::DbAccess;
+#[cfg(any(feature = "actix", feature = "axum", feature = "warp"))]
+pub use db_conn::{DbConn, DbConnRejection};
+pub use deadpool::managed::QueueMode;
+#[cfg(feature = "async-graphql")]
+pub use drivers::get_connection;
+pub use drivers::DbPoolManager;
+#[cfg(feature = "any")]
+pub use drivers::{AnyPool, AnyPoolManager};
+#[cfg(feature = "mysql")]
+pub use drivers::{MySqlPool, MySqlPoolManager};
+#[cfg(feature = "sqlite")]
+pub use drivers::{SqlitePool, SqlitePoolManager};
+pub use filter::{CompareOp, Condition, Filter, FilterQuery};
+#[cfg(feature = "async-graphql")]
+pub use filter_view::{BoolFilter, FieldFilter, FilterView, FloatFilter, IntFilter, StringFilter};
+pub use fingerprint::{
+    fingerprint, StatementMetrics, StatementStats, DEFAULT_MAX_FINGERPRINTS, LATENCY_BUCKETS,
+    OTHER_FINGERPRINT,
+};
+pub use health::{check_health, check_health_with_timeout, HealthReport, DEFAULT_HEALTH_TIMEOUT};
+pub use hooks::ConnectionHook;
+pub use jobs::{
+    enqueue, requeue, requeue_dead, Job, JobError, NewJob, Worker, WorkerHandle,
+    DEFAULT_MAX_ATTEMPTS, DEFAULT_QUEUE, JOBS_SCHEMA, JOBS_TABLE,
+};
+pub use leader::{leader_task, LeaderTask};
+#[cfg(feature = "async-graphql")]
+pub use leak::get_tracked_connection;
+pub use leak::{LeakDetection, TrackedConnection, DEFAULT_LEAK_THRESHOLD};
+#[cfg(feature = "async-graphql")]
+pub use listen::{
+    decode_payload, notify_stream, notify_stream_typed, notify_stream_url, ListenerEvent,
+    ListenerManager, INVALID_NOTIFY_PAYLOAD, LISTENER_BUFFER,
+};
+#[cfg(feature = "async-graphql")]
+pub use loader::{group_rows, SqlLoader};
+#[cfg(feature = "async-graphql")]
+pub use locking::{fetch_for_share, fetch_for_update, LockWait, LOCK_NOT_AVAILABLE};
+pub use maintenance::{MaintenanceConfig, PoolMaintenance, DEFAULT_MAINTENANCE_INTERVAL};
+pub use manager::{ConnectRetry, PoolManager, DEFAULT_FAILOVER_COOLDOWN};
+#[cfg(feature = "async-graphql")]
+pub use migrate::migration_status;
+pub use migrate::{read_migration_status, run_migrations, MigrationReport, MigrationStatus};
+#[cfg(feature = "async-graphql")]
+pub use n_plus_one::{
+    record_query, NPlusOneDetection, QueryCountReport, RepeatedQuery, RequestQueries,
+    DEFAULT_N_PLUS_ONE_THRESHOLD,
+};
+#[cfg(feature = "async-graphql")]
+pub use order_by::{OrderBy, INVALID_SORT_FIELD};
+#[cfg(feature = "async-graphql")]
+pub use pagination::{
+    paginate_offset, relay_connection, KeysetBuilder, KeysetQuery, OffsetPage, OffsetPageInfo,
+    PageRequest, SortDirection, DEFAULT_PAGE_SIZE, INVALID_CURSOR, MAX_PAGE_SIZE,
+};
+pub use pool_ext::PoolExt;
+pub use query_tags::sql_comment;
+#[cfg(feature = "async-graphql")]
+pub use query_tags::{tag_sql, QueryTagging, RequestTags};
+#[cfg(feature = "rds-iam")]
+pub use rds_iam::RdsIamProvider;
+#[cfg(feature = "async-graphql")]
+pub use registry::get_named_connection;
+pub use registry::PoolRegistry;
+#[cfg(feature = "async-graphql")]
+pub use request_connection::{
+    get_request_connection, ConnectionPerRequest, RequestConnection, NO_REQUEST_CONNECTION,
+};
+#[cfg(feature = "async-graphql")]
+pub use request_transaction::{
+    get_request_transaction, RequestTransaction, TransactionPerRequest, NO_REQUEST_TRANSACTION,
+};
+#[cfg(feature = "async-graphql")]
+pub use retry::execute_with_retry;
+pub use retry::run_with_retry;
+#[cfg(feature = "async-graphql")]
+pub use rls::{
+    get_db_connection_for_user, RlsClaims, RlsConnection, CURRENT_TENANT_ID_SETTING,
+    CURRENT_USER_ID_SETTING,
+};
+#[cfg(feature = "async-graphql")]
+pub use role::{get_db_connection_as_role, RequestRole, NO_REQUEST_ROLE};
+#[cfg(feature = "async-graphql")]
+pub use savepoint::{with_savepoint, Savepoint};
+pub use scoped::ScopedConnection;
+pub use secrets::{CachedSecret, SecretSource, DEFAULT_SECRET_TTL};
+pub use seed::{Seeder, SEEDS_TABLE};
+pub use session::SessionConfig;
+pub use slow_query::{SlowQuery, SlowQueryLog, DEFAULT_MAX_SQL_LEN, DEFAULT_SLOW_QUERY_THRESHOLD};
+pub use soft_delete::{restore, soft_delete, DELETED_AT_COLUMN};
+pub use split_pool::SplitPool;
+#[cfg(feature = "async-graphql")]
+pub use split_pool::{get_read_connection, get_write_connection, ReadYourWrites, RequestWrites};
+pub use sql::{to_arguments, SqlValue};
+pub use sqlx::postgres::PgSslMode;
+#[cfg(feature = "testing")]
+pub use sqlx_helpers_macros::test;
+pub use stats::{PoolStats, WAIT_SAMPLE_SIZE};
+#[cfg(feature = "async-graphql")]
+pub use stream::fetch_stream;
+#[cfg(feature = "async-graphql")]
+pub use tenant::{get_schema_connection, get_tenant_connection};
+pub use tenant::{TenantPools, TenantSchemas, DEFAULT_MAX_TENANTS, UNKNOWN_TENANT_SCHEMA};
+#[cfg(feature = "async-graphql")]
+pub use timeout::with_timeout;
+pub use tls::{RootCert, TlsConfig};
+#[cfg(feature = "async-graphql")]
+pub use transaction::{retry_transaction, with_transaction};
+pub use transaction::{run_retry_transaction, run_transaction, RetryPolicy};
+pub use upsert::Upsert;
+#[cfg(feature = "async-graphql")]
+pub use versioned::{update_versioned, STALE_VERSION, VERSION_COLUMN};
+#[cfg(feature = "warp")]
+pub use warp_ext::{recover_db_conn, with_db_conn, with_pool};
 
 /// A replacement for sqlx's connection pool using deadpool
 pub type Pool = deadpool::managed::Pool<PoolManager>;
 
-/// A pool manager for managing sqlx database connections
-pub struct PoolManager {
-    pub url: String,
+#[cfg(feature = "async-graphql")]
+/// This is a convenience function that performs match on a Result type and if
+/// is an error, prepends a helpful error message to the Err returned.
+///
+/// Returns Ok or Err with a custom error message. The error extensions carry
+/// the error `category` and, for database errors, the `sqlstate` and
+/// `constraint` (see [`error::map_sqlx_error`]).
+/// # Arguments
+/// * `res` - a Result type to evaluate
+/// * `err_msg` - a custom error message that will be prepended if Err is returned  
+pub fn match_result<T>(res: Result<T, SqlxError>, err_msg: String) -> FieldResult<T> {
+    res.map_err(|e| {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            error_kind = error::categorize(&e).as_str(),
+            error = %error::redact(&e.to_string()),
+            "{}",
+            err_msg
+        );
+        error::map_sqlx_error(e, &err_msg)
+    })
 }
 
-#[async_trait]
+#[cfg(feature = "async-graphql")]
+/// A variant of [`match_result`] that only builds the error message when the
+/// result is an error and sets `code` as the `code` extension, in place of
+/// the one derived from the error.
+///
+/// Returns Ok or Err with the message returned by `err_msg`
+/// # Arguments
+/// * `res` - a Result type to evaluate
+/// * `code` - value for the `code` extension key
+/// * `err_msg` - called on error to build the message that is prepended
+///
 /// Example usage
 /// ```ignore
-///  // somewhere in your initialization code for your graphql server
-///  let mgr = PoolManager { url: database_url };
-///  let db_pool = Pool::new(mgr, 16);
-///  async_graphql::Schema::build(QueryRoot::default(),
-///     EmptyMutation::default(), EmptySubscription).data(pool);
+/// match_result_with(res, "ORDER_NOT_SAVED", || format!("Failed to save order {}", id))
 /// ```
-impl Manager for PoolManager {
-    type Type = PgConnection;
-    type Error = SqlxError;
-    async fn create(&self) -> Result<PgConnection, SqlxError> {
-        PgConnection::connect(&self.url).await
-    }
-    async fn recycle(&self, obj: &mut PgConnection) -> RecycleResult<SqlxError> {
-        Ok(obj.ping().await?)
-    }
-
-    fn detach(&self, _obj: &mut Self::Type) {}
+pub fn match_result_with<T, F>(res: Result<T, SqlxError>, code: &str, err_msg: F) -> FieldResult<T>
+where
+    F: FnOnce() -> String,
+{
+    res.map_err(|e| {
+        let err_msg = err_msg();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            error_kind = error::categorize(&e).as_str(),
+            error = %error::redact(&e.to_string()),
+            code,
+            "{}",
+            err_msg
+        );
+        let mut err = error::map_sqlx_error(e, &err_msg);
+        err.extensions
+            .get_or_insert_with(Default::default)
+            .set("code", code);
+        err
+    })
 }
 
-/// This is a convenience function that performs match on a Result type and if
-/// is an error, prepends a helpful error message to the Err returned.
+#[cfg(feature = "async-graphql")]
+/// Like [`match_result`], but for nullable fields: `SqlxError::RowNotFound`
+/// becomes `Ok(None)` instead of an error.
 ///
-/// Returns Ok or Err with a custom error message
+/// Returns Ok with the optional value or Err with a custom error message
 /// # Arguments
-/// * `res` - a Result type to evaluate
-/// * `err_msg` - a custom error message that will be prepended if Err is returned  
-pub fn match_result<T>(res: Result<T, SqlxError>, err_msg: String) -> FieldResult<T> {
+/// * `res` - a Result type to evaluate, typically from `fetch_one`
+/// * `err_msg` - a custom error message that will be prepended if Err is returned
+///
+/// Example usage
+/// ```ignore
+/// let row = query_as::<_, MyData>("SELECT * FROM my_data WHERE id = $1")
+///     .bind(id)
+///     .fetch_one(&mut *conn)
+///     .await;
+/// match_optional(row, format!("Failed to get my_data {}", id))
+/// ```
+pub fn match_optional<T>(res: Result<T, SqlxError>, err_msg: String) -> FieldResult<Option<T>> {
     match res {
-        Ok(res) => Ok(res),
-        Err(e) => {
-            return Err(FieldError {
-                message: format!("{} {:?}", err_msg, e),
-                extensions: None,
-                source: None,
-            });
-        }
+        Ok(value) => Ok(Some(value)),
+        Err(SqlxError::RowNotFound) => Ok(None),
+        Err(e) => match_result(Err(e), err_msg),
     }
 }
 
+#[cfg(feature = "async-graphql")]
 /// Extracts a connection object out of the Pool. Caller will still need to call
 /// a deref_mut() on the returned object to get the object dereferenced in its
 /// correct Type. This function assumes you have graphQL context that has a Pool
-/// object defined in it.
+/// object defined in it (see [`get_pool`]).
 ///
-/// Returns a Result with the connection object or a graphQL error
+/// Returns a Result with the connection object or a graphQL error. If the
+/// context has no Pool, the error carries the `POOL_NOT_CONFIGURED` code.
 /// # Arguments
 /// * `ctx` - graphQL context where the Pool object is stored
 ///
@@ -83,13 +353,83 @@ pub fn match_result<T>(res: Result<T, SqlxError>, err_msg: String) -> FieldResul
 ///       format!("Failed to get my_data"),
 ///     )
 /// ```
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "sqlx_helpers.get_db_connection", skip_all)
+)]
 pub async fn get_db_connection(
     ctx: &Context<'_>,
 ) -> Result<Object<PoolManager>, async_graphql::FieldError> {
-    let pool = ctx.data::<Pool>().unwrap(); // this cannot fail - panic if failure
-    pool.get().await.map_err(|e| async_graphql::FieldError {
-        message: format!("Database connectivity error: {:?}", e.to_string()),
-        extensions: None,
-        source: None,
+    let mut conn = acquire(get_pool(ctx)?).await?;
+    application_name::apply_application_name(ctx, &mut conn).await?;
+    Ok(conn)
+}
+
+#[cfg(feature = "async-graphql")]
+/// Gets a connection from the given pool, mapping failures to a graphQL error
+pub(crate) async fn acquire(pool: &Pool) -> FieldResult<Object<PoolManager>> {
+    stats::timed_get(pool).await.map_err(error::map_pool_error)
+}
+
+#[cfg(feature = "async-graphql")]
+/// Looks up the Pool stored in the graphQL context without panicking.
+///
+/// Returns the Pool or a graphQL error with the `POOL_NOT_CONFIGURED` code if
+/// the schema was built without a Pool
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool object is stored
+pub fn get_pool<'a>(ctx: &Context<'a>) -> FieldResult<&'a Pool> {
+    ctx.data_opt::<Pool>().ok_or_else(|| {
+        error::coded_error(
+            "Database pool is not configured in the graphQL context".to_string(),
+            error::POOL_NOT_CONFIGURED,
+        )
     })
 }
+
+#[cfg(feature = "async-graphql")]
+/// Like [`get_db_connection`], for services with more than one database: the
+/// pool is looked up by the newtype `K` wrapping it instead of as a plain
+/// Pool, so every database gets its own entry in the graphQL context.
+///
+/// Returns a Result with the connection object or a graphQL error with the
+/// `POOL_NOT_CONFIGURED` code if the context has no `K`
+/// # Arguments
+/// * `ctx` - graphQL context where the `K` object is stored
+///
+/// Example usage
+/// ```ignore
+/// pub struct AnalyticsPool(pub Pool);
+///
+/// impl AsRef<Pool> for AnalyticsPool {
+///     fn as_ref(&self) -> &Pool {
+///         &self.0
+///     }
+/// }
+///
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     EmptyMutation::default(), EmptySubscription)
+///     .data(db_pool)
+///     .data(AnalyticsPool(analytics_pool))
+///     .finish();
+///
+/// // in a resolver
+/// let mut conn = get_db_connection_as::<AnalyticsPool>(ctx).await?;
+/// ```
+pub async fn get_db_connection_as<K>(ctx: &Context<'_>) -> FieldResult<Object<PoolManager>>
+where
+    K: AsRef<Pool> + Send + Sync + 'static,
+{
+    let pool = ctx.data_opt::<K>().ok_or_else(|| {
+        error::coded_error(
+            format!(
+                "Database pool {} is not configured in the graphQL context",
+                std::any::type_name::<K>()
+            ),
+            error::POOL_NOT_CONFIGURED,
+        )
+    })?;
+    let mut conn = acquire(pool.as_ref()).await?;
+    application_name::apply_application_name(ctx, &mut conn).await?;
+    Ok(conn)
+}
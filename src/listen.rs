@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_graphql::FieldResult;
+use futures::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use sqlx::postgres::{PgListener, PgPool, PgPoolOptions};
+use sqlx::Error as SqlxError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::{error, match_result, Pool};
+
+/// Extension code of the error item produced for a NOTIFY payload that does
+/// not deserialize into the expected type
+pub const INVALID_NOTIFY_PAYLOAD: &str = "INVALID_NOTIFY_PAYLOAD";
+
+/// Opens a dedicated LISTEN connection to the database of the given Pool and
+/// returns the payloads of the notifications sent to `channel`, suitable for
+/// returning from an async_graphql subscription resolver.
+///
+/// The listener uses its own connection, so it does not hold a pooled
+/// connection for its lifetime. It connects like the Pool's manager, to the
+/// first failover host accepting connections and with the credentials of its
+/// credentials provider. Reconnects after a dropped connection reuse that
+/// host and those credentials.
+///
+/// Returns the stream or a graphQL error if connecting or listening fails
+/// # Arguments
+/// * `pool` - the pool whose connect options are used
+/// * `channel` - the NOTIFY channel to listen on
+///
+/// Example usage
+/// ```ignore
+/// #[Subscription]
+/// impl SubscriptionRoot {
+///     async fn order_events(&self, ctx: &Context<'_>)
+///         -> FieldResult<impl Stream<Item = FieldResult<String>>> {
+///         notify_stream(get_pool(ctx)?, "order_events").await
+///     }
+/// }
+/// ```
+pub async fn notify_stream(
+    pool: &Pool,
+    channel: &str,
+) -> FieldResult<impl Stream<Item = FieldResult<String>>> {
+    let listener_pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_lazy_with(match_result(
+            pool.manager().connect_options().await,
+            "Failed to open listener connection".to_string(),
+        )?);
+    let listener = match_result(
+        PgListener::connect_with(&listener_pool).await,
+        "Failed to open listener connection".to_string(),
+    )?;
+    listen_stream(listener, channel).await
+}
+
+/// Like [`notify_stream`], but connects to the given database url
+///
+/// # Arguments
+/// * `url` - a postgres connection url
+/// * `channel` - the NOTIFY channel to listen on
+pub async fn notify_stream_url(
+    url: &str,
+    channel: &str,
+) -> FieldResult<impl Stream<Item = FieldResult<String>>> {
+    let listener = match_result(
+        PgListener::connect(url).await,
+        "Failed to open listener connection".to_string(),
+    )?;
+    listen_stream(listener, channel).await
+}
+
+/// Like [`notify_stream`], but deserializes every JSON payload into `T`. A
+/// malformed payload produces an error item with the `INVALID_NOTIFY_PAYLOAD`
+/// code and the stream carries on with the next notification.
+///
+/// # Arguments
+/// * `pool` - the pool whose connect options are used
+/// * `channel` - the NOTIFY channel to listen on
+///
+/// Example usage
+/// ```ignore
+/// #[derive(Deserialize, SimpleObject)]
+/// struct OrderEvent { order_id: i64, status: String }
+///
+/// async fn order_events(&self, ctx: &Context<'_>)
+///     -> FieldResult<impl Stream<Item = FieldResult<OrderEvent>>> {
+///     notify_stream_typed::<OrderEvent>(get_pool(ctx)?, "order_events").await
+/// }
+/// ```
+pub async fn notify_stream_typed<T: DeserializeOwned>(
+    pool: &Pool,
+    channel: &str,
+) -> FieldResult<impl Stream<Item = FieldResult<T>>> {
+    let stream = notify_stream(pool, channel).await?;
+    Ok(stream.map(|res| res.and_then(|payload| decode_payload(&payload))))
+}
+
+/// Deserializes a JSON NOTIFY payload into `T`
+///
+/// Returns the value or a graphQL error with the `INVALID_NOTIFY_PAYLOAD` code
+/// # Arguments
+/// * `payload` - the notification payload
+pub fn decode_payload<T: DeserializeOwned>(payload: &str) -> FieldResult<T> {
+    serde_json::from_str(payload).map_err(|e| {
+        error::coded_error(
+            format!("Invalid notification payload {:?}", e.to_string()),
+            INVALID_NOTIFY_PAYLOAD,
+        )
+    })
+}
+
+async fn listen_stream(
+    mut listener: PgListener,
+    channel: &str,
+) -> FieldResult<impl Stream<Item = FieldResult<String>>> {
+    match_result(
+        listener.listen(channel).await,
+        format!("Failed to listen on channel {}", channel),
+    )?;
+    Ok(listener.into_stream().map(|res| {
+        match_result(
+            res.map(|notification| notification.payload().to_string()),
+            "Failed to receive notification".to_string(),
+        )
+    }))
+}
+
+/// Capacity of the broadcast channel of every channel subscribed through a
+/// [`ListenerManager`]
+pub const LISTENER_BUFFER: usize = 256;
+
+/// The delay before the listener task retries after failing to reconnect
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// An event delivered to the subscribers of a [`ListenerManager`]
+#[derive(Clone, Debug)]
+pub enum ListenerEvent {
+    /// A notification sent to the subscribed channel
+    Notification { channel: String, payload: String },
+    /// The listener connection was lost and has been re-established with all
+    /// channels listened to again. Notifications sent in between are lost, so
+    /// consumers should refetch the state they track.
+    Resubscribed,
+}
+
+type Subscribers = Arc<Mutex<HashMap<String, broadcast::Sender<ListenerEvent>>>>;
+
+enum ListenCommand {
+    Listen {
+        channel: String,
+        reply: oneshot::Sender<Result<(), SqlxError>>,
+    },
+    /// Issued for channels whose receivers have all been dropped
+    Unlisten(String),
+}
+
+/// Owns one dedicated LISTEN connection and multiplexes any number of
+/// channels to broadcast receivers. Lost connections are re-established and
+/// all channels listened to again transparently, after which subscribers get
+/// a [`ListenerEvent::Resubscribed`] event. Channels whose receivers have all
+/// been dropped are unlistened at the next subscription.
+///
+/// Example usage
+/// ```ignore
+///  let listeners = ListenerManager::start(&db_pool).await?;
+///  // in a subscription resolver
+///  let events = listeners.subscribe("order_events").await?;
+///  Ok(BroadcastStream::new(events))
+/// ```
+pub struct ListenerManager {
+    subscribers: Subscribers,
+    commands: mpsc::UnboundedSender<ListenCommand>,
+    task: JoinHandle<()>,
+}
+
+impl ListenerManager {
+    /// Opens the listener connection like the Pool's manager connects (see
+    /// [`notify_stream`]) and starts the background task delivering
+    /// notifications
+    ///
+    /// Returns the manager or a graphQL error if the connection fails
+    /// # Arguments
+    /// * `pool` - the pool whose connect options are used
+    pub async fn start(pool: &Pool) -> FieldResult<Self> {
+        let listener_pool =
+            PgPoolOptions::new()
+                .max_connections(1)
+                .connect_lazy_with(match_result(
+                    pool.manager().connect_options().await,
+                    "Failed to open listener connection".to_string(),
+                )?);
+        let listener = match_result(
+            PgListener::connect_with(&listener_pool).await,
+            "Failed to open listener connection".to_string(),
+        )?;
+        let subscribers = Subscribers::default();
+        let (commands, command_rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run_listener(
+            listener_pool,
+            listener,
+            subscribers.clone(),
+            command_rx,
+        ));
+        Ok(Self {
+            subscribers,
+            commands,
+            task,
+        })
+    }
+
+    /// Subscribes to `channel`, issuing `LISTEN` for it the first time, and
+    /// `UNLISTEN` for the channels nobody receives anymore
+    ///
+    /// Returns a receiver of the channel's events or a graphQL error
+    /// # Arguments
+    /// * `channel` - the NOTIFY channel to subscribe to
+    pub async fn subscribe(
+        &self,
+        channel: &str,
+    ) -> FieldResult<broadcast::Receiver<ListenerEvent>> {
+        let receiver = {
+            let mut subscribers = self.subscribers.lock().unwrap();
+            // queued under the lock, so an UNLISTEN always precedes the
+            // LISTEN of a new subscription to the same channel
+            subscribers.retain(|name, sender| {
+                let unused = sender.receiver_count() == 0;
+                if unused {
+                    let _ = self.commands.send(ListenCommand::Unlisten(name.clone()));
+                }
+                !unused
+            });
+            if let Some(sender) = subscribers.get(channel) {
+                return Ok(sender.subscribe());
+            }
+            let (sender, receiver) = broadcast::channel(LISTENER_BUFFER);
+            subscribers.insert(channel.to_string(), sender);
+            receiver
+        };
+        let (reply, reply_rx) = oneshot::channel();
+        let sent = self.commands.send(ListenCommand::Listen {
+            channel: channel.to_string(),
+            reply,
+        });
+        let res = match (sent, reply_rx.await) {
+            (Ok(()), Ok(res)) => res,
+            _ => Err(SqlxError::WorkerCrashed),
+        };
+        if res.is_err() {
+            self.subscribers.lock().unwrap().remove(channel);
+        }
+        match_result(res, format!("Failed to listen on channel {}", channel))?;
+        Ok(receiver)
+    }
+
+    /// Stops the background task and closes the listener connection
+    pub fn shutdown(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for ListenerManager {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn broadcast_event(subscribers: &Subscribers, channel: Option<&str>, event: ListenerEvent) {
+    let subscribers = subscribers.lock().unwrap();
+    match channel {
+        Some(channel) => {
+            if let Some(sender) = subscribers.get(channel) {
+                // no receivers left is not an error for the listener
+                let _ = sender.send(event);
+            }
+        }
+        None => {
+            for sender in subscribers.values() {
+                let _ = sender.send(event.clone());
+            }
+        }
+    }
+}
+
+async fn run_listener(
+    pool: PgPool,
+    mut listener: PgListener,
+    subscribers: Subscribers,
+    mut commands: mpsc::UnboundedReceiver<ListenCommand>,
+) {
+    let mut channels: Vec<String> = Vec::new();
+    loop {
+        if !serve(&mut listener, &subscribers, &mut commands, &mut channels).await {
+            return;
+        }
+        // the connection was lost: subscribers are told only once every
+        // channel is listened to again, so a refetch can't miss anything
+        drop(listener);
+        listener = reconnect(&pool, &channels).await;
+        broadcast_event(&subscribers, None, ListenerEvent::Resubscribed);
+    }
+}
+
+/// Delivers notifications and runs commands until the connection is lost,
+/// returning true, or the manager is dropped, returning false
+async fn serve(
+    listener: &mut PgListener,
+    subscribers: &Subscribers,
+    commands: &mut mpsc::UnboundedReceiver<ListenCommand>,
+    channels: &mut Vec<String>,
+) -> bool {
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(ListenCommand::Listen { channel, reply }) => {
+                    let res = listener.listen(&channel).await;
+                    if res.is_ok() {
+                        channels.push(channel);
+                    }
+                    let _ = reply.send(res);
+                }
+                Some(ListenCommand::Unlisten(channel)) => {
+                    channels.retain(|listened| *listened != channel);
+                    let _ = listener.unlisten(&channel).await;
+                }
+                // the manager was dropped
+                None => return false,
+            },
+            res = listener.try_recv() => match res {
+                Ok(Some(notification)) => broadcast_event(
+                    subscribers,
+                    Some(notification.channel()),
+                    ListenerEvent::Notification {
+                        channel: notification.channel().to_string(),
+                        payload: notification.payload().to_string(),
+                    },
+                ),
+                Ok(None) | Err(_) => return true,
+            },
+        }
+    }
+}
+
+/// Opens a new listener connection and listens to `channels` on it,
+/// retrying until it succeeds
+async fn reconnect(pool: &PgPool, channels: &[String]) -> PgListener {
+    loop {
+        let res = async {
+            let mut listener = PgListener::connect_with(pool).await?;
+            listener
+                .listen_all(channels.iter().map(String::as_str))
+                .await?;
+            Ok::<_, SqlxError>(listener)
+        }
+        .await;
+        match res {
+            Ok(listener) => return listener,
+            Err(_) => tokio::time::sleep(RECONNECT_DELAY).await,
+        }
+    }
+}
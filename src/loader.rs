@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use async_graphql::dataloader::Loader;
+use async_graphql::FieldError;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use sqlx::{Error as SqlxError, PgConnection};
+
+use crate::{acquire, match_result, Pool};
+
+type BatchFn<K, V> = dyn for<'c> Fn(&'c mut PgConnection, &'c [K]) -> BoxFuture<'c, Result<HashMap<K, V>, SqlxError>>
+    + Send
+    + Sync;
+
+/// An async_graphql Loader backed by the Pool. It gets a connection for every
+/// batch and runs the batched query function with all requested keys, mapping
+/// failures to graphQL errors.
+///
+/// Example usage
+/// ```ignore
+///  let users = SqlLoader::new(db_pool.clone(), |conn, ids: &[i64]| {
+///      Box::pin(async move {
+///          let rows = query_as::<_, User>("SELECT * FROM users WHERE id = ANY($1)")
+///              .bind(ids)
+///              .fetch_all(conn)
+///              .await?;
+///          Ok(rows.into_iter().map(|user| (user.id, user)).collect())
+///      })
+///  });
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     EmptyMutation::default(), EmptySubscription)
+///     .data(DataLoader::new(users, tokio::spawn));
+/// ```
+pub struct SqlLoader<K, V> {
+    pool: Pool,
+    load_fn: Arc<BatchFn<K, V>>,
+}
+
+impl<K, V> SqlLoader<K, V> {
+    /// Creates a loader from a pool and a batched query function
+    ///
+    /// # Arguments
+    /// * `pool` - the pool used to get a connection for every batch
+    /// * `load_fn` - receives a connection and the keys of a batch and
+    ///   returns the values found, keyed by their key
+    pub fn new<F>(pool: Pool, load_fn: F) -> Self
+    where
+        F: for<'c> Fn(
+                &'c mut PgConnection,
+                &'c [K],
+            ) -> BoxFuture<'c, Result<HashMap<K, V>, SqlxError>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            pool,
+            load_fn: Arc::new(load_fn),
+        }
+    }
+}
+
+#[async_trait]
+impl<K, V> Loader<K> for SqlLoader<K, V>
+where
+    K: Send + Sync + Hash + Eq + Clone + 'static,
+    V: Send + Sync + Clone + 'static,
+{
+    type Value = V;
+    type Error = FieldError;
+
+    async fn load(&self, keys: &[K]) -> Result<HashMap<K, V>, FieldError> {
+        let mut conn = acquire(&self.pool).await?;
+        match_result(
+            (self.load_fn)(&mut *conn, keys).await,
+            "Failed to batch load".to_string(),
+        )
+    }
+}
+
+impl<K, V> SqlLoader<K, Vec<V>>
+where
+    K: Send + Sync + Hash + Eq + Clone + 'static,
+    V: Send + 'static,
+{
+    /// Creates a loader for one-to-many and many-to-many relationships. The
+    /// query function returns `(parent key, child)` rows, typically from a
+    /// join table, and the loader groups them by parent key. Every requested
+    /// key gets an entry, so parents without children resolve to an empty list.
+    ///
+    /// # Arguments
+    /// * `pool` - the pool used to get a connection for every batch
+    /// * `rows_fn` - receives a connection and the parent keys of a batch and
+    ///   returns the matching `(parent key, child)` rows
+    ///
+    /// Example usage
+    /// ```ignore
+    ///  let tags_by_post = SqlLoader::grouped(db_pool.clone(), |conn, post_ids: &[i64]| {
+    ///      Box::pin(async move {
+    ///          let rows = query_as::<_, (i64, Tag)>(
+    ///              "SELECT pt.post_id, t.* FROM post_tags pt
+    ///               JOIN tags t ON t.id = pt.tag_id
+    ///               WHERE pt.post_id = ANY($1)",
+    ///          )
+    ///          .bind(post_ids)
+    ///          .fetch_all(conn)
+    ///          .await?;
+    ///          Ok(rows)
+    ///      })
+    ///  });
+    /// ```
+    pub fn grouped<F>(pool: Pool, rows_fn: F) -> Self
+    where
+        F: for<'c> Fn(
+                &'c mut PgConnection,
+                &'c [K],
+            ) -> BoxFuture<'c, Result<Vec<(K, V)>, SqlxError>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self::new(pool, move |conn, keys| {
+            let rows = rows_fn(conn, keys);
+            Box::pin(async move { Ok(group_rows(keys, rows.await?)) })
+        })
+    }
+}
+
+/// Groups `(parent key, child)` rows by parent key, keeping the row order
+/// within each group and adding an empty group for every key without rows
+///
+/// # Arguments
+/// * `keys` - the parent keys that were requested
+/// * `rows` - the `(parent key, child)` rows returned by the query
+pub fn group_rows<K, V>(keys: &[K], rows: Vec<(K, V)>) -> HashMap<K, Vec<V>>
+where
+    K: Hash + Eq + Clone,
+{
+    let mut groups: HashMap<K, Vec<V>> = keys.iter().map(|key| (key.clone(), Vec::new())).collect();
+    for (key, value) in rows {
+        groups.entry(key).or_default().push(value);
+    }
+    groups
+}
@@ -0,0 +1,116 @@
+use async_graphql::FieldResult;
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::{FromRow, PgConnection};
+
+use crate::error::sqlstate;
+use crate::{match_result, match_result_with};
+
+/// Extension code returned when a row lock could not be taken, because of
+/// `NOWAIT` or because the `lock_timeout` of the session expired
+pub const LOCK_NOT_AVAILABLE: &str = "LOCK_NOT_AVAILABLE";
+
+/// What a locking read does about rows locked by other transactions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockWait {
+    /// Waits until the rows are unlocked, up to the `lock_timeout`
+    Wait,
+    /// Fails with `LOCK_NOT_AVAILABLE` right away
+    NoWait,
+    /// Leaves the locked rows out of the result, e.g. for job queues
+    SkipLocked,
+}
+
+impl LockWait {
+    fn clause(self) -> &'static str {
+        match self {
+            LockWait::Wait => "",
+            LockWait::NoWait => " NOWAIT",
+            LockWait::SkipLocked => " SKIP LOCKED",
+        }
+    }
+}
+
+async fn fetch_locked<T>(
+    conn: &mut PgConnection,
+    sql: &str,
+    args: PgArguments,
+    strength: &str,
+    wait: LockWait,
+) -> FieldResult<Vec<T>>
+where
+    T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+{
+    let sql = format!("{} {}{}", sql.trim_end(), strength, wait.clause());
+    crate::n_plus_one::record_sql(&sql);
+    let res = sqlx::query_as_with::<_, T, _>(&sql, args)
+        .fetch_all(conn)
+        .await;
+    match res {
+        Err(e) if sqlstate(&e).as_deref() == Some("55P03") => {
+            match_result_with(Err(e), LOCK_NOT_AVAILABLE, || {
+                "The rows are locked by another transaction".to_string()
+            })
+        }
+        res => match_result(res, "Failed to lock rows".to_string()),
+    }
+}
+
+/// Runs `sql` with `FOR UPDATE` appended, locking the returned rows against
+/// updates, deletes and other locking reads until the end of the
+/// transaction. Run it inside a transaction, e.g. of
+/// [`crate::with_transaction`] or [`crate::ContextExt::db_tx`]; outside of
+/// one the locks are released as soon as the statement finishes.
+///
+/// Returns the rows, or a graphQL error with the `LOCK_NOT_AVAILABLE` code
+/// when the rows are locked and `wait` is [`LockWait::NoWait`] or the
+/// `lock_timeout` expired
+/// # Arguments
+/// * `conn` - the connection of the transaction
+/// * `sql` - a SELECT without a locking clause
+/// * `args` - the binds of `sql`
+/// * `wait` - what to do about rows locked by other transactions
+///
+/// Example usage
+/// ```ignore
+/// let mut args = PgArguments::default();
+/// args.add(account_id);
+/// let accounts: Vec<Account> = fetch_for_update(
+///     &mut *tx,
+///     "SELECT * FROM accounts WHERE id = $1",
+///     args,
+///     LockWait::NoWait,
+/// )
+/// .await?;
+/// ```
+pub async fn fetch_for_update<T>(
+    conn: &mut PgConnection,
+    sql: &str,
+    args: PgArguments,
+    wait: LockWait,
+) -> FieldResult<Vec<T>>
+where
+    T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+{
+    fetch_locked(conn, sql, args, "FOR UPDATE", wait).await
+}
+
+/// Like [`fetch_for_update`], but takes a shared lock with `FOR SHARE`, which
+/// blocks updates and deletes of the rows but not other shared locks
+///
+/// Returns the rows, or a graphQL error with the `LOCK_NOT_AVAILABLE` code
+/// # Arguments
+/// * `conn` - the connection of the transaction
+/// * `sql` - a SELECT without a locking clause
+/// * `args` - the binds of `sql`
+/// * `wait` - what to do about rows locked by other transactions
+pub async fn fetch_for_share<T>(
+    conn: &mut PgConnection,
+    sql: &str,
+    args: PgArguments,
+    wait: LockWait,
+) -> FieldResult<Vec<T>>
+where
+    T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+{
+    fetch_locked(conn, sql, args, "FOR SHARE", wait).await
+}
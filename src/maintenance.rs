@@ -0,0 +1,143 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+use deadpool::managed::Timeouts;
+use tokio::task::JoinHandle;
+
+use crate::Pool;
+
+/// How often the maintenance task runs by default
+pub const DEFAULT_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Settings of the [`PoolMaintenance`] task
+#[derive(Clone, Debug)]
+pub struct MaintenanceConfig {
+    /// Idle connections unused for longer than this are closed
+    pub idle_timeout: Option<Duration>,
+    /// The number of idle connections the task keeps open
+    pub min_idle: usize,
+    /// The time between two maintenance runs
+    pub interval: Duration,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: None,
+            min_idle: 0,
+            interval: DEFAULT_MAINTENANCE_INTERVAL,
+        }
+    }
+}
+
+impl MaintenanceConfig {
+    /// Creates a config that neither prunes nor tops up connections
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets after how long without use an idle connection is closed
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the number of idle connections to keep open
+    pub fn min_idle(mut self, min_idle: usize) -> Self {
+        self.min_idle = min_idle;
+        self
+    }
+
+    /// Sets the time between two maintenance runs
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+/// A background task that closes connections idle for longer than the idle
+/// timeout and opens new ones while fewer than `min_idle` are idle, so the
+/// pool follows the traffic instead of holding its peak size forever. The
+/// task stops when the value is dropped or the pool is closed.
+///
+/// Example usage
+/// ```ignore
+///  let maintenance = PoolMaintenance::start(
+///      db_pool.clone(),
+///      MaintenanceConfig::new()
+///          .idle_timeout(Duration::from_secs(600))
+///          .min_idle(4),
+///  );
+/// ```
+pub struct PoolMaintenance {
+    task: JoinHandle<()>,
+}
+
+impl PoolMaintenance {
+    /// Starts the maintenance task for `pool`
+    pub fn start(pool: Pool, config: MaintenanceConfig) -> Self {
+        Self {
+            task: tokio::spawn(run_maintenance(pool, config)),
+        }
+    }
+
+    /// Stops the maintenance task
+    pub fn shutdown(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for PoolMaintenance {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run_maintenance(pool: Pool, config: MaintenanceConfig) {
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        interval.tick().await;
+        if pool.is_closed() || pool.manager().is_draining() {
+            return;
+        }
+        if let Some(idle_timeout) = config.idle_timeout {
+            prune_idle(&pool, idle_timeout, config.min_idle);
+        }
+        top_up(&pool, config.min_idle).await;
+    }
+}
+
+/// Closes idle connections unused for longer than `idle_timeout`, keeping
+/// at least `min_idle` of them
+fn prune_idle(pool: &Pool, idle_timeout: Duration, min_idle: usize) {
+    let kept = Cell::new(0);
+    pool.retain(|_, metrics| {
+        let keep = metrics.last_used() < idle_timeout || kept.get() < min_idle;
+        if keep {
+            kept.set(kept.get() + 1);
+        }
+        keep
+    });
+}
+
+/// Opens connections until `min_idle` connections are idle or the pool is
+/// full. Connections are only taken without waiting, so callers are never
+/// held up by the task.
+async fn top_up(pool: &Pool, min_idle: usize) {
+    let status = pool.status();
+    if status.available >= min_idle {
+        return;
+    }
+    let no_wait = Timeouts {
+        wait: Some(Duration::ZERO),
+        ..pool.timeouts()
+    };
+    let mut held = Vec::with_capacity(min_idle);
+    while held.len() < min_idle {
+        match pool.timeout_get(&no_wait).await {
+            Ok(conn) => held.push(conn),
+            Err(_) => break,
+        }
+    }
+    // the connections become idle again when they are dropped here
+}
@@ -0,0 +1,162 @@
+//! Prometheus metrics for the pool and the queries run through this crate.
+//! Only available with the `prometheus` feature.
+//!
+//! Example usage
+//! ```ignore
+//!  // in the handler of your /metrics endpoint
+//!  let body = sqlx_helpers::metrics::encode(&db_pool)?;
+//! ```
+
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use prometheus::{
+    Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::{Pool, PoolExt};
+
+struct Metrics {
+    registry: Registry,
+    pool_max_size: IntGauge,
+    pool_size: IntGauge,
+    pool_idle: IntGauge,
+    pool_in_use: IntGauge,
+    pool_waiting: IntGauge,
+    acquire_seconds: Histogram,
+    recycle_failures: IntCounter,
+    query_seconds: Histogram,
+    statement_seconds: HistogramVec,
+    statement_errors: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("sqlx_helpers".to_string()), None)?;
+        let gauge = |name: &str, help: &str| -> prometheus::Result<IntGauge> {
+            let gauge = IntGauge::new(name, help)?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        let pool_max_size = gauge("pool_max_size", "Maximum number of pooled connections")?;
+        let pool_size = gauge("pool_size", "Number of connections held by the pool")?;
+        let pool_idle = gauge("pool_idle", "Number of idle pooled connections")?;
+        let pool_in_use = gauge("pool_in_use", "Number of checked out connections")?;
+        let pool_waiting = gauge("pool_waiting", "Number of callers waiting for a connection")?;
+        let histogram = |name: &str, help: &str| -> prometheus::Result<Histogram> {
+            let histogram = Histogram::with_opts(HistogramOpts::new(name, help))?;
+            registry.register(Box::new(histogram.clone()))?;
+            Ok(histogram)
+        };
+        let acquire_seconds = histogram(
+            "acquire_duration_seconds",
+            "Time spent waiting for a pooled connection",
+        )?;
+        let query_seconds = histogram("query_duration_seconds", "Time spent executing queries")?;
+        let recycle_failures = IntCounter::new(
+            "recycle_failures_total",
+            "Number of pooled connections that failed to recycle",
+        )?;
+        registry.register(Box::new(recycle_failures.clone()))?;
+        let statement_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "statement_duration_seconds",
+                "Time spent executing statements, by fingerprint",
+            ),
+            &["fingerprint"],
+        )?;
+        registry.register(Box::new(statement_seconds.clone()))?;
+        let statement_errors = IntCounterVec::new(
+            Opts::new(
+                "statement_errors_total",
+                "Number of failed statements, by fingerprint",
+            ),
+            &["fingerprint"],
+        )?;
+        registry.register(Box::new(statement_errors.clone()))?;
+        Ok(Self {
+            registry,
+            pool_max_size,
+            pool_size,
+            pool_idle,
+            pool_in_use,
+            pool_waiting,
+            acquire_seconds,
+            recycle_failures,
+            query_seconds,
+            statement_seconds,
+            statement_errors,
+        })
+    }
+}
+
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    // the metric names are static, so registering them cannot fail
+    METRICS.get_or_init(|| Metrics::new().expect("invalid sqlx_helpers metric definitions"))
+}
+
+/// The registry holding the metrics of this crate, for merging into an
+/// application wide registry
+pub fn registry() -> &'static Registry {
+    &metrics().registry
+}
+
+/// Updates the pool gauges from the given pool and encodes all metrics of this
+/// crate in the Prometheus text format
+///
+/// Returns the encoded metrics or a prometheus error
+/// # Arguments
+/// * `pool` - the pool whose size, idle and in use connections are reported
+pub fn encode(pool: &Pool) -> prometheus::Result<String> {
+    let metrics = metrics();
+    let stats = pool.stats();
+    metrics.pool_max_size.set(stats.max_size as i64);
+    metrics.pool_size.set(stats.size as i64);
+    metrics.pool_idle.set(stats.idle as i64);
+    metrics.pool_in_use.set(stats.in_use as i64);
+    metrics.pool_waiting.set(stats.waiting as i64);
+    TextEncoder::new().encode_to_string(&metrics.registry.gather())
+}
+
+/// Records a query duration in the query histogram
+pub fn observe_query(duration: Duration) {
+    metrics().query_seconds.observe(duration.as_secs_f64());
+}
+
+/// Runs a query future and records its duration in the query histogram
+///
+/// Example usage
+/// ```ignore
+///  let rows = time_query(query_as::<_, MyData>(sql).fetch_all(&mut *conn)).await;
+/// ```
+pub async fn time_query<F: Future>(fut: F) -> F::Output {
+    let started = Instant::now();
+    let output = fut.await;
+    observe_query(started.elapsed());
+    output
+}
+
+pub(crate) fn observe_statement(fingerprint: &str, duration: Duration, succeeded: bool) {
+    let metrics = metrics();
+    metrics
+        .statement_seconds
+        .with_label_values(&[fingerprint])
+        .observe(duration.as_secs_f64());
+    if !succeeded {
+        metrics
+            .statement_errors
+            .with_label_values(&[fingerprint])
+            .inc();
+    }
+}
+
+pub(crate) fn observe_acquire(duration: Duration) {
+    metrics().acquire_seconds.observe(duration.as_secs_f64());
+}
+
+pub(crate) fn inc_recycle_failures() {
+    metrics().recycle_failures.inc();
+}
@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+
+#[cfg(feature = "async-graphql")]
+use async_graphql::{Context, FieldResult, SimpleObject};
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{query_as, query_scalar, Error as SqlxError, PgConnection};
+
+use crate::error::SqlxHelperError;
+use crate::stats::timed_get;
+#[cfg(feature = "async-graphql")]
+use crate::{get_db_connection, match_result};
+use crate::{AdvisoryLock, Pool};
+
+/// The key of the advisory lock held while migrations run
+const MIGRATIONS_LOCK: &str = "sqlx_helpers.migrations";
+
+/// The outcome of [`run_migrations`]
+#[derive(Clone, Debug, Default)]
+pub struct MigrationReport {
+    /// The versions applied by this run, in order
+    pub applied: Vec<i64>,
+    /// The versions that were already applied before this run
+    pub previously_applied: Vec<i64>,
+}
+
+/// Applies the pending migrations of `migrator`, usually built with
+/// `sqlx::migrate!()`, while holding an advisory lock, so replicas starting
+/// at the same time run them one after the other instead of racing: the
+/// first one applies them and the others find nothing left to do.
+///
+/// Returns the applied and previously applied versions, or a
+/// [`SqlxHelperError`]: `Query` when a migration failed to execute, `Config`
+/// when the migrations don't match the database (a changed checksum, a
+/// missing version, a dirty migration), both naming the versions that are
+/// still pending
+/// # Arguments
+/// * `pool` - the pool to get the connection from
+/// * `migrator` - the migrations of the app
+///
+/// Example usage
+/// ```ignore
+///  static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+///
+///  let report = run_migrations(&db_pool, &MIGRATOR).await?;
+///  log::info!("applied migrations {:?}", report.applied);
+/// ```
+pub async fn run_migrations(
+    pool: &Pool,
+    migrator: &Migrator,
+) -> Result<MigrationReport, SqlxHelperError> {
+    let conn = timed_get(pool).await.map_err(SqlxHelperError::from_pool)?;
+    let mut lock = AdvisoryLock::acquire(conn, MIGRATIONS_LOCK)
+        .await
+        .map_err(|e| SqlxHelperError::from_sqlx(e, "Failed to lock the migrations"))?;
+    let previously_applied = applied_versions(&mut lock)
+        .await
+        .map_err(|e| to_helper_error(e, "Failed to list the applied migrations"))?;
+    let res = migrator.run(&mut *lock).await;
+    let now_applied = applied_versions(&mut lock)
+        .await
+        .unwrap_or_else(|_| previously_applied.clone());
+    let applied: Vec<i64> = now_applied
+        .iter()
+        .filter(|version| !previously_applied.contains(version))
+        .copied()
+        .collect();
+    if let Err(e) = res {
+        let pending = pending_versions(migrator, &now_applied);
+        let message = format!(
+            "Migration failed after applying {:?}, pending {:?}",
+            applied, pending
+        );
+        return Err(to_helper_error(e, &message));
+    }
+    // release explicitly so an unlock error surfaces here
+    lock.release()
+        .await
+        .map_err(|e| SqlxHelperError::from_sqlx(e, "Failed to unlock the migrations"))?;
+    Ok(MigrationReport {
+        applied,
+        previously_applied,
+    })
+}
+
+/// The versions recorded in the migrations table, creating the table if it
+/// doesn't exist yet
+async fn applied_versions(conn: &mut PgConnection) -> Result<Vec<i64>, MigrateError> {
+    conn.ensure_migrations_table().await?;
+    let mut versions: Vec<i64> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect();
+    versions.sort_unstable();
+    Ok(versions)
+}
+
+/// The up migrations of `migrator` missing from `applied`
+pub(crate) fn pending_versions(migrator: &Migrator, applied: &[i64]) -> Vec<i64> {
+    let applied: HashSet<i64> = applied.iter().copied().collect();
+    migrator
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect()
+}
+
+fn to_helper_error(err: MigrateError, message: &str) -> SqlxHelperError {
+    match err {
+        MigrateError::Execute(e) => SqlxHelperError::from_sqlx(e, message),
+        other => SqlxHelperError::Config(format!("{}: {}", message, other)),
+    }
+}
+
+/// The state of the schema migrations, ready to be exposed through an admin
+/// field
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "async-graphql", derive(SimpleObject))]
+pub struct MigrationStatus {
+    /// The versions recorded as applied, in order
+    pub applied: Vec<i64>,
+    /// The versions of the migrator that are not applied yet, in order
+    pub pending: Vec<i64>,
+    /// Whether a migration failed halfway and must be fixed by hand
+    pub dirty: bool,
+    /// When the last migration was applied, in ISO 8601
+    pub last_applied_at: Option<String>,
+}
+
+/// Reads the state of the migrations without changing anything, also when
+/// the migrations table doesn't exist yet
+///
+/// Returns the MigrationStatus or the sqlx error of the query
+/// # Arguments
+/// * `conn` - the connection to run the queries on
+/// * `migrator` - the migrations of the app
+pub async fn read_migration_status(
+    conn: &mut PgConnection,
+    migrator: &Migrator,
+) -> Result<MigrationStatus, SqlxError> {
+    let exists = query_scalar::<_, bool>("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(&mut *conn)
+        .await?;
+    let rows: Vec<(i64, bool, String)> = if exists {
+        query_as(
+            "SELECT version, success, to_json(installed_on) #>> '{}' \
+             FROM _sqlx_migrations ORDER BY version",
+        )
+        .fetch_all(&mut *conn)
+        .await?
+    } else {
+        Vec::new()
+    };
+    let applied: Vec<i64> = rows
+        .iter()
+        .filter(|(_, success, _)| *success)
+        .map(|(version, _, _)| *version)
+        .collect();
+    Ok(MigrationStatus {
+        pending: pending_versions(migrator, &applied),
+        dirty: rows.iter().any(|(_, success, _)| !success),
+        last_applied_at: rows
+            .iter()
+            .map(|(_, _, installed_on)| installed_on)
+            .max()
+            .cloned(),
+        applied,
+    })
+}
+
+#[cfg(feature = "async-graphql")]
+/// Reads the state of the migrations with a connection of the Pool stored in
+/// the graphQL context. Like `pool_status`, protect the field with a guard.
+///
+/// Returns the MigrationStatus or a graphQL error
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool object is stored
+/// * `migrator` - the migrations of the app
+///
+/// Example usage
+/// ```ignore
+/// static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+///
+/// #[Object]
+/// impl AdminQuery {
+///     #[graphql(guard = "AdminGuard")]
+///     async fn migration_status(&self, ctx: &Context<'_>) -> FieldResult<MigrationStatus> {
+///         migration_status(ctx, &MIGRATOR).await
+///     }
+/// }
+/// ```
+pub async fn migration_status(
+    ctx: &Context<'_>,
+    migrator: &Migrator,
+) -> FieldResult<MigrationStatus> {
+    let mut conn = get_db_connection(ctx).await?;
+    let status = read_migration_status(&mut conn, migrator).await;
+    match_result(status, "Failed to read the migration status".to_string())
+}
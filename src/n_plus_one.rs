@@ -0,0 +1,296 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextExecute, NextPrepareRequest, NextResolve,
+    ResolveInfo,
+};
+use async_graphql::{Context, Request, Response, ServerResult, Value};
+use async_trait::async_trait;
+
+use crate::fingerprint;
+
+/// Executions of one fingerprint at one field path after which
+/// [`NPlusOneDetection`] reports it by default
+pub const DEFAULT_N_PLUS_ONE_THRESHOLD: usize = 5;
+
+/// A statement run again and again by the same field, typically once per
+/// item of a list: a missing dataloader
+#[derive(Clone, Debug)]
+pub struct RepeatedQuery {
+    /// The fingerprint of the statement, see [`crate::fingerprint`]
+    pub fingerprint: String,
+    /// The field running it, with list indices replaced by `[]`, e.g.
+    /// `users[].posts`
+    pub field_path: String,
+    /// How many times it ran during the request
+    pub count: usize,
+    /// How many list items (distinct field paths) ran it
+    pub siblings: usize,
+}
+
+/// The queries of one GraphQL request, as seen by [`NPlusOneDetection`]
+#[derive(Clone, Debug)]
+pub struct QueryCountReport {
+    /// The name of the operation, if it has one
+    pub operation_name: Option<String>,
+    /// Number of queries recorded during the request
+    pub queries: usize,
+    /// The statements repeated at least the threshold number of times, the
+    /// most repeated first
+    pub repeated: Vec<RepeatedQuery>,
+}
+
+tokio::task_local! {
+    /// The queries of the request being executed, for the helpers recording
+    /// without a graphQL context
+    static REQUEST_QUERIES: RequestQueries;
+    /// The path of the field being resolved
+    static FIELD_PATH: String;
+}
+
+type ReportHandler = dyn Fn(&QueryCountReport) + Send + Sync;
+
+/// The queries recorded for the current request, stored in the request data
+/// by [`NPlusOneDetection`]
+#[derive(Clone, Default)]
+pub struct RequestQueries {
+    /// (fingerprint, field path) of every recorded query
+    recorded: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+/// An opt-in async_graphql extension counting the queries of every request
+/// and reporting statements repeated by the same field, e.g. one query per
+/// item of a list where a [`crate::SqlLoader`] would run one for all of them.
+/// Queries run by the crate's execution helpers are recorded: the Pool's
+/// `DbAccess` methods, [`crate::paginate_offset`], [`crate::fetch_for_update`],
+/// [`crate::fetch_for_share`], [`crate::SlowQueryLog::run`] and
+/// [`crate::StatementMetrics`]; a query wrapped in both of the latter counts
+/// twice. Queries run directly on a connection, including
+/// inside the closures given to `execute_with_retry`, `relay_connection` or
+/// `with_transaction`, are only recorded through one of these helpers or a
+/// call to [`record_query`]. [`crate::SqlLoader`] batches run outside of the
+/// resolvers and are not recorded: they are the fix, not the problem.
+/// Reports go to `tracing` with the `tracing` feature and to the `log` crate
+/// otherwise, unless a custom handler is set.
+///
+/// Meant for development and staging, where the overhead of fingerprinting
+/// every query doesn't matter.
+///
+/// Example usage
+/// ```ignore
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     EmptyMutation::default(), EmptySubscription)
+///     .data(db_pool)
+///     .extension(NPlusOneDetection::new().threshold(3))
+///     .finish();
+/// ```
+#[derive(Clone)]
+pub struct NPlusOneDetection {
+    threshold: usize,
+    handler: Option<Arc<ReportHandler>>,
+}
+
+impl Default for NPlusOneDetection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NPlusOneDetection {
+    /// Reports statements repeated [`DEFAULT_N_PLUS_ONE_THRESHOLD`] times
+    pub fn new() -> Self {
+        Self {
+            threshold: DEFAULT_N_PLUS_ONE_THRESHOLD,
+            handler: None,
+        }
+    }
+
+    /// Sets how many executions of a statement at one field path are reported
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold.max(2);
+        self
+    }
+
+    /// Sends the report of every request to `handler` instead of logging the
+    /// repeated statements, e.g. to fail tests on an N+1
+    pub fn on_report<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&QueryCountReport) + Send + Sync + 'static,
+    {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+}
+
+impl ExtensionFactory for NPlusOneDetection {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(NPlusOneExtension {
+            settings: self.clone(),
+            queries: RequestQueries::default(),
+        })
+    }
+}
+
+struct NPlusOneExtension {
+    settings: NPlusOneDetection,
+    queries: RequestQueries,
+}
+
+#[async_trait]
+impl Extension for NPlusOneExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        next.run(ctx, request.data(self.queries.clone())).await
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let response = REQUEST_QUERIES
+            .scope(self.queries.clone(), next.run(ctx, operation_name))
+            .await;
+        let recorded = std::mem::take(&mut *self.queries.recorded.lock().unwrap());
+        let report = QueryCountReport {
+            operation_name: operation_name.map(str::to_string),
+            queries: recorded.len(),
+            repeated: find_repeated(&recorded, self.settings.threshold),
+        };
+        match &self.settings.handler {
+            Some(handler) => handler(&report),
+            None => log_report(&report),
+        }
+        response
+    }
+
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> ServerResult<Option<Value>> {
+        // the task local is set on every poll, so sibling fields resolved
+        // concurrently on the same task each see their own path
+        FIELD_PATH
+            .scope(info.path_node.to_string(), next.run(ctx, info))
+            .await
+    }
+}
+
+/// Groups the recorded queries by fingerprint and list-agnostic field path
+fn find_repeated(recorded: &[(String, String)], threshold: usize) -> Vec<RepeatedQuery> {
+    let mut groups: HashMap<(&str, String), (usize, HashSet<&str>)> = HashMap::new();
+    for (fingerprint, path) in recorded {
+        let group = groups
+            .entry((fingerprint.as_str(), collapse_indices(path)))
+            .or_default();
+        group.0 += 1;
+        group.1.insert(path.as_str());
+    }
+    let mut repeated: Vec<RepeatedQuery> = groups
+        .into_iter()
+        .filter(|(_, (count, _))| *count >= threshold)
+        .map(
+            |((fingerprint, field_path), (count, paths))| RepeatedQuery {
+                fingerprint: fingerprint.to_string(),
+                field_path,
+                count,
+                siblings: paths.len(),
+            },
+        )
+        .collect();
+    repeated.sort_by(|a, b| b.count.cmp(&a.count));
+    repeated
+}
+
+/// `users.0.posts` to `users[].posts`; GraphQL names can't start with a
+/// digit, so numeric segments are list indices
+fn collapse_indices(path: &str) -> String {
+    let mut collapsed = String::with_capacity(path.len());
+    for segment in path.split('.').filter(|segment| !segment.is_empty()) {
+        if segment.bytes().all(|byte| byte.is_ascii_digit()) {
+            collapsed.push_str("[]");
+        } else {
+            if !collapsed.is_empty() {
+                collapsed.push('.');
+            }
+            collapsed.push_str(segment);
+        }
+    }
+    collapsed
+}
+
+fn log_report(report: &QueryCountReport) {
+    let operation = report.operation_name.as_deref().unwrap_or("anonymous");
+    #[cfg(feature = "tracing")]
+    tracing::debug!(operation, queries = report.queries, "queries per request");
+    for repeated in &report.repeated {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            operation,
+            field_path = %repeated.field_path,
+            fingerprint = %repeated.fingerprint,
+            count = repeated.count,
+            siblings = repeated.siblings,
+            "possible N+1 query, consider a dataloader"
+        );
+        #[cfg(not(feature = "tracing"))]
+        log::warn!(
+            "possible N+1 query in {} at {} ({} times): {}",
+            operation,
+            repeated.field_path,
+            repeated.count,
+            repeated.fingerprint
+        );
+    }
+}
+
+/// Records a query run by the current resolver for [`NPlusOneDetection`].
+/// Queries run through the crate's execution helpers are recorded already;
+/// call this for the ones run directly on a connection. Does nothing when the
+/// extension is not registered.
+///
+/// # Arguments
+/// * `ctx` - graphQL context of the resolver
+/// * `sql` - the query
+///
+/// Example usage
+/// ```ignore
+/// record_query(ctx, sql);
+/// let rows = query_as::<_, MyData>(sql).fetch_all(&mut *conn).await;
+/// ```
+pub fn record_query(ctx: &Context<'_>, sql: &str) {
+    if let Some(queries) = ctx.data_opt::<RequestQueries>() {
+        let path = ctx
+            .path_node
+            .as_ref()
+            .map(|node| node.to_string())
+            .unwrap_or_default();
+        queries
+            .recorded
+            .lock()
+            .unwrap()
+            .push((fingerprint(sql), path));
+    }
+}
+
+/// Records a query run by an execution helper of the crate for the request
+/// and field being resolved on the current task. Does nothing outside of a
+/// request executed with [`NPlusOneDetection`].
+pub(crate) fn record_sql(sql: &str) {
+    let _ = REQUEST_QUERIES.try_with(|queries| {
+        let path = FIELD_PATH.try_with(String::clone).unwrap_or_default();
+        queries
+            .recorded
+            .lock()
+            .unwrap()
+            .push((fingerprint(sql), path));
+    });
+}
@@ -0,0 +1,280 @@
+//! The transactional outbox: events are written to the outbox table in the
+//! transaction of the mutation that produces them, so an event exists if and
+//! only if the mutation committed, and are then delivered to the message
+//! broker from the table.
+//!
+//! Example usage
+//! ```ignore
+//!  let mut tx = ctx.db_tx().await?;
+//!  let res = query("UPDATE orders SET status = 'shipped' WHERE id = $1")
+//!      .bind(order_id)
+//!      .execute(&mut *tx)
+//!      .await;
+//!  match_result(res, "Failed to ship order".to_string())?;
+//!  let res = outbox::publish(&mut tx, "order.shipped", &json!({ "order_id": order_id })).await;
+//!  match_result(res, "Failed to publish order.shipped".to_string())?;
+//!  tx.commit().await?;
+//!
+//!  // once per app, delivers the events to the broker
+//!  let relay = OutboxRelay::start(db_pool.clone(), RelayConfig::new(), |event| Box::pin(async move {
+//!      producer.send(&event.topic, &event.payload).await?;
+//!      Ok(())
+//!  }));
+//! ```
+
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::{query, query_as, query_scalar, Connection, Error as SqlxError, PgConnection};
+use tokio::task::JoinHandle;
+
+use crate::error::pool_error_to_sqlx;
+use crate::stats::timed_get;
+use crate::{LockKey, Pool};
+
+/// The table holding the events
+pub const OUTBOX_TABLE: &str = "outbox";
+
+/// The DDL of the outbox table, to add to the migrations of the app
+pub const OUTBOX_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS outbox (
+    id bigserial PRIMARY KEY,
+    topic text NOT NULL,
+    payload jsonb NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    published_at timestamptz
+);
+CREATE INDEX IF NOT EXISTS outbox_unpublished_idx ON outbox (id) WHERE published_at IS NULL;";
+
+/// Writes an event to the outbox. Call it on the connection of the
+/// transaction of the mutation, so the event is rolled back with it.
+///
+/// Returns the id of the event, or the sqlx error of the insert, an
+/// `Encode` error when `payload` can't be serialized to JSON
+/// # Arguments
+/// * `conn` - the connection of the transaction
+/// * `topic` - the topic, or subject, to deliver the event to
+/// * `payload` - the event, serialized to JSON
+pub async fn publish<P: Serialize>(
+    conn: &mut PgConnection,
+    topic: &str,
+    payload: &P,
+) -> Result<i64, SqlxError> {
+    let payload = serde_json::to_string(payload).map_err(|e| SqlxError::Encode(Box::new(e)))?;
+    let sql = format!(
+        "INSERT INTO {} (topic, payload) VALUES ($1, $2::jsonb) RETURNING id",
+        OUTBOX_TABLE
+    );
+    query_scalar::<_, i64>(&sql)
+        .bind(topic)
+        .bind(payload)
+        .fetch_one(conn)
+        .await
+}
+
+/// An event read from the outbox by the [`OutboxRelay`]
+#[derive(Clone, Debug)]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub topic: String,
+    /// The JSON payload, as text
+    pub payload: String,
+}
+
+impl OutboxEvent {
+    /// Deserializes the payload into `T`
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_str(&self.payload)
+    }
+}
+
+/// The error of a publisher of the [`OutboxRelay`]
+pub type PublishError = Box<dyn StdError + Send + Sync>;
+
+type Publisher =
+    dyn for<'e> Fn(&'e OutboxEvent) -> BoxFuture<'e, Result<(), PublishError>> + Send + Sync;
+
+/// Settings of the [`OutboxRelay`] task
+#[derive(Clone, Debug)]
+pub struct RelayConfig {
+    /// The most events read and marked delivered per transaction
+    pub batch_size: i64,
+    /// The time between two polls when the outbox is empty, or after a
+    /// publish failure
+    pub poll_interval: Duration,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RelayConfig {
+    /// Creates a config reading 100 events at a time, polling every second
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the most events read and marked delivered per transaction
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size as i64;
+        self
+    }
+
+    /// Sets the time between two polls when the outbox is empty
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+}
+
+/// A background task delivering the unpublished events of the outbox, in
+/// the order of their ids, to a publisher supplied by the app, e.g. a Kafka
+/// or SNS producer, and marking them delivered.
+///
+/// Ids are taken when the events are inserted, not when their transactions
+/// commit: an event of a long transaction can commit after events with
+/// greater ids were delivered, and is delivered after them on a later poll.
+/// Events that must keep their order, e.g. those of one aggregate, should be
+/// written by transactions serialized on a lock of that aggregate, such as
+/// `SELECT ... FOR UPDATE` on its row.
+///
+/// Delivery is at least once: an event is marked delivered after the
+/// publisher returned, in the transaction that read it, so an event is
+/// published again when the relay dies or fails in between; consumers must
+/// be idempotent. When the publisher fails, the events before the failed one
+/// are marked delivered and the failed one is retried on the next poll, so
+/// no event overtakes a failed one. An advisory lock makes sure only one
+/// relay of the fleet delivers at a time.
+///
+/// The task stops when the value is dropped or the pool is closed.
+pub struct OutboxRelay {
+    task: JoinHandle<()>,
+}
+
+impl OutboxRelay {
+    /// Starts the relay on a background task
+    ///
+    /// # Arguments
+    /// * `pool` - the pool to read the outbox with
+    /// * `config` - the batch size and the poll interval
+    /// * `publisher` - a closure delivering one event to the broker
+    pub fn start<F>(pool: Pool, config: RelayConfig, publisher: F) -> Self
+    where
+        F: for<'e> Fn(&'e OutboxEvent) -> BoxFuture<'e, Result<(), PublishError>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            task: tokio::spawn(run_relay(pool, config, Arc::new(publisher))),
+        }
+    }
+
+    /// Stops the relay
+    pub fn shutdown(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for OutboxRelay {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run_relay(pool: Pool, config: RelayConfig, publisher: Arc<Publisher>) {
+    loop {
+        if pool.is_closed() || pool.manager().is_draining() {
+            return;
+        }
+        let delivered = match relay_batch(&pool, &config, publisher.as_ref()).await {
+            Ok(delivered) => delivered,
+            Err(e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    error = %crate::error::redact(&e.to_string()),
+                    "failed to relay outbox events"
+                );
+                #[cfg(not(feature = "tracing"))]
+                log::warn!(
+                    "failed to relay outbox events: {}",
+                    crate::error::redact(&e.to_string())
+                );
+                None
+            }
+        };
+        // keep going while full batches are delivered
+        if delivered != Some(config.batch_size) {
+            tokio::time::sleep(config.poll_interval).await;
+        }
+    }
+}
+
+/// Delivers one batch of events, returning the number of delivered events,
+/// or None when the batch was cut short or another relay holds the lock
+async fn relay_batch(
+    pool: &Pool,
+    config: &RelayConfig,
+    publisher: &Publisher,
+) -> Result<Option<i64>, SqlxError> {
+    let mut conn = timed_get(pool).await.map_err(pool_error_to_sqlx)?;
+    let mut tx = conn.begin().await?;
+    let locked = query_scalar::<_, bool>("SELECT pg_try_advisory_xact_lock($1)")
+        .bind(LockKey::from("sqlx_helpers.outbox_relay").0)
+        .fetch_one(&mut *tx)
+        .await?;
+    if !locked {
+        return Ok(None);
+    }
+    // insert order, not commit order, see OutboxRelay
+    let sql = format!(
+        "SELECT id, topic, payload::text FROM {} WHERE published_at IS NULL ORDER BY id LIMIT $1",
+        OUTBOX_TABLE
+    );
+    let events = query_as::<_, (i64, String, String)>(&sql)
+        .bind(config.batch_size)
+        .fetch_all(&mut *tx)
+        .await?;
+    let read = events.len();
+    let mut delivered = Vec::with_capacity(read);
+    for (id, topic, payload) in events {
+        let event = OutboxEvent { id, topic, payload };
+        if let Err(e) = publisher(&event).await {
+            let error = crate::error::redact(&e.to_string());
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                event = id,
+                topic = %event.topic,
+                error = %error,
+                "failed to publish outbox event"
+            );
+            #[cfg(not(feature = "tracing"))]
+            log::warn!(
+                "failed to publish outbox event {} to {}: {}",
+                id,
+                event.topic,
+                error
+            );
+            break;
+        }
+        delivered.push(id);
+    }
+    let sql = format!(
+        "UPDATE {} SET published_at = now() WHERE id = ANY($1)",
+        OUTBOX_TABLE
+    );
+    query(&sql).bind(&delivered).execute(&mut *tx).await?;
+    tx.commit().await?;
+    if delivered.len() < read {
+        return Ok(None);
+    }
+    Ok(Some(read as i64))
+}
@@ -0,0 +1,594 @@
+use async_graphql::connection::{query, Connection, CursorType, Edge};
+use async_graphql::{Context, FieldError, FieldResult, OutputType, SimpleObject};
+use futures::future::BoxFuture;
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::{Error as SqlxError, FromRow, PgConnection};
+
+use crate::{error, get_db_connection, match_result, to_arguments, SqlValue};
+
+/// Page size used when neither `first` nor `last` is given
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+
+/// Upper bound for `first` and `last`
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// The keyset page [`relay_connection`] asks the query function for
+#[derive(Clone, Debug)]
+pub struct PageRequest<C> {
+    /// Only rows after this cursor are wanted
+    pub after: Option<C>,
+    /// Only rows before this cursor are wanted
+    pub before: Option<C>,
+    /// Maximum number of rows to return; one more than the page size so the
+    /// helper can tell whether another page exists
+    pub limit: usize,
+    /// True when paginating with `last`; the query must then order by the
+    /// sort key descending (the helper restores ascending order)
+    pub backward: bool,
+}
+
+/// Runs a keyset query on a pooled connection and builds a Relay connection
+/// from the `first`/`after`/`last`/`before` arguments. `hasNextPage` and
+/// `hasPreviousPage` are derived from fetching one row more than requested.
+///
+/// Returns the Connection or a graphQL error
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool object is stored
+/// * `after`, `before`, `first`, `last` - the Relay pagination arguments
+/// * `cursor_of` - computes the cursor of a node
+/// * `fetch` - runs the keyset query for the given PageRequest
+///
+/// Example usage
+/// ```ignore
+/// async fn posts(&self, ctx: &Context<'_>, after: Option<String>, before: Option<String>,
+///     first: Option<i32>, last: Option<i32>) -> FieldResult<Connection<i64, Post>> {
+///     relay_connection(ctx, after, before, first, last, |post: &Post| post.id, |conn, page| {
+///         Box::pin(async move {
+///             let order = if page.backward { "DESC" } else { "ASC" };
+///             let sql = format!(
+///                 "SELECT * FROM posts WHERE ($1::bigint IS NULL OR id > $1)
+///                  AND ($2::bigint IS NULL OR id < $2) ORDER BY id {} LIMIT $3",
+///                 order
+///             );
+///             query_as::<_, Post>(&sql)
+///                 .bind(page.after)
+///                 .bind(page.before)
+///                 .bind(page.limit as i64)
+///                 .fetch_all(conn)
+///                 .await
+///         })
+///     })
+///     .await
+/// }
+/// ```
+pub async fn relay_connection<C, N, K, F>(
+    ctx: &Context<'_>,
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<i32>,
+    last: Option<i32>,
+    cursor_of: K,
+    fetch: F,
+) -> FieldResult<Connection<C, N>>
+where
+    C: CursorType + Send + Sync,
+    N: OutputType,
+    K: Fn(&N) -> C,
+    F: for<'c> FnOnce(
+        &'c mut PgConnection,
+        PageRequest<C>,
+    ) -> BoxFuture<'c, Result<Vec<N>, SqlxError>>,
+{
+    query(
+        after,
+        before,
+        first,
+        last,
+        |after, before, first, last| async move {
+            let backward = first.is_none() && last.is_some();
+            let size = if backward { last } else { first }
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .min(MAX_PAGE_SIZE);
+            let has_cursor_behind = if backward {
+                before.is_some()
+            } else {
+                after.is_some()
+            };
+            let page = PageRequest {
+                after,
+                before,
+                limit: size + 1,
+                backward,
+            };
+            let mut conn = get_db_connection(ctx).await?;
+            let mut nodes = match_result(
+                fetch(&mut *conn, page).await,
+                "Failed to fetch page".to_string(),
+            )?;
+            let has_more = nodes.len() > size;
+            nodes.truncate(size);
+            if backward {
+                nodes.reverse();
+            }
+            let (has_previous_page, has_next_page) = if backward {
+                (has_more, has_cursor_behind)
+            } else {
+                (has_cursor_behind, has_more)
+            };
+            let mut connection = Connection::new(has_previous_page, has_next_page);
+            connection.edges.extend(
+                nodes
+                    .into_iter()
+                    .map(|node| Edge::new(cursor_of(&node), node)),
+            );
+            Ok::<_, FieldError>(connection)
+        },
+    )
+    .await
+}
+
+/// Page metadata of an offset paginated list
+#[derive(Clone, Debug, SimpleObject)]
+pub struct OffsetPageInfo {
+    /// The current page, starting at 1
+    pub page: usize,
+    /// Maximum number of items per page
+    pub per_page: usize,
+    /// Number of items across all pages
+    pub total: i64,
+    /// Number of pages
+    pub total_pages: i64,
+    pub has_next_page: bool,
+    pub has_previous_page: bool,
+}
+
+/// The items of one page and its metadata. Wrap it in your own SimpleObject
+/// to expose it, e.g. `struct PostPage { items: Vec<Post>, page_info: OffsetPageInfo }`.
+#[derive(Clone, Debug)]
+pub struct OffsetPage<T> {
+    pub items: Vec<T>,
+    pub page_info: OffsetPageInfo,
+}
+
+/// Runs a data query limited to one page and a count query over the same SQL
+/// on one connection.
+///
+/// Returns the page or a graphQL error
+/// # Arguments
+/// * `conn` - the connection both queries run on
+/// * `sql` - the query without LIMIT/OFFSET; it must not end with a semicolon
+/// * `bind` - adds the bind values of `sql`; called once per query
+/// * `page` - the page to fetch, starting at 1
+/// * `per_page` - the page size, capped at [`MAX_PAGE_SIZE`]
+///
+/// Example usage
+/// ```ignore
+/// let mut conn = get_db_connection(ctx).await?;
+/// let page = paginate_offset::<Post, _>(
+///     &mut conn,
+///     "SELECT * FROM posts WHERE author_id = $1 ORDER BY created_at DESC",
+///     |args| args.add(author_id),
+///     page,
+///     per_page,
+/// )
+/// .await?;
+/// ```
+pub async fn paginate_offset<T, B>(
+    conn: &mut PgConnection,
+    sql: &str,
+    bind: B,
+    page: usize,
+    per_page: usize,
+) -> FieldResult<OffsetPage<T>>
+where
+    T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    B: Fn(&mut PgArguments),
+{
+    let page = page.max(1);
+    let per_page = per_page.clamp(1, MAX_PAGE_SIZE);
+    let arguments = || {
+        let mut args = PgArguments::default();
+        bind(&mut args);
+        args
+    };
+
+    let count_sql = format!("SELECT COUNT(*) FROM ({}) AS counted", sql);
+    crate::n_plus_one::record_sql(&count_sql);
+    let total = match_result(
+        sqlx::query_scalar_with::<_, i64, _>(&count_sql, arguments())
+            .fetch_one(&mut *conn)
+            .await,
+        "Failed to count items".to_string(),
+    )?;
+
+    let offset = (page - 1) * per_page;
+    let data_sql = format!("{} LIMIT {} OFFSET {}", sql, per_page, offset);
+    crate::n_plus_one::record_sql(&data_sql);
+    let items = match_result(
+        sqlx::query_as_with::<_, T, _>(&data_sql, arguments())
+            .fetch_all(&mut *conn)
+            .await,
+        "Failed to fetch page".to_string(),
+    )?;
+
+    let total_pages = (total + per_page as i64 - 1) / per_page as i64;
+    Ok(OffsetPage {
+        items,
+        page_info: OffsetPageInfo {
+            page,
+            per_page,
+            total,
+            total_pages,
+            has_next_page: (page as i64) < total_pages,
+            has_previous_page: page > 1,
+        },
+    })
+}
+
+/// Extension code returned when a decoded cursor does not match the sort keys
+pub const INVALID_CURSOR: &str = "INVALID_CURSOR";
+
+/// Sort direction of a keyset column or an [`crate::OrderBy`] term. It is a
+/// GraphQL enum, so it can be used as is in sort arguments.
+#[derive(async_graphql::Enum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    fn reversed(self) -> Self {
+        match self {
+            SortDirection::Asc => SortDirection::Desc,
+            SortDirection::Desc => SortDirection::Asc,
+        }
+    }
+
+    pub(crate) fn keyword(self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+
+    fn operator(self) -> &'static str {
+        match self {
+            SortDirection::Asc => ">",
+            SortDirection::Desc => "<",
+        }
+    }
+}
+
+/// Builds the WHERE/ORDER BY/LIMIT fragment of a keyset paginated query for
+/// one or more sort columns. Uniform directions produce a row comparison,
+/// e.g. `(a, b) > ($1, $2)`; mixed directions are expanded into the
+/// equivalent OR chain.
+///
+/// Write a column as `name::type`, e.g. `"created_at::timestamptz"`, to cast
+/// its cursor placeholder, which is needed when the cursor value is sent as
+/// text for a column of another type, such as timestamps or uuids. Column
+/// names are written into the SQL as given, so never pass user input as a
+/// column name.
+///
+/// Example usage
+/// ```ignore
+/// let keyset = KeysetBuilder::new()
+///     .column("created_at::timestamptz", SortDirection::Desc)
+///     .column("id", SortDirection::Desc)
+///     .cursor(vec![cursor.created_at.into(), cursor.id.into()])
+///     .limit(21)
+///     .first_param(2)
+///     .build()?;
+/// let sql = format!("SELECT * FROM posts WHERE author_id = $1 {}", keyset.to_sql_and());
+/// let mut args = PgArguments::default();
+/// args.add(author_id);
+/// for value in &keyset.binds {
+///     value.add_to(&mut args);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct KeysetBuilder {
+    columns: Vec<KeysetColumn>,
+    cursor: Option<Vec<SqlValue>>,
+    limit: Option<usize>,
+    first_param: usize,
+    backward: bool,
+}
+
+impl Default for KeysetBuilder {
+    fn default() -> Self {
+        Self {
+            columns: Vec::new(),
+            cursor: None,
+            limit: None,
+            first_param: 1,
+            backward: false,
+        }
+    }
+}
+
+/// A sort column of a [`KeysetBuilder`], with the cast of its placeholder
+#[derive(Clone, Debug)]
+struct KeysetColumn {
+    name: String,
+    cast: Option<String>,
+    direction: SortDirection,
+}
+
+impl KeysetColumn {
+    fn placeholder(&self, param: usize) -> String {
+        match &self.cast {
+            Some(cast) => format!("${}::{}", param, cast),
+            None => format!("${}", param),
+        }
+    }
+}
+
+/// The fragments produced by [`KeysetBuilder::build`]
+#[derive(Clone, Debug)]
+pub struct KeysetQuery {
+    /// The keyset condition without `WHERE`, absent on the first page
+    pub condition: Option<String>,
+    /// The ORDER BY clause without `ORDER BY`
+    pub order_by: String,
+    pub limit: Option<usize>,
+    /// The cursor values, in placeholder order
+    pub binds: Vec<SqlValue>,
+}
+
+impl KeysetBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a sort column, optionally as `name::type`; columns are compared
+    /// in the order they are added
+    pub fn column(mut self, column: &str, direction: SortDirection) -> Self {
+        let (name, cast) = match column.split_once("::") {
+            Some((name, cast)) => (name, Some(cast.to_string())),
+            None => (column, None),
+        };
+        self.columns.push(KeysetColumn {
+            name: name.to_string(),
+            cast,
+            direction,
+        });
+        self
+    }
+
+    /// Sets the decoded cursor, one value per sort column
+    pub fn cursor(mut self, values: Vec<SqlValue>) -> Self {
+        self.cursor = Some(values);
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Sets the number of the first placeholder, for queries that already
+    /// use `$1..$n` for other binds
+    pub fn first_param(mut self, first_param: usize) -> Self {
+        self.first_param = first_param;
+        self
+    }
+
+    /// Pages before the cursor instead of after it by flipping every
+    /// comparison and sort direction
+    pub fn backward(mut self, backward: bool) -> Self {
+        self.backward = backward;
+        self
+    }
+
+    /// Returns the fragments, a graphQL error with the `INVALID_CURSOR` code
+    /// when the cursor does not have one value per sort column, or a graphQL
+    /// error when there is no sort column
+    pub fn build(self) -> FieldResult<KeysetQuery> {
+        if self.columns.is_empty() {
+            return Err(FieldError::new(
+                "Keyset pagination needs at least one sort column",
+            ));
+        }
+        let backward = self.backward;
+        let columns: Vec<KeysetColumn> = self
+            .columns
+            .into_iter()
+            .map(|column| KeysetColumn {
+                direction: if backward {
+                    column.direction.reversed()
+                } else {
+                    column.direction
+                },
+                ..column
+            })
+            .collect();
+        let order_by = columns
+            .iter()
+            .map(|column| format!("{} {}", column.name, column.direction.keyword()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let binds = self.cursor.unwrap_or_default();
+        let condition = if binds.is_empty() {
+            None
+        } else if binds.len() != columns.len() {
+            return Err(error::coded_error(
+                format!(
+                    "Cursor has {} values but the query sorts by {} columns",
+                    binds.len(),
+                    columns.len()
+                ),
+                INVALID_CURSOR,
+            ));
+        } else {
+            Some(keyset_condition(&columns, self.first_param))
+        };
+        Ok(KeysetQuery {
+            condition,
+            order_by,
+            limit: self.limit,
+            binds,
+        })
+    }
+}
+
+fn keyset_condition(columns: &[KeysetColumn], first_param: usize) -> String {
+    let param = |i: usize| columns[i].placeholder(first_param + i);
+    let uniform = columns
+        .windows(2)
+        .all(|pair| pair[0].direction == pair[1].direction);
+    if uniform {
+        let names: Vec<&str> = columns.iter().map(|column| column.name.as_str()).collect();
+        let params: Vec<String> = (0..columns.len()).map(param).collect();
+        return format!(
+            "({}) {} ({})",
+            names.join(", "),
+            columns[0].direction.operator(),
+            params.join(", ")
+        );
+    }
+    // (a > $1) OR (a = $1 AND b < $2) OR ...
+    let branches: Vec<String> = (0..columns.len())
+        .map(|i| {
+            let mut terms: Vec<String> = columns[..i]
+                .iter()
+                .enumerate()
+                .map(|(j, column)| format!("{} = {}", column.name, param(j)))
+                .collect();
+            let column = &columns[i];
+            terms.push(format!(
+                "{} {} {}",
+                column.name,
+                column.direction.operator(),
+                param(i)
+            ));
+            format!("({})", terms.join(" AND "))
+        })
+        .collect();
+    format!("({})", branches.join(" OR "))
+}
+
+impl KeysetQuery {
+    fn clauses(&self) -> String {
+        let mut sql = format!("ORDER BY {}", self.order_by);
+        if let Some(limit) = self.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+        sql
+    }
+
+    /// `WHERE <condition> ORDER BY ... LIMIT n`, for queries without a WHERE
+    pub fn to_sql(&self) -> String {
+        match &self.condition {
+            Some(condition) => format!("WHERE {} {}", condition, self.clauses()),
+            None => self.clauses(),
+        }
+    }
+
+    /// `AND <condition> ORDER BY ... LIMIT n`, for queries with a WHERE
+    pub fn to_sql_and(&self) -> String {
+        match &self.condition {
+            Some(condition) => format!("AND {} {}", condition, self.clauses()),
+            None => self.clauses(),
+        }
+    }
+
+    /// The cursor values as sqlx arguments, for queries without other binds
+    pub fn arguments(&self) -> PgArguments {
+        to_arguments(&self.binds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_page_has_no_condition() {
+        let keyset = KeysetBuilder::new()
+            .column("id", SortDirection::Asc)
+            .limit(11)
+            .build()
+            .unwrap();
+        assert_eq!(keyset.condition, None);
+        assert_eq!(keyset.to_sql(), "ORDER BY id ASC LIMIT 11");
+        assert_eq!(keyset.to_sql_and(), "ORDER BY id ASC LIMIT 11");
+    }
+
+    #[test]
+    fn uniform_directions_compare_rows() {
+        let keyset = KeysetBuilder::new()
+            .column("created_at::timestamptz", SortDirection::Desc)
+            .column("id", SortDirection::Desc)
+            .cursor(vec!["2024-01-01T00:00:00Z".into(), 42i64.into()])
+            .limit(21)
+            .first_param(2)
+            .build()
+            .unwrap();
+        assert_eq!(
+            keyset.to_sql_and(),
+            "AND (created_at, id) < ($2::timestamptz, $3) \
+             ORDER BY created_at DESC, id DESC LIMIT 21"
+        );
+        assert_eq!(
+            keyset.binds,
+            vec![
+                SqlValue::Text("2024-01-01T00:00:00Z".to_string()),
+                SqlValue::Int(42)
+            ]
+        );
+    }
+
+    #[test]
+    fn mixed_directions_expand_into_an_or_chain() {
+        let keyset = KeysetBuilder::new()
+            .column("score::numeric", SortDirection::Desc)
+            .column("name", SortDirection::Asc)
+            .column("id", SortDirection::Asc)
+            .cursor(vec!["9.5".into(), "Ada".into(), 3i64.into()])
+            .build()
+            .unwrap();
+        assert_eq!(
+            keyset.condition.as_deref(),
+            Some(
+                "((score < $1::numeric) OR (score = $1::numeric AND name > $2) \
+                 OR (score = $1::numeric AND name = $2 AND id > $3))"
+            )
+        );
+        assert_eq!(keyset.order_by, "score DESC, name ASC, id ASC");
+    }
+
+    #[test]
+    fn backward_flips_comparisons_and_sort_directions() {
+        let keyset = KeysetBuilder::new()
+            .column("id", SortDirection::Asc)
+            .cursor(vec![7i64.into()])
+            .backward(true)
+            .build()
+            .unwrap();
+        assert_eq!(keyset.to_sql(), "WHERE (id) < ($1) ORDER BY id DESC");
+    }
+
+    #[test]
+    fn requires_a_sort_column() {
+        let err = KeysetBuilder::new().limit(10).build().unwrap_err();
+        assert_eq!(
+            err.message,
+            "Keyset pagination needs at least one sort column"
+        );
+    }
+
+    #[test]
+    fn cursor_must_match_sort_columns() {
+        let err = KeysetBuilder::new()
+            .column("created_at", SortDirection::Desc)
+            .column("id", SortDirection::Desc)
+            .cursor(vec![42i64.into()])
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.message,
+            "Cursor has 1 values but the query sorts by 2 columns"
+        );
+    }
+}
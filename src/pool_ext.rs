@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use deadpool::managed::{Object, PoolError, TimeoutType, Timeouts};
+use futures::future::join_all;
+use sqlx::{Connection, Error as SqlxError};
+
+use crate::error::pool_error_to_sqlx;
+use crate::stats::{pool_stats, timed_get};
+use crate::{Pool, PoolStats, TrackedConnection};
+
+/// How often [`PoolExt::drain`] looks for returned connections
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Additional operations on a [`Pool`]
+///
+/// Example usage
+/// ```ignore
+///  let stats = db_pool.stats();
+///  println!("{} of {} connections in use", stats.in_use, stats.max_size);
+/// ```
+#[async_trait]
+pub trait PoolExt {
+    /// Takes a snapshot of the pool's size, idle and in use connections and
+    /// recent acquire wait times
+    fn stats(&self) -> PoolStats;
+
+    /// Shuts the pool down gracefully: the helpers of this crate stop handing
+    /// out connections and no new ones are created, connections in use are
+    /// waited for until `timeout` and every connection is closed with a
+    /// proper terminate message. Finally the pool is closed, so remaining
+    /// connections are dropped when they are returned.
+    ///
+    /// Returns the number of connections still in use at the deadline, 0 if
+    /// the pool drained completely
+    /// # Arguments
+    /// * `timeout` - how long to wait for connections in use
+    ///
+    /// Example usage
+    /// ```ignore
+    ///  // after the server stopped accepting requests
+    ///  let leftover = db_pool.drain(Duration::from_secs(30)).await;
+    ///  if leftover > 0 {
+    ///      log::warn!("{} connections were still in use", leftover);
+    ///  }
+    /// ```
+    async fn drain(&self, timeout: Duration) -> usize;
+
+    /// Opens up to `n` connections concurrently (at most the free slots of
+    /// the pool) and returns them to the pool idle, so the first requests
+    /// after a deploy don't pay for connection setup. It never waits for a
+    /// connection in use: slots taken by other callers in the meantime are
+    /// skipped.
+    ///
+    /// Returns the number of idle connections afterwards, or the first sqlx
+    /// error, e.g. to fail a readiness check
+    /// # Arguments
+    /// * `n` - the number of connections to open
+    ///
+    /// Example usage
+    /// ```ignore
+    ///  let db_pool = PoolConfig::new().max_size(32).build(mgr)?;
+    ///  db_pool.warm_up(8).await?;
+    /// ```
+    async fn warm_up(&self, n: usize) -> Result<usize, SqlxError>;
+
+    /// Gets a connection that reports being held past the leak threshold of
+    /// the manager (see [`crate::LeakDetection`])
+    ///
+    /// Returns the tracked connection or the sqlx error of the acquisition
+    /// # Arguments
+    /// * `label` - names the acquirer in the warnings, e.g. the job name
+    async fn get_tracked(&self, label: &str) -> Result<TrackedConnection, SqlxError>;
+
+    /// Changes the maximum number of connections while the pool is in use.
+    /// Growing takes effect immediately; when shrinking, idle connections
+    /// over the new size are closed right away and connections in use are
+    /// closed when they are returned.
+    ///
+    /// Returns the pool statistics after the resize
+    /// # Arguments
+    /// * `max_size` - the new maximum number of connections, at least 1
+    fn resize_to(&self, max_size: usize) -> PoolStats;
+}
+
+#[async_trait]
+impl PoolExt for Pool {
+    fn stats(&self) -> PoolStats {
+        pool_stats(self)
+    }
+
+    async fn drain(&self, timeout: Duration) -> usize {
+        self.manager().start_draining();
+        let deadline = Instant::now() + timeout;
+        let no_wait = Timeouts {
+            wait: Some(Duration::ZERO),
+            ..self.timeouts()
+        };
+        while self.status().size > 0 && Instant::now() < deadline {
+            match self.timeout_get(&no_wait).await {
+                Ok(conn) => {
+                    let _ = Object::take(conn).close().await;
+                }
+                Err(_) => tokio::time::sleep(DRAIN_POLL_INTERVAL).await,
+            }
+        }
+        let remaining = self.status().size;
+        self.close();
+        remaining
+    }
+
+    async fn warm_up(&self, n: usize) -> Result<usize, SqlxError> {
+        let status = self.status();
+        let in_use = status.size.saturating_sub(status.available);
+        let n = n.min(status.max_size.saturating_sub(in_use));
+        let no_wait = Timeouts {
+            wait: Some(Duration::ZERO),
+            ..self.timeouts()
+        };
+        let results = join_all((0..n).map(|_| self.timeout_get(&no_wait))).await;
+        let mut held = Vec::with_capacity(n);
+        for res in results {
+            match res {
+                Ok(conn) => held.push(conn),
+                Err(PoolError::Timeout(TimeoutType::Wait)) => {}
+                Err(e) => return Err(pool_error_to_sqlx(e)),
+            }
+        }
+        drop(held);
+        Ok(self.status().available)
+    }
+
+    async fn get_tracked(&self, label: &str) -> Result<TrackedConnection, SqlxError> {
+        let conn = timed_get(self).await.map_err(pool_error_to_sqlx)?;
+        Ok(TrackedConnection::new(conn, self, label))
+    }
+
+    fn resize_to(&self, max_size: usize) -> PoolStats {
+        let max_size = max_size.max(1);
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            from = self.status().max_size,
+            to = max_size,
+            "resizing pool"
+        );
+        self.resize(max_size);
+        pool_stats(self)
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt::Write;
+#[cfg(feature = "async-graphql")]
+use std::sync::Arc;
+
+#[cfg(feature = "async-graphql")]
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextPrepareRequest,
+};
+#[cfg(feature = "async-graphql")]
+use async_graphql::{Context, Request, ServerResult};
+#[cfg(feature = "async-graphql")]
+use async_trait::async_trait;
+
+#[cfg(feature = "async-graphql")]
+type TraceparentFn = dyn Fn() -> Option<String> + Send + Sync;
+
+#[cfg(feature = "async-graphql")]
+/// An opt-in async_graphql extension that makes [`tag_sql`] append a
+/// sqlcommenter style comment with the trace context, GraphQL operation name
+/// and field path to queries, so pg logs can be correlated with traces.
+///
+/// Example usage
+/// ```ignore
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     EmptyMutation::default(), EmptySubscription)
+///     .data(db_pool)
+///     .extension(QueryTagging::new().traceparent(current_traceparent))
+///     .finish();
+/// ```
+#[derive(Clone, Default)]
+pub struct QueryTagging {
+    traceparent: Option<Arc<TraceparentFn>>,
+}
+
+#[cfg(feature = "async-graphql")]
+impl QueryTagging {
+    /// Tags queries with the operation name and field path only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a callback returning the W3C `traceparent` of the current trace,
+    /// called once per request
+    pub fn traceparent<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> Option<String> + Send + Sync + 'static,
+    {
+        self.traceparent = Some(Arc::new(f));
+        self
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// The tags of the current request, stored in the request data by
+/// [`QueryTagging`]
+#[derive(Clone, Debug, Default)]
+pub struct RequestTags {
+    pub traceparent: Option<String>,
+    pub operation_name: Option<String>,
+}
+
+#[cfg(feature = "async-graphql")]
+impl ExtensionFactory for QueryTagging {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(QueryTaggingExtension {
+            traceparent: self.traceparent.clone(),
+        })
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+struct QueryTaggingExtension {
+    traceparent: Option<Arc<TraceparentFn>>,
+}
+
+#[cfg(feature = "async-graphql")]
+#[async_trait]
+impl Extension for QueryTaggingExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        let tags = RequestTags {
+            traceparent: self.traceparent.as_ref().and_then(|f| f()),
+            operation_name: request.operation_name.clone(),
+        };
+        next.run(ctx, request.data(tags)).await
+    }
+}
+
+/// Percent-encodes a sqlcommenter value; only unreserved characters are kept
+/// so the value can't terminate the quote or the comment
+pub(crate) fn encode_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
+
+/// Builds the sqlcommenter comment for the given key/value pairs, with the
+/// keys in sorted order as the spec requires
+pub fn sql_comment(tags: &[(&str, &str)]) -> String {
+    let mut tags = tags.to_vec();
+    tags.sort_by_key(|(key, _)| *key);
+    let pairs: Vec<String> = tags
+        .iter()
+        .map(|(key, value)| format!("{}='{}'", encode_value(key), encode_value(value)))
+        .collect();
+    format!("/*{}*/", pairs.join(","))
+}
+
+#[cfg(feature = "async-graphql")]
+/// Appends a sqlcommenter comment with the `traceparent`, `graphql_operation`
+/// and `graphql_path` of the current resolver to `sql`. Returns `sql`
+/// unchanged when the [`QueryTagging`] extension is not registered.
+///
+/// # Arguments
+/// * `ctx` - graphQL context of the resolver
+/// * `sql` - the query to tag
+///
+/// Example usage
+/// ```ignore
+/// let sql = tag_sql(ctx, "SELECT * FROM my_data");
+/// let rows = query_as::<_, MyData>(&sql).fetch_all(&mut *conn).await;
+/// ```
+pub fn tag_sql(ctx: &Context<'_>, sql: &str) -> String {
+    let request_tags = match ctx.data_opt::<RequestTags>() {
+        Some(tags) => tags,
+        None => return sql.to_string(),
+    };
+    let path = ctx.path_node.as_ref().map(|node| node.to_string());
+    let mut tags = Vec::new();
+    if let Some(traceparent) = &request_tags.traceparent {
+        tags.push(("traceparent", traceparent.as_str()));
+    }
+    if let Some(operation_name) = &request_tags.operation_name {
+        tags.push(("graphql_operation", operation_name.as_str()));
+    }
+    if let Some(path) = &path {
+        tags.push(("graphql_path", path.as_str()));
+    }
+    if tags.is_empty() {
+        return sql.to_string();
+    }
+    // the comment goes before a trailing semicolon to stay part of the statement
+    let sql = sql.trim_end();
+    match sql.strip_suffix(';') {
+        Some(body) => format!("{} {};", body, sql_comment(&tags)),
+        None => format!("{} {}", sql, sql_comment(&tags)),
+    }
+}
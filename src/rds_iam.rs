@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use async_trait::async_trait;
+use aws_config::BehaviorVersion;
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use aws_sigv4::http_request::{
+    sign, SignableBody, SignableRequest, SignatureLocation, SigningSettings,
+};
+use aws_sigv4::sign::v4;
+use sqlx::Error as SqlxError;
+use tokio::sync::Mutex;
+
+use crate::query_tags::encode_value;
+use crate::{Credentials, CredentialsProvider};
+
+/// How long RDS accepts an IAM auth token
+const TOKEN_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+/// How long a generated token is reused, leaving a margin before it expires
+const TOKEN_REFRESH_AFTER: Duration = Duration::from_secs(10 * 60);
+
+fn config_error<E: std::fmt::Display>(err: E) -> SqlxError {
+    SqlxError::Configuration(err.to_string().into())
+}
+
+/// A [`CredentialsProvider`] that logs in to RDS or Aurora with IAM
+/// database authentication instead of a static password. Auth tokens are
+/// signed with the AWS credentials of the process and regenerated before
+/// they expire. RDS only accepts IAM tokens over TLS, so combine it with
+/// [`crate::TlsConfig`].
+///
+/// Example usage
+/// ```ignore
+///  let endpoint = "orders.cluster-abc.eu-west-1.rds.amazonaws.com";
+///  let rds = RdsIamProvider::from_env(endpoint, 5432, "app_user", "eu-west-1").await?;
+///  let mgr = PoolManager::from_options(PgConnectOptions::new().host(endpoint).database("orders"))
+///     .with_tls(TlsConfig::verify_full(RootCert::Path("/etc/ssl/rds-ca.pem".into())))
+///     .credentials_provider(rds);
+/// ```
+pub struct RdsIamProvider {
+    host: String,
+    port: u16,
+    user: String,
+    region: String,
+    aws_credentials: SharedCredentialsProvider,
+    cached: Mutex<Option<(String, Instant)>>,
+}
+
+impl RdsIamProvider {
+    /// Creates a provider signing tokens with the given AWS credentials
+    ///
+    /// # Arguments
+    /// * `host` - the instance or cluster endpoint
+    /// * `port` - the database port
+    /// * `user` - the database user granted `rds_iam`
+    /// * `region` - the AWS region of the instance
+    /// * `aws_credentials` - the AWS credentials used for signing
+    pub fn new(
+        host: &str,
+        port: u16,
+        user: &str,
+        region: &str,
+        aws_credentials: SharedCredentialsProvider,
+    ) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            user: user.to_string(),
+            region: region.to_string(),
+            aws_credentials,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Creates a provider using the AWS credentials of the environment (env
+    /// variables, profile, instance or task role)
+    ///
+    /// Returns the provider or a sqlx Configuration error if the environment
+    /// has no AWS credentials
+    pub async fn from_env(
+        host: &str,
+        port: u16,
+        user: &str,
+        region: &str,
+    ) -> Result<Self, SqlxError> {
+        let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let aws_credentials = config
+            .credentials_provider()
+            .ok_or_else(|| config_error("no AWS credentials provider in the environment"))?;
+        Ok(Self::new(host, port, user, region, aws_credentials))
+    }
+
+    /// Generates a new auth token, the presigned `connect` url without its
+    /// scheme
+    async fn generate_token(&self) -> Result<String, SqlxError> {
+        let identity = self
+            .aws_credentials
+            .provide_credentials()
+            .await
+            .map_err(config_error)?
+            .into();
+        let mut settings = SigningSettings::default();
+        settings.expires_in = Some(TOKEN_LIFETIME);
+        settings.signature_location = SignatureLocation::QueryParams;
+        let params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region(&self.region)
+            .name("rds-db")
+            .time(SystemTime::now())
+            .settings(settings)
+            .build()
+            .map_err(config_error)?
+            .into();
+        let url = format!(
+            "https://{}:{}/?Action=connect&DBUser={}",
+            self.host,
+            self.port,
+            encode_value(&self.user)
+        );
+        let request =
+            SignableRequest::new("GET", &url, std::iter::empty(), SignableBody::Bytes(&[]))
+                .map_err(config_error)?;
+        let (instructions, _signature) = sign(request, &params).map_err(config_error)?.into_parts();
+        let mut token = url.trim_start_matches("https://").to_string();
+        for (name, value) in instructions.params() {
+            token.push('&');
+            token.push_str(name);
+            token.push('=');
+            token.push_str(&encode_value(value));
+        }
+        Ok(token)
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for RdsIamProvider {
+    async fn fetch(&self) -> Result<Credentials, SqlxError> {
+        let mut cached = self.cached.lock().await;
+        if let Some((token, generated)) = cached.as_ref() {
+            if generated.elapsed() < TOKEN_REFRESH_AFTER {
+                return Ok(Credentials::new(&self.user, token));
+            }
+        }
+        let token = self.generate_token().await?;
+        *cached = Some((token.clone(), Instant::now()));
+        Ok(Credentials::new(&self.user, &token))
+    }
+
+    fn invalidate(&self) {
+        if let Ok(mut cached) = self.cached.try_lock() {
+            *cached = None;
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+#[cfg(feature = "async-graphql")]
+use async_graphql::{Context, FieldResult};
+#[cfg(feature = "async-graphql")]
+use deadpool::managed::Object;
+
+use crate::error::SqlxHelperError;
+use crate::Pool;
+#[cfg(feature = "async-graphql")]
+use crate::{acquire, application_name, error, PoolManager};
+
+/// Pools of several Postgres clusters, looked up by name, for apps that talk
+/// to more than one database. Store it in the graphQL context and get
+/// connections with [`get_named_connection`].
+///
+/// Example usage
+/// ```ignore
+///  let registry = PoolRegistry::new()
+///     .register("main", main_pool)
+///     .register("analytics", analytics_pool);
+///  registry.require(&["main", "analytics", "reporting"])?;
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     EmptyMutation::default(), EmptySubscription)
+///     .data(registry)
+///     .finish();
+/// ```
+#[derive(Clone, Default)]
+pub struct PoolRegistry {
+    pools: HashMap<String, Pool>,
+}
+
+impl PoolRegistry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `pool` under `name`, replacing any pool registered before under
+    /// the same name
+    pub fn register(mut self, name: &str, pool: Pool) -> Self {
+        self.pools.insert(name.to_string(), pool);
+        self
+    }
+
+    /// The pool registered under `name`
+    pub fn get(&self, name: &str) -> Option<&Pool> {
+        self.pools.get(name)
+    }
+
+    /// The names of the registered pools, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.pools.keys().map(String::as_str)
+    }
+
+    /// Checks at startup that a pool is registered for every name the app
+    /// relies on, so a missing one fails the deployment instead of the first
+    /// request that needs it
+    ///
+    /// Returns Ok or a Config error listing the missing names
+    /// # Arguments
+    /// * `names` - the names of the required pools
+    pub fn require(&self, names: &[&str]) -> Result<(), SqlxHelperError> {
+        let missing: Vec<&str> = names
+            .iter()
+            .copied()
+            .filter(|name| !self.pools.contains_key(*name))
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(SqlxHelperError::Config(format!(
+                "No database pool is registered for {}",
+                missing.join(", ")
+            )))
+        }
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// Extracts a connection object out of the pool registered under `name` in
+/// the PoolRegistry stored in the graphQL context.
+///
+/// Returns a Result with the connection object or a graphQL error with the
+/// `POOL_NOT_CONFIGURED` code if there is no registry or no pool of that name
+/// # Arguments
+/// * `ctx` - graphQL context where the PoolRegistry object is stored
+/// * `name` - the name the pool was registered under
+///
+/// Example usage
+/// ```ignore
+/// let mut conn = get_named_connection(ctx, "analytics").await?;
+/// ```
+pub async fn get_named_connection(
+    ctx: &Context<'_>,
+    name: &str,
+) -> FieldResult<Object<PoolManager>> {
+    let pool = ctx
+        .data_opt::<PoolRegistry>()
+        .and_then(|registry| registry.get(name))
+        .ok_or_else(|| {
+            error::coded_error(
+                format!(
+                    "Database pool {} is not configured in the graphQL context",
+                    name
+                ),
+                error::POOL_NOT_CONFIGURED,
+            )
+        })?;
+    let mut conn = acquire(pool).await?;
+    application_name::apply_application_name(ctx, &mut conn).await?;
+    Ok(conn)
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextExecute, NextPrepareRequest,
+};
+use async_graphql::{Context, FieldResult, Request, Response, ServerResult};
+use async_trait::async_trait;
+use deadpool::managed::Object;
+use sqlx::PgConnection;
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+
+use crate::{error, get_db_connection, PoolManager};
+
+/// Extension code returned when a resolver asks for the request connection
+/// but the [`ConnectionPerRequest`] extension is not registered
+pub const NO_REQUEST_CONNECTION: &str = "NO_REQUEST_CONNECTION";
+
+/// The connection pinned to the current GraphQL operation by
+/// [`ConnectionPerRequest`]. It is stored in the request data so every
+/// resolver of the operation shares the same connection.
+#[derive(Clone, Default)]
+pub struct RequestConnection {
+    conn: Arc<Mutex<Option<Object<PoolManager>>>>,
+}
+
+impl RequestConnection {
+    /// Whether a resolver already asked for the connection
+    pub async fn is_acquired(&self) -> bool {
+        self.conn.lock().await.is_some()
+    }
+}
+
+/// An async_graphql extension that pins one connection to every GraphQL
+/// operation. The connection is acquired when the first resolver asks for it
+/// with [`get_request_connection`], handed to every further resolver of the
+/// operation, and released once the response is done. Deep queries then use
+/// a single connection instead of one per concurrently running resolver,
+/// which keeps them from exhausting small pools. Unlike
+/// [`crate::TransactionPerRequest`] it opens no transaction.
+///
+/// Example usage
+/// ```ignore
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     EmptyMutation::default(), EmptySubscription)
+///     .data(db_pool)
+///     .extension(ConnectionPerRequest)
+///     .finish();
+/// ```
+pub struct ConnectionPerRequest;
+
+impl ExtensionFactory for ConnectionPerRequest {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ConnectionPerRequestExtension {
+            conn: RequestConnection::default(),
+        })
+    }
+}
+
+struct ConnectionPerRequestExtension {
+    conn: RequestConnection,
+}
+
+#[async_trait]
+impl Extension for ConnectionPerRequestExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        next.run(ctx, request.data(self.conn.clone())).await
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let response = next.run(ctx, operation_name).await;
+        // returns the connection to the pool
+        self.conn.conn.lock().await.take();
+        response
+    }
+}
+
+/// Locks and returns the connection pinned to the current GraphQL operation
+/// by [`ConnectionPerRequest`], getting it from the Pool stored in the
+/// graphQL context on first use. Resolvers of the same operation take turns
+/// using the connection, so drop the guard as soon as the statements are
+/// done.
+///
+/// Returns the locked connection or a graphQL error with the
+/// `NO_REQUEST_CONNECTION` code, or the error of getting the connection
+/// # Arguments
+/// * `ctx` - graphQL context of the resolver
+///
+/// Example usage
+/// ```ignore
+/// let mut conn = get_request_connection(ctx).await?;
+/// let rows = query_as::<_, MyData>("SELECT * FROM my_data")
+///   .fetch_all(&mut *conn)
+///   .await;
+/// match_result(rows, format!("Failed to get my_data"))
+/// ```
+pub async fn get_request_connection<'a>(
+    ctx: &Context<'a>,
+) -> FieldResult<MappedMutexGuard<'a, PgConnection>> {
+    let no_connection = || {
+        error::coded_error(
+            "No request connection is available; register the ConnectionPerRequest extension"
+                .to_string(),
+            NO_REQUEST_CONNECTION,
+        )
+    };
+    let pinned = ctx
+        .data_opt::<RequestConnection>()
+        .ok_or_else(no_connection)?;
+    let mut guard = pinned.conn.lock().await;
+    if guard.is_none() {
+        *guard = Some(get_db_connection(ctx).await?);
+    }
+    MutexGuard::try_map(guard, |conn| conn.as_deref_mut()).map_err(|_| no_connection())
+}
@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextExecute, NextPrepareRequest,
+};
+use async_graphql::{Context, FieldError, FieldResult, Pos, Request, Response, ServerResult};
+use async_trait::async_trait;
+use deadpool::managed::Object;
+use sqlx::PgConnection;
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+
+use crate::stats::timed_get;
+use crate::{error, Pool, PoolManager};
+
+/// Extension code returned when a resolver asks for the request transaction
+/// but the [`TransactionPerRequest`] extension is not registered or no
+/// transaction is open
+pub const NO_REQUEST_TRANSACTION: &str = "NO_REQUEST_TRANSACTION";
+
+/// The transaction opened for the current GraphQL operation by
+/// [`TransactionPerRequest`]. It is stored in the request data so every
+/// resolver of the operation shares the same connection.
+#[derive(Clone, Default)]
+pub struct RequestTransaction {
+    conn: Arc<Mutex<Option<Object<PoolManager>>>>,
+}
+
+impl RequestTransaction {
+    /// Shares a connection with an open transaction managed elsewhere, e.g.
+    /// by a test transaction
+    #[cfg(feature = "testing")]
+    pub(crate) fn shared(conn: Arc<Mutex<Option<Object<PoolManager>>>>) -> Self {
+        Self { conn }
+    }
+}
+
+/// An async_graphql extension that opens a transaction at the start of every
+/// GraphQL operation and commits it if the operation produced no errors, or
+/// rolls it back otherwise. Resolvers get the transaction connection with
+/// [`get_request_transaction`].
+///
+/// Do not call `begin()` on the shared connection, it already has an open
+/// transaction that sqlx does not know about; for the same reason
+/// [`crate::Savepoint`] is only available inside sqlx managed transactions
+/// such as [`crate::with_transaction`].
+///
+/// Example usage
+/// ```ignore
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     MutationRoot::default(), EmptySubscription)
+///     .data(db_pool)
+///     .extension(TransactionPerRequest)
+///     .finish();
+/// ```
+pub struct TransactionPerRequest;
+
+impl ExtensionFactory for TransactionPerRequest {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(TransactionPerRequestExtension {
+            tx: RequestTransaction::default(),
+        })
+    }
+}
+
+struct TransactionPerRequestExtension {
+    tx: RequestTransaction,
+}
+
+async fn run_statement(conn: &mut PgConnection, sql: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(sql).execute(conn).await.map(|_| ())
+}
+
+impl TransactionPerRequestExtension {
+    async fn begin(&self, pool: &Pool) -> Result<(), FieldError> {
+        let conn = timed_get(pool).await.map_err(error::map_pool_error)?;
+        // stored before BEGIN runs, so a cancelled BEGIN is cleaned up by Drop
+        let mut guard = self.tx.conn.lock().await;
+        let conn = guard.insert(conn);
+        if let Err(e) = run_statement(conn, "BEGIN").await {
+            if let Some(conn) = guard.take() {
+                drop(Object::take(conn));
+            }
+            return Err(error::map_tx_error(e, "Failed to begin transaction"));
+        }
+        Ok(())
+    }
+
+    async fn finish(&self, commit: bool) -> Result<(), FieldError> {
+        let conn = self.tx.conn.lock().await.take();
+        if let Some(mut conn) = conn {
+            let (sql, action) = if commit {
+                ("COMMIT", "commit")
+            } else {
+                ("ROLLBACK", "roll back")
+            };
+            if let Err(e) = run_statement(&mut conn, sql).await {
+                // the transaction may still be open or aborted, so the
+                // connection is closed rather than returned to the pool
+                drop(Object::take(conn));
+                return Err(error::map_tx_error(
+                    e,
+                    &format!("Failed to {} transaction", action),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TransactionPerRequestExtension {
+    fn drop(&mut self) {
+        // still holding the connection means the operation was cancelled
+        // before finish ran, e.g. by a client disconnect or a timeout, so the
+        // connection is closed instead of going back to the pool inside the
+        // transaction. A locked mutex means a resolver guard still lives,
+        // which can't outlive the request.
+        if let Ok(mut conn) = self.tx.conn.try_lock() {
+            if let Some(conn) = conn.take() {
+                drop(Object::take(conn));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Extension for TransactionPerRequestExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        next.run(ctx, request.data(self.tx.clone())).await
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let pool = match ctx.data_opt::<Pool>() {
+            Some(pool) => pool,
+            None => {
+                let e = error::coded_error(
+                    "Database pool is not configured in the graphQL context".to_string(),
+                    error::POOL_NOT_CONFIGURED,
+                );
+                return Response::from_errors(vec![e.into_server_error(Pos::default())]);
+            }
+        };
+        if let Err(e) = self.begin(pool).await {
+            return Response::from_errors(vec![e.into_server_error(Pos::default())]);
+        }
+        let mut response = next.run(ctx, operation_name).await;
+        if let Err(e) = self.finish(response.is_ok()).await {
+            response.errors.push(e.into_server_error(Pos::default()));
+        }
+        response
+    }
+}
+
+/// Locks and returns the connection of the transaction opened for the current
+/// GraphQL operation by [`TransactionPerRequest`]. Resolvers of the same
+/// operation take turns using the connection, so drop the guard as soon as the
+/// statements are done.
+///
+/// Returns the locked connection or a graphQL error with the
+/// `NO_REQUEST_TRANSACTION` code
+/// # Arguments
+/// * `ctx` - graphQL context of the resolver
+///
+/// Example usage
+/// ```ignore
+/// let mut conn = get_request_transaction(ctx).await?;
+/// let res = query("UPDATE accounts SET balance = balance - $1 WHERE id = $2")
+///   .bind(amount)
+///   .bind(id)
+///   .execute(&mut *conn)
+///   .await;
+/// match_result(res, format!("Failed to debit account"))?;
+/// ```
+pub async fn get_request_transaction<'a>(
+    ctx: &Context<'a>,
+) -> FieldResult<MappedMutexGuard<'a, PgConnection>> {
+    let no_transaction = || {
+        error::coded_error(
+            "No request transaction is open; register the TransactionPerRequest extension"
+                .to_string(),
+            NO_REQUEST_TRANSACTION,
+        )
+    };
+    let tx = ctx
+        .data_opt::<RequestTransaction>()
+        .ok_or_else(no_transaction)?;
+    let guard = tx.conn.lock().await;
+    MutexGuard::try_map(guard, |conn| conn.as_deref_mut()).map_err(|_| no_transaction())
+}
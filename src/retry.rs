@@ -0,0 +1,125 @@
+#[cfg(feature = "async-graphql")]
+use async_graphql::{Context, FieldResult};
+use futures::future::BoxFuture;
+use sqlx::{Error as SqlxError, PgConnection};
+
+#[cfg(feature = "async-graphql")]
+use crate::error::map_sqlx_error;
+use crate::error::{categorize, pool_error_to_sqlx, ErrorCategory};
+#[cfg(feature = "async-graphql")]
+use crate::get_db_connection;
+use crate::stats::timed_get;
+use crate::{Pool, RetryPolicy};
+
+/// Transient errors: connection resets, deadlocks, serialization failures,
+/// admin shutdowns and the other failures of [`ErrorCategory::Transient`]
+fn is_retryable(err: &SqlxError) -> bool {
+    categorize(err) == ErrorCategory::Transient
+}
+
+/// Runs `f` on a connection from `pool`, running it again on a fresh
+/// connection when it fails with a transient error, until it succeeds, fails
+/// otherwise or the attempts of the policy are exhausted. This is
+/// `execute_with_retry` for code without a graphQL context.
+///
+/// Returns the value produced by `f` or the last sqlx error
+/// # Arguments
+/// * `pool` - the pool to get the connections from
+/// * `policy` - the number of attempts and the backoff between them
+/// * `f` - a closure that runs idempotent statements on the connection
+pub async fn run_with_retry<T, F>(
+    pool: &Pool,
+    policy: &RetryPolicy,
+    mut f: F,
+) -> Result<T, SqlxError>
+where
+    T: Send,
+    F: for<'c> FnMut(&'c mut PgConnection) -> BoxFuture<'c, Result<T, SqlxError>>,
+{
+    let mut attempt = 1;
+    loop {
+        let mut conn = timed_get(pool).await.map_err(pool_error_to_sqlx)?;
+        match f(&mut *conn).await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < policy.max_attempts && is_retryable(&e) => {
+                #[cfg(feature = "tracing")]
+                tracing::info!(
+                    attempt,
+                    error = %crate::error::redact(&e.to_string()),
+                    "retrying statement"
+                );
+                drop(conn);
+                tokio::time::sleep(policy.delay_for(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// Runs `f` on a connection from the Pool stored in the graphQL context and
+/// runs it again when it fails with a connection reset, deadlock,
+/// serialization failure, admin shutdown or another transient error. Every
+/// attempt gets a fresh connection, since the one that failed may be broken.
+/// The closure may run several times, so it must only run idempotent
+/// statements; use [`crate::retry_transaction`] for whole transactions.
+///
+/// Returns the value produced by `f` or a graphQL error once the attempts of
+/// the policy are exhausted or a non retryable error occurs
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool object is stored
+/// * `policy` - the number of attempts and the backoff between them
+/// * `err_msg` - a custom error message that will be prepended if Err is returned
+/// * `f` - a closure that runs idempotent statements on the connection
+///
+/// Example usage
+/// ```ignore
+/// let rows = execute_with_retry(ctx, &RetryPolicy::default(), "Failed to get my_data", |conn| {
+///     Box::pin(async move {
+///         query_as::<_, MyData>("SELECT * FROM my_data")
+///             .fetch_all(conn)
+///             .await
+///     })
+/// })
+/// .await?;
+/// ```
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "sqlx_helpers.execute_with_retry", skip_all)
+)]
+pub async fn execute_with_retry<T, F>(
+    ctx: &Context<'_>,
+    policy: &RetryPolicy,
+    err_msg: &str,
+    mut f: F,
+) -> FieldResult<T>
+where
+    T: Send,
+    F: for<'c> FnMut(&'c mut PgConnection) -> BoxFuture<'c, Result<T, SqlxError>>,
+{
+    let mut attempt = 1;
+    loop {
+        let mut conn = get_db_connection(ctx).await?;
+        match f(&mut *conn).await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < policy.max_attempts && is_retryable(&e) => {
+                #[cfg(feature = "tracing")]
+                tracing::info!(
+                    attempt,
+                    error = %crate::error::redact(&e.to_string()),
+                    "retrying statement"
+                );
+                drop(conn);
+                tokio::time::sleep(policy.delay_for(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(map_sqlx_error(
+                    e,
+                    &format!("{} after {} attempt(s)", err_msg, attempt),
+                ))
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::ops::{Deref, DerefMut};
+
+use async_graphql::{Context, FieldResult};
+use sqlx::{query, Executor, PgConnection};
+
+use crate::error::map_tx_error;
+use crate::{get_db_connection, match_result, ScopedConnection};
+
+/// The setting holding the user id for row level security policies, read in
+/// a policy with `current_setting('app.current_user_id')`
+pub const CURRENT_USER_ID_SETTING: &str = "app.current_user_id";
+
+/// The setting holding the tenant id for row level security policies
+pub const CURRENT_TENANT_ID_SETTING: &str = "app.current_tenant_id";
+
+/// The identity a connection from [`get_db_connection_for_user`] is scoped
+/// to. Every field becomes a transaction local setting.
+///
+/// Example usage
+/// ```ignore
+///  let claims = RlsClaims::new(&user.id.to_string())
+///     .tenant_id(&user.tenant_id.to_string())
+///     .set("app.current_plan", "pro");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RlsClaims {
+    pub user_id: String,
+    pub tenant_id: Option<String>,
+    pub settings: Vec<(String, String)>,
+}
+
+impl RlsClaims {
+    /// Claims for the given user id
+    pub fn new(user_id: &str) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Sets the tenant id
+    pub fn tenant_id(mut self, tenant_id: &str) -> Self {
+        self.tenant_id = Some(tenant_id.to_string());
+        self
+    }
+
+    /// Adds another setting, e.g. `app.current_role`
+    pub fn set(mut self, name: &str, value: &str) -> Self {
+        self.settings.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn pairs(&self) -> Vec<(&str, &str)> {
+        let mut pairs = vec![(CURRENT_USER_ID_SETTING, self.user_id.as_str())];
+        if let Some(tenant_id) = &self.tenant_id {
+            pairs.push((CURRENT_TENANT_ID_SETTING, tenant_id.as_str()));
+        }
+        pairs.extend(
+            self.settings
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+        pairs
+    }
+}
+
+/// A connection with an open transaction whose local settings carry the
+/// [`RlsClaims`]. The transaction is rolled back unless
+/// [`RlsConnection::commit`] is called, also when the value is dropped, so the
+/// settings never outlive it. The transaction is opened with a plain `BEGIN`,
+/// so don't call `begin()` on the connection.
+pub struct RlsConnection {
+    conn: ScopedConnection,
+}
+
+impl RlsConnection {
+    /// Commits the transaction and returns the connection to the pool
+    pub async fn commit(self) -> FieldResult<()> {
+        self.conn
+            .release_with("COMMIT")
+            .await
+            .map_err(|e| map_tx_error(e, "Failed to commit transaction"))
+    }
+
+    /// Rolls the transaction back and returns the connection to the pool
+    pub async fn rollback(self) -> FieldResult<()> {
+        self.conn
+            .release()
+            .await
+            .map_err(|e| map_tx_error(e, "Failed to rollback transaction"))
+    }
+}
+
+impl Deref for RlsConnection {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        &self.conn
+    }
+}
+
+impl DerefMut for RlsConnection {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        &mut self.conn
+    }
+}
+
+/// Gets a connection out of the Pool in the graphQL context, opens a
+/// transaction on it and issues `SET LOCAL` for every claim, so Postgres row
+/// level security policies see the user of the request.
+///
+/// Returns the connection or a graphQL error
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool object is stored
+/// * `claims` - the identity of the user of the request
+///
+/// Example usage
+/// ```ignore
+/// let mut conn = get_db_connection_for_user(ctx, &claims).await?;
+/// let rows = query_as::<_, Document>("SELECT * FROM documents")
+///     .fetch_all(&mut *conn)
+///     .await;
+/// let rows = match_result(rows, "Failed to get documents".to_string())?;
+/// conn.commit().await?;
+/// ```
+pub async fn get_db_connection_for_user(
+    ctx: &Context<'_>,
+    claims: &RlsClaims,
+) -> FieldResult<RlsConnection> {
+    let conn = get_db_connection(ctx).await?;
+    let mut conn = ScopedConnection::new(conn, "ROLLBACK");
+    (&mut *conn)
+        .execute("BEGIN")
+        .await
+        .map_err(|e| map_tx_error(e, "Failed to begin transaction"))?;
+    for (name, value) in claims.pairs() {
+        // set_config(.., true) is SET LOCAL with bind parameters
+        let res = query("SELECT set_config($1, $2, true)")
+            .bind(name)
+            .bind(value)
+            .execute(&mut *conn)
+            .await;
+        match_result(res, format!("Failed to set {}", name))?;
+    }
+    Ok(RlsConnection { conn })
+}
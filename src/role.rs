@@ -0,0 +1,58 @@
+use async_graphql::{Context, FieldResult};
+use sqlx::query;
+
+use crate::{error, get_db_connection, match_result, ScopedConnection};
+
+/// Extension code returned by [`get_db_connection_as_role`] when the context
+/// has no [`RequestRole`]
+pub const NO_REQUEST_ROLE: &str = "NO_REQUEST_ROLE";
+
+/// The Postgres role the current request acts as. Put it in the request data,
+/// typically where the request is authenticated, and resolvers get
+/// connections restricted to it with [`get_db_connection_as_role`].
+///
+/// Example usage
+/// ```ignore
+///  let request = async_graphql::Request::new(query)
+///     .data(RequestRole(claims.db_role.clone()));
+///  schema.execute(request).await
+/// ```
+#[derive(Clone, Debug)]
+pub struct RequestRole(pub String);
+
+/// Gets a connection out of the Pool in the graphQL context and switches it
+/// to the [`RequestRole`] of the request with `SET ROLE`, so grants and row
+/// level security policies of that role apply. The role is reset with
+/// `RESET ROLE` before the connection is reused (see [`ScopedConnection`]).
+///
+/// Returns the connection or a graphQL error; the error carries the
+/// `NO_REQUEST_ROLE` code if the context has no role, so resolvers never run
+/// with the privileges of the pool user by accident
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool and the RequestRole are stored
+///
+/// Example usage
+/// ```ignore
+/// let mut conn = get_db_connection_as_role(ctx).await?;
+/// let rows = query_as::<_, Invoice>("SELECT * FROM invoices")
+///     .fetch_all(&mut *conn)
+///     .await;
+/// match_result(rows, "Failed to get invoices".to_string())
+/// ```
+pub async fn get_db_connection_as_role(ctx: &Context<'_>) -> FieldResult<ScopedConnection> {
+    let RequestRole(role) = ctx.data_opt::<RequestRole>().ok_or_else(|| {
+        error::coded_error(
+            "No database role in the graphQL context".to_string(),
+            NO_REQUEST_ROLE,
+        )
+    })?;
+    let conn = get_db_connection(ctx).await?;
+    let mut conn = ScopedConnection::new(conn, "RESET ROLE");
+    // set_config takes the role as a bind parameter, unlike SET ROLE
+    let res = query("SELECT set_config('role', $1, false)")
+        .bind(role)
+        .execute(&mut *conn)
+        .await;
+    match_result(res, format!("Failed to set role {}", role))?;
+    Ok(conn)
+}
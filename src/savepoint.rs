@@ -0,0 +1,114 @@
+use std::ops::{Deref, DerefMut};
+
+use async_graphql::FieldResult;
+use futures::future::BoxFuture;
+use sqlx::{Connection, PgConnection, Postgres, Transaction};
+
+use crate::error::map_tx_error;
+
+/// A guard for a savepoint inside a larger transaction. Creating it issues
+/// `SAVEPOINT`, [`Savepoint::release`] issues `RELEASE SAVEPOINT` and
+/// [`Savepoint::rollback`] issues `ROLLBACK TO SAVEPOINT`. If the guard is
+/// dropped without being released, the work done since the savepoint is
+/// rolled back while the enclosing transaction stays usable.
+///
+/// Example usage
+/// ```ignore
+/// let mut sp = Savepoint::new(&mut tx).await?;
+/// let res = query("INSERT INTO audit_log (msg) VALUES ($1)")
+///   .bind(msg)
+///   .execute(&mut *sp)
+///   .await;
+/// if res.is_ok() {
+///     sp.release().await?;
+/// } // otherwise the insert is rolled back when `sp` is dropped
+/// ```
+pub struct Savepoint<'c> {
+    tx: Transaction<'c, Postgres>,
+}
+
+impl<'c> Savepoint<'c> {
+    /// Issues a `SAVEPOINT` inside the given transaction
+    ///
+    /// Returns the guard or a graphQL error
+    /// # Arguments
+    /// * `tx` - the enclosing transaction
+    pub async fn new(tx: &'c mut Transaction<'_, Postgres>) -> FieldResult<Savepoint<'c>> {
+        let conn: &'c mut PgConnection = tx;
+        let tx = conn
+            .begin()
+            .await
+            .map_err(|e| map_tx_error(e, "Failed to create savepoint"))?;
+        Ok(Savepoint { tx })
+    }
+
+    /// Keeps the work done since the savepoint as part of the enclosing
+    /// transaction
+    pub async fn release(self) -> FieldResult<()> {
+        self.tx
+            .commit()
+            .await
+            .map_err(|e| map_tx_error(e, "Failed to release savepoint"))
+    }
+
+    /// Discards the work done since the savepoint
+    pub async fn rollback(self) -> FieldResult<()> {
+        self.tx
+            .rollback()
+            .await
+            .map_err(|e| map_tx_error(e, "Failed to roll back to savepoint"))
+    }
+}
+
+impl<'c> Deref for Savepoint<'c> {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        &self.tx
+    }
+}
+
+impl<'c> DerefMut for Savepoint<'c> {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        &mut self.tx
+    }
+}
+
+/// Runs `f` inside a savepoint of the given transaction. The savepoint is
+/// released if `f` returns Ok and rolled back if it returns Err, so the caller
+/// can carry on with the enclosing transaction either way.
+///
+/// Returns the value produced by `f` or a graphQL error
+/// # Arguments
+/// * `tx` - the enclosing transaction
+/// * `f` - a closure that receives the savepoint connection
+///
+/// Example usage
+/// ```ignore
+/// with_transaction(ctx, |tx| {
+///     Box::pin(async move {
+///         // a failing notification must not undo the order
+///         let _ = with_savepoint(tx, |conn| Box::pin(notify_customer(conn, id))).await;
+///         insert_order(tx, order).await
+///     })
+/// })
+/// .await?;
+/// ```
+pub async fn with_savepoint<T, F>(tx: &mut Transaction<'_, Postgres>, f: F) -> FieldResult<T>
+where
+    T: Send,
+    F: for<'c> FnOnce(&'c mut PgConnection) -> BoxFuture<'c, FieldResult<T>>,
+{
+    let mut savepoint = Savepoint::new(tx).await?;
+    match f(&mut *savepoint).await {
+        Ok(value) => {
+            savepoint.release().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            // the closure error is more useful to the caller than a rollback error
+            let _ = savepoint.rollback().await;
+            Err(e)
+        }
+    }
+}
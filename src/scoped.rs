@@ -0,0 +1,94 @@
+use std::borrow::Cow;
+use std::ops::{Deref, DerefMut};
+
+use deadpool::managed::Object;
+use sqlx::{Error as SqlxError, Executor, PgConnection};
+
+use crate::PoolManager;
+
+/// A pooled connection with session state (a role, a search_path) that must
+/// not leak to the next user of the connection. The state is reset with
+/// [`ScopedConnection::release`], or when the value is dropped: the reset then
+/// runs on a spawned task before the connection goes back to the pool, and a
+/// connection that can't be reset is closed instead of returned.
+pub struct ScopedConnection {
+    conn: Option<Object<PoolManager>>,
+    reset_sql: Cow<'static, str>,
+}
+
+impl ScopedConnection {
+    pub(crate) fn new(conn: Object<PoolManager>, reset_sql: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            conn: Some(conn),
+            reset_sql: reset_sql.into(),
+        }
+    }
+
+    /// Resets the session state and returns the connection to the pool
+    ///
+    /// Returns Ok or the sqlx error of the reset, in which case the connection
+    /// is closed
+    pub async fn release(mut self) -> Result<(), SqlxError> {
+        let reset_sql = std::mem::take(&mut self.reset_sql);
+        self.release_with(&reset_sql).await
+    }
+
+    /// Like [`ScopedConnection::release`], but ends the scope with `sql`
+    /// instead of the reset statement, e.g. `COMMIT` instead of `ROLLBACK`
+    pub(crate) async fn release_with(mut self, sql: &str) -> Result<(), SqlxError> {
+        match self.conn.take() {
+            Some(conn) => reset(conn, sql).await,
+            None => Ok(()),
+        }
+    }
+}
+
+async fn reset(mut conn: Object<PoolManager>, reset_sql: &str) -> Result<(), SqlxError> {
+    match (&mut *conn).execute(reset_sql).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                error = %crate::error::redact(&e.to_string()),
+                "failed to reset connection, closing it"
+            );
+            drop(Object::take(conn));
+            Err(e)
+        }
+    }
+}
+
+impl Deref for ScopedConnection {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        // only None after release, which consumes self
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for ScopedConnection {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl Drop for ScopedConnection {
+    fn drop(&mut self) {
+        let conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let reset_sql = std::mem::take(&mut self.reset_sql);
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    let _ = reset(conn, &reset_sql).await;
+                });
+            }
+            // without a runtime the reset can't run, so don't hand the
+            // connection to anyone else
+            Err(_) => drop(Object::take(conn)),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use sqlx::Error as SqlxError;
+use tokio::sync::Mutex;
+
+use crate::{Credentials, CredentialsProvider};
+
+/// How long credentials from a [`SecretSource`] are cached by default
+pub const DEFAULT_SECRET_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// A secrets backend holding the database username and password, such as
+/// Vault, AWS Secrets Manager or a KMS encrypted file. Register it with
+/// [`crate::PoolManager::secret_source`]; the secret is cached for a TTL and
+/// read again early when the database rejects it.
+///
+/// Example usage, a Vault KV v2 secret read over HTTP
+/// ```ignore
+///  struct VaultSecret { client: reqwest::Client, url: String, token: String }
+///
+///  #[async_trait]
+///  impl SecretSource for VaultSecret {
+///      async fn get_secret(&self) -> Result<Credentials, sqlx::Error> {
+///          let body: serde_json::Value = self.client.get(&self.url)
+///              .header("X-Vault-Token", &self.token)
+///              .send().await.and_then(|r| r.error_for_status())
+///              .map_err(|e| sqlx::Error::Configuration(e.into()))?
+///              .json().await
+///              .map_err(|e| sqlx::Error::Configuration(e.into()))?;
+///          let data = &body["data"]["data"];
+///          Ok(Credentials::new(
+///              data["username"].as_str().unwrap_or_default(),
+///              data["password"].as_str().unwrap_or_default(),
+///          ))
+///      }
+///  }
+/// ```
+///
+/// and a password decrypted with a KMS style client
+/// ```ignore
+///  struct KmsPassword { kms: KmsClient, ciphertext: Vec<u8>, username: String }
+///
+///  #[async_trait]
+///  impl SecretSource for KmsPassword {
+///      async fn get_secret(&self) -> Result<Credentials, sqlx::Error> {
+///          let plaintext = self.kms.decrypt(&self.ciphertext).await
+///              .map_err(|e| sqlx::Error::Configuration(e.into()))?;
+///          let password = String::from_utf8(plaintext)
+///              .map_err(|e| sqlx::Error::Configuration(e.into()))?;
+///          Ok(Credentials::new(&self.username, &password))
+///      }
+///  }
+/// ```
+#[async_trait]
+pub trait SecretSource: Send + Sync {
+    /// Reads the current username and password from the backend
+    async fn get_secret(&self) -> Result<Credentials, SqlxError>;
+}
+
+/// A [`CredentialsProvider`] caching the credentials of a [`SecretSource`]
+/// for a TTL, so the backend isn't asked for every new connection
+pub struct CachedSecret<S> {
+    source: S,
+    ttl: Duration,
+    cached: Mutex<Option<(Credentials, Instant)>>,
+}
+
+impl<S: SecretSource> CachedSecret<S> {
+    /// Caches the secrets of `source` for `ttl`
+    pub fn new(source: S, ttl: Duration) -> Self {
+        Self {
+            source,
+            ttl,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Drops the cached credentials, so the next connection reads the secret
+    /// again, e.g. right after rotating it
+    pub async fn refresh(&self) {
+        *self.cached.lock().await = None;
+    }
+}
+
+#[async_trait]
+impl<S: SecretSource> CredentialsProvider for CachedSecret<S> {
+    async fn fetch(&self) -> Result<Credentials, SqlxError> {
+        let mut cached = self.cached.lock().await;
+        if let Some((credentials, fetched)) = cached.as_ref() {
+            if fetched.elapsed() < self.ttl {
+                return Ok(credentials.clone());
+            }
+        }
+        let credentials = self.source.get_secret().await?;
+        *cached = Some((credentials.clone(), Instant::now()));
+        Ok(credentials)
+    }
+
+    fn invalidate(&self) {
+        // fetch holds the lock only while reading the secret, in which case
+        // the value it stores is fresh anyway
+        if let Ok(mut cached) = self.cached.try_lock() {
+            *cached = None;
+        }
+    }
+}
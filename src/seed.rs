@@ -0,0 +1,191 @@
+use std::io;
+use std::path::Path;
+
+use futures::future::BoxFuture;
+use sqlx::{query, query_as, Connection, Error as SqlxError, Executor, PgConnection};
+
+use crate::advisory_lock::fnv1a;
+use crate::error::SqlxHelperError;
+use crate::stats::timed_get;
+use crate::{LockKey, Pool};
+
+/// The table recording the applied seeds and their checksums
+pub const SEEDS_TABLE: &str = "_sqlx_helpers_seeds";
+
+type SeedFn =
+    dyn for<'c> Fn(&'c mut PgConnection) -> BoxFuture<'c, Result<(), SqlxError>> + Send + Sync;
+
+enum SeedSource {
+    Sql(String),
+    Function(Box<SeedFn>),
+}
+
+struct Seed {
+    name: String,
+    checksum: String,
+    source: SeedSource,
+}
+
+/// Loads seed data for dev and test databases: SQL scripts and Rust
+/// functions run in the order they were added, all in one transaction. Every
+/// applied seed is recorded with a checksum, so running the seeder again
+/// only applies new seeds and seeds whose content changed. A changed seed
+/// runs on top of its previous version, so write seeds that can, e.g. with
+/// upserts.
+///
+/// Example usage
+/// ```ignore
+///  let applied = Seeder::new()
+///      .dir("./seeds")?
+///      .function("demo_users", "v2", |conn| Box::pin(async move {
+///          for i in 0..100 {
+///              query("INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING")
+///                  .bind(format!("user{}", i))
+///                  .execute(&mut *conn)
+///                  .await?;
+///          }
+///          Ok(())
+///      }))
+///      .run(&db_pool)
+///      .await?;
+/// ```
+#[derive(Default)]
+pub struct Seeder {
+    seeds: Vec<Seed>,
+}
+
+impl Seeder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a SQL script, which may hold several statements
+    pub fn sql(mut self, name: &str, sql: &str) -> Self {
+        self.seeds.push(Seed {
+            name: name.to_string(),
+            checksum: format!("{:016x}", fnv1a(sql.as_bytes())),
+            source: SeedSource::Sql(sql.to_string()),
+        });
+        self
+    }
+
+    /// Adds the SQL script in the file at `path`, named after the file
+    pub fn sql_file(self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let sql = std::fs::read_to_string(path)?;
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        Ok(self.sql(&name, &sql))
+    }
+
+    /// Adds the `.sql` files of the directory at `path`, ordered by file
+    /// name, e.g. `01_users.sql`, `02_orders.sql`
+    pub fn dir(self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension() == Some("sql".as_ref()) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        paths.into_iter().try_fold(self, Seeder::sql_file)
+    }
+
+    /// Adds a Rust seed function; change `version` to apply it again
+    pub fn function<F>(mut self, name: &str, version: &str, f: F) -> Self
+    where
+        F: for<'c> Fn(&'c mut PgConnection) -> BoxFuture<'c, Result<(), SqlxError>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.seeds.push(Seed {
+            name: name.to_string(),
+            checksum: version.to_string(),
+            source: SeedSource::Function(Box::new(f)),
+        });
+        self
+    }
+
+    /// Applies the new and changed seeds in one transaction, holding an
+    /// advisory lock so concurrent runs don't apply them twice
+    ///
+    /// Returns the names of the applied seeds, or a [`SqlxHelperError`]
+    /// naming the failed seed, in which case nothing was applied
+    /// # Arguments
+    /// * `pool` - the pool to get the connection from
+    pub async fn run(&self, pool: &Pool) -> Result<Vec<String>, SqlxHelperError> {
+        let mut conn = timed_get(pool).await.map_err(SqlxHelperError::from_pool)?;
+        let mut tx = conn
+            .begin()
+            .await
+            .map_err(|e| tx_error(e, "Failed to begin the seed transaction"))?;
+        let applied = self
+            .apply(&mut tx)
+            .await
+            .map_err(|(e, message)| SqlxHelperError::from_sqlx(e, &message))?;
+        tx.commit()
+            .await
+            .map_err(|e| tx_error(e, "Failed to commit the seeds"))?;
+        Ok(applied)
+    }
+
+    async fn apply(&self, conn: &mut PgConnection) -> Result<Vec<String>, (SqlxError, String)> {
+        let setup = |e| (e, "Failed to prepare the seeds table".to_string());
+        query("SELECT pg_advisory_xact_lock($1)")
+            .bind(LockKey::from(SEEDS_TABLE).0)
+            .execute(&mut *conn)
+            .await
+            .map_err(setup)?;
+        let sql = format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+             name text PRIMARY KEY, checksum text NOT NULL, \
+             applied_at timestamptz NOT NULL DEFAULT now())",
+            SEEDS_TABLE
+        );
+        (&mut *conn).execute(sql.as_str()).await.map_err(setup)?;
+        let sql = format!("SELECT name, checksum FROM {}", SEEDS_TABLE);
+        let recorded: Vec<(String, String)> =
+            query_as(&sql).fetch_all(&mut *conn).await.map_err(setup)?;
+        let record = format!(
+            "INSERT INTO {} (name, checksum) VALUES ($1, $2) \
+             ON CONFLICT (name) DO UPDATE SET checksum = $2, applied_at = now()",
+            SEEDS_TABLE
+        );
+        let mut applied = Vec::new();
+        for seed in &self.seeds {
+            if recorded
+                .iter()
+                .any(|(name, checksum)| *name == seed.name && *checksum == seed.checksum)
+            {
+                continue;
+            }
+            let res = match &seed.source {
+                SeedSource::Sql(sql) => (&mut *conn).execute(sql.as_str()).await.map(|_| ()),
+                SeedSource::Function(f) => f(&mut *conn).await,
+            };
+            let failed = |e| (e, format!("Failed to apply seed {}", seed.name));
+            res.map_err(failed)?;
+            query(&record)
+                .bind(&seed.name)
+                .bind(&seed.checksum)
+                .execute(&mut *conn)
+                .await
+                .map_err(failed)?;
+            #[cfg(feature = "tracing")]
+            tracing::info!(seed = %seed.name, "applied seed");
+            applied.push(seed.name.clone());
+        }
+        Ok(applied)
+    }
+}
+
+fn tx_error(source: SqlxError, message: &str) -> SqlxHelperError {
+    SqlxHelperError::Tx {
+        message: message.to_string(),
+        source,
+    }
+}
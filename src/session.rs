@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use sqlx::{query, Error as SqlxError, PgConnection};
+
+/// Session settings applied once to every connection created by a
+/// [`crate::PoolManager`]. Values are passed to `set_config` as bind
+/// parameters, so they don't need quoting.
+///
+/// Example usage
+/// ```ignore
+///  let session = SessionConfig::new()
+///     .search_path("app, public")
+///     .timezone("UTC")
+///     .statement_timeout(Duration::from_secs(30))
+///     .set("app.region", "eu-west-1");
+///  let mgr = PoolManager::new(&database_url)?.with_session(session);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SessionConfig {
+    pub search_path: Option<String>,
+    pub timezone: Option<String>,
+    pub statement_timeout: Option<Duration>,
+    pub lock_timeout: Option<Duration>,
+    pub settings: Vec<(String, String)>,
+}
+
+impl SessionConfig {
+    /// Creates a config that changes no settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the `search_path`, e.g. `"app, public"`
+    pub fn search_path(mut self, search_path: &str) -> Self {
+        self.search_path = Some(search_path.to_string());
+        self
+    }
+
+    /// Sets the session `TimeZone`
+    pub fn timezone(mut self, timezone: &str) -> Self {
+        self.timezone = Some(timezone.to_string());
+        self
+    }
+
+    /// Sets the `statement_timeout` of the session
+    pub fn statement_timeout(mut self, timeout: Duration) -> Self {
+        self.statement_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the `lock_timeout` of the session
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets any other run-time parameter, including custom `app.*` settings
+    pub fn set(mut self, name: &str, value: &str) -> Self {
+        self.settings.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The settings as (name, value) pairs in the order they are applied
+    fn pairs(&self) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        if let Some(search_path) = &self.search_path {
+            pairs.push(("search_path".to_string(), search_path.clone()));
+        }
+        if let Some(timezone) = &self.timezone {
+            pairs.push(("TimeZone".to_string(), timezone.clone()));
+        }
+        if let Some(timeout) = self.statement_timeout {
+            pairs.push((
+                "statement_timeout".to_string(),
+                format!("{}ms", timeout.as_millis()),
+            ));
+        }
+        if let Some(timeout) = self.lock_timeout {
+            pairs.push((
+                "lock_timeout".to_string(),
+                format!("{}ms", timeout.as_millis()),
+            ));
+        }
+        pairs.extend(self.settings.iter().cloned());
+        pairs
+    }
+
+    /// Applies the settings to `conn` for the rest of the session
+    ///
+    /// Returns Ok or the sqlx error of the first setting that was rejected
+    /// # Arguments
+    /// * `conn` - the connection to configure
+    pub async fn apply(&self, conn: &mut PgConnection) -> Result<(), SqlxError> {
+        for (name, value) in self.pairs() {
+            query("SELECT set_config($1, $2, false)")
+                .bind(name)
+                .bind(value)
+                .execute(&mut *conn)
+                .await?;
+        }
+        Ok(())
+    }
+}
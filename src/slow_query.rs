@@ -0,0 +1,129 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default duration above which [`SlowQueryLog`] reports a query
+pub const DEFAULT_SLOW_QUERY_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Default number of characters of the SQL text kept in a slow query report
+pub const DEFAULT_MAX_SQL_LEN: usize = 200;
+
+/// A query that took longer than the [`SlowQueryLog`] threshold
+#[derive(Clone, Debug)]
+pub struct SlowQuery {
+    /// The SQL text, truncated to the configured length
+    pub sql: String,
+    /// Number of bind values of the query
+    pub binds: usize,
+    /// How long the query took
+    pub elapsed: Duration,
+}
+
+type SlowQueryHandler = dyn Fn(&SlowQuery) + Send + Sync;
+
+/// Times queries and reports those exceeding a threshold. Reports go to
+/// `tracing` with the `tracing` feature and to the `log` crate otherwise,
+/// unless a custom handler is set.
+///
+/// Example usage
+/// ```ignore
+///  let slow_log = SlowQueryLog::new(Duration::from_millis(250));
+///  let sql = "SELECT * FROM my_data WHERE owner_id = $1";
+///  let rows = slow_log
+///     .run(sql, 1, query_as::<_, MyData>(sql).bind(owner_id).fetch_all(&mut *conn))
+///     .await;
+/// ```
+#[derive(Clone)]
+pub struct SlowQueryLog {
+    threshold: Duration,
+    max_sql_len: usize,
+    handler: Option<Arc<SlowQueryHandler>>,
+}
+
+impl Default for SlowQueryLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOW_QUERY_THRESHOLD)
+    }
+}
+
+impl SlowQueryLog {
+    /// Reports queries taking longer than `threshold`
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            max_sql_len: DEFAULT_MAX_SQL_LEN,
+            handler: None,
+        }
+    }
+
+    /// Sets how many characters of the SQL text are kept in reports
+    pub fn max_sql_len(mut self, max_sql_len: usize) -> Self {
+        self.max_sql_len = max_sql_len;
+        self
+    }
+
+    /// Sends reports to `handler` instead of the log
+    pub fn on_slow<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&SlowQuery) + Send + Sync + 'static,
+    {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Awaits the query future and reports it if it took longer than the
+    /// threshold
+    ///
+    /// Returns the output of the query future
+    /// # Arguments
+    /// * `sql` - the SQL text of the query, used in the report
+    /// * `binds` - the number of bind values of the query
+    /// * `fut` - the query future, e.g. `query(sql).fetch_all(conn)`
+    pub async fn run<F: Future>(&self, sql: &str, binds: usize, fut: F) -> F::Output {
+        #[cfg(feature = "async-graphql")]
+        crate::n_plus_one::record_sql(sql);
+        let started = Instant::now();
+        let output = fut.await;
+        let elapsed = started.elapsed();
+        #[cfg(feature = "prometheus")]
+        crate::metrics::observe_query(elapsed);
+        if elapsed > self.threshold {
+            self.report(&SlowQuery {
+                sql: truncate(sql, self.max_sql_len),
+                binds,
+                elapsed,
+            });
+        }
+        output
+    }
+
+    fn report(&self, query: &SlowQuery) {
+        if let Some(handler) = &self.handler {
+            handler(query);
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            sql = %query.sql,
+            binds = query.binds,
+            elapsed_ms = query.elapsed.as_millis() as u64,
+            "slow query"
+        );
+        #[cfg(not(feature = "tracing"))]
+        log::warn!(
+            "slow query ({} ms, {} binds): {}",
+            query.elapsed.as_millis(),
+            query.binds,
+            query.sql
+        );
+    }
+}
+
+/// Shortens `sql` to at most `max_len` characters, marking the cut with `...`
+pub(crate) fn truncate(sql: &str, max_len: usize) -> String {
+    let sql = sql.trim();
+    match sql.char_indices().nth(max_len) {
+        Some((end, _)) => format!("{}...", &sql[..end]),
+        None => sql.to_string(),
+    }
+}
@@ -0,0 +1,60 @@
+use sqlx::{Encode, Error as SqlxError, PgConnection, Postgres, Type};
+
+/// The column holding the deletion time of soft deleted rows, NULL for rows
+/// that are not deleted
+pub const DELETED_AT_COLUMN: &str = "deleted_at";
+
+/// Marks the row of `table` with the given `id` as deleted by setting its
+/// `deleted_at` to the current time. Queries built with a [`crate::Filter`]
+/// skip the row once [`crate::Filter::soft_delete`] is set.
+///
+/// Table names are written into the SQL as given, so never pass user input
+/// as a table name.
+///
+/// Returns whether a row was deleted, false if there is no such row or it was
+/// deleted already, or the sqlx error
+/// # Arguments
+/// * `conn` - the connection to run the update on
+/// * `table` - the table of the row, with an `id` and a `deleted_at` column
+/// * `id` - the id of the row
+///
+/// Example usage
+/// ```ignore
+/// let deleted = soft_delete(&mut conn, "posts", post_id).await;
+/// if !match_result(deleted, format!("Failed to delete post {}", post_id))? {
+///     return Err(coded_error("No such post".to_string(), "NOT_FOUND"));
+/// }
+/// ```
+pub async fn soft_delete<I>(conn: &mut PgConnection, table: &str, id: I) -> Result<bool, SqlxError>
+where
+    I: for<'q> Encode<'q, Postgres> + Type<Postgres> + Send,
+{
+    let sql = format!(
+        "UPDATE {} SET {col} = now() WHERE id = $1 AND {col} IS NULL",
+        table,
+        col = DELETED_AT_COLUMN
+    );
+    let res = sqlx::query(&sql).bind(id).execute(conn).await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Undoes [`soft_delete`] by clearing the `deleted_at` of the row
+///
+/// Returns whether a row was restored, false if there is no such row or it
+/// wasn't deleted, or the sqlx error
+/// # Arguments
+/// * `conn` - the connection to run the update on
+/// * `table` - the table of the row, with an `id` and a `deleted_at` column
+/// * `id` - the id of the row
+pub async fn restore<I>(conn: &mut PgConnection, table: &str, id: I) -> Result<bool, SqlxError>
+where
+    I: for<'q> Encode<'q, Postgres> + Type<Postgres> + Send,
+{
+    let sql = format!(
+        "UPDATE {} SET {col} = NULL WHERE id = $1 AND {col} IS NOT NULL",
+        table,
+        col = DELETED_AT_COLUMN
+    );
+    let res = sqlx::query(&sql).bind(id).execute(conn).await?;
+    Ok(res.rows_affected() > 0)
+}
@@ -0,0 +1,169 @@
+#[cfg(feature = "async-graphql")]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "async-graphql")]
+use std::sync::Arc;
+
+#[cfg(feature = "async-graphql")]
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextPrepareRequest,
+};
+#[cfg(feature = "async-graphql")]
+use async_graphql::{Context, FieldResult, Request, ServerResult};
+#[cfg(feature = "async-graphql")]
+use async_trait::async_trait;
+#[cfg(feature = "async-graphql")]
+use deadpool::managed::Object;
+
+use crate::Pool;
+#[cfg(feature = "async-graphql")]
+use crate::{acquire, error, PoolManager};
+
+/// A primary pool for writes plus replica pools for reads. Reads are spread
+/// over the replicas round robin and fall back to the primary when there are
+/// no replicas.
+///
+/// Example usage
+/// ```ignore
+///  let primary = PoolConfig::new().build(PoolManager::new(&primary_url)?)?;
+///  let replica = PoolConfig::new().build(PoolManager::new(&replica_url)?)?;
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     MutationRoot::default(), EmptySubscription)
+///     .data(SplitPool::new(primary, vec![replica]));
+/// ```
+pub struct SplitPool {
+    write: Pool,
+    read: Vec<Pool>,
+    next_read: AtomicUsize,
+}
+
+impl SplitPool {
+    /// Creates a split pool from a primary pool and any number of replicas
+    pub fn new(write: Pool, read: Vec<Pool>) -> Self {
+        Self {
+            write,
+            read,
+            next_read: AtomicUsize::new(0),
+        }
+    }
+
+    /// The pool of the primary
+    pub fn write_pool(&self) -> &Pool {
+        &self.write
+    }
+
+    /// The pools of the replicas
+    pub fn read_pools(&self) -> &[Pool] {
+        &self.read
+    }
+
+    /// The next replica pool in round robin order, or the primary pool if
+    /// there are no replicas
+    pub fn read_pool(&self) -> &Pool {
+        if self.read.is_empty() {
+            return &self.write;
+        }
+        let next = self.next_read.fetch_add(1, Ordering::Relaxed);
+        &self.read[next % self.read.len()]
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+fn get_split_pool<'a>(ctx: &Context<'a>) -> FieldResult<&'a SplitPool> {
+    ctx.data_opt::<SplitPool>().ok_or_else(|| {
+        error::coded_error(
+            "Database split pool is not configured in the graphQL context".to_string(),
+            error::POOL_NOT_CONFIGURED,
+        )
+    })
+}
+
+#[cfg(feature = "async-graphql")]
+/// Records whether the current GraphQL request has asked for a write
+/// connection. Stored in the request data by [`ReadYourWrites`].
+#[derive(Clone, Default)]
+pub struct RequestWrites {
+    written: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "async-graphql")]
+impl RequestWrites {
+    /// Whether a write connection was handed out during this request
+    pub fn has_written(&self) -> bool {
+        self.written.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// An async_graphql extension that pins reads to the primary once a write
+/// connection was handed out in the same GraphQL request, so clients don't
+/// read stale replica data right after a mutation.
+///
+/// Example usage
+/// ```ignore
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     MutationRoot::default(), EmptySubscription)
+///     .data(split_pool)
+///     .extension(ReadYourWrites)
+///     .finish();
+/// ```
+pub struct ReadYourWrites;
+
+#[cfg(feature = "async-graphql")]
+impl ExtensionFactory for ReadYourWrites {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ReadYourWritesExtension)
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+struct ReadYourWritesExtension;
+
+#[cfg(feature = "async-graphql")]
+#[async_trait]
+impl Extension for ReadYourWritesExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        next.run(ctx, request.data(RequestWrites::default())).await
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// Extracts a connection to a replica out of the SplitPool stored in the
+/// graphQL context. Use it in query resolvers. If the [`ReadYourWrites`]
+/// extension is registered and the request already wrote to the primary, the
+/// connection comes from the primary instead.
+///
+/// Returns a Result with the connection object or a graphQL error
+/// # Arguments
+/// * `ctx` - graphQL context where the SplitPool object is stored
+pub async fn get_read_connection(ctx: &Context<'_>) -> FieldResult<Object<PoolManager>> {
+    let split_pool = get_split_pool(ctx)?;
+    let written = ctx
+        .data_opt::<RequestWrites>()
+        .is_some_and(RequestWrites::has_written);
+    if written {
+        acquire(split_pool.write_pool()).await
+    } else {
+        acquire(split_pool.read_pool()).await
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// Extracts a connection to the primary out of the SplitPool stored in the
+/// graphQL context. Use it in mutation resolvers.
+///
+/// Returns a Result with the connection object or a graphQL error
+/// # Arguments
+/// * `ctx` - graphQL context where the SplitPool object is stored
+pub async fn get_write_connection(ctx: &Context<'_>) -> FieldResult<Object<PoolManager>> {
+    let split_pool = get_split_pool(ctx)?;
+    if let Some(writes) = ctx.data_opt::<RequestWrites>() {
+        writes.written.store(true, Ordering::Relaxed);
+    }
+    acquire(split_pool.write_pool()).await
+}
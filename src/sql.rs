@@ -0,0 +1,99 @@
+use sqlx::postgres::PgArguments;
+use sqlx::Arguments;
+
+/// A bind value produced by the query builders of this crate
+///
+/// `Null` is sent as a text typed NULL, so compare it with `IS NULL` or add a
+/// cast when the column is not a text column.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl SqlValue {
+    /// Adds the value to a set of sqlx arguments
+    pub fn add_to(&self, args: &mut PgArguments) {
+        match self {
+            SqlValue::Null => args.add(Option::<String>::None),
+            SqlValue::Bool(v) => args.add(*v),
+            SqlValue::Int(v) => args.add(*v),
+            SqlValue::Float(v) => args.add(*v),
+            SqlValue::Text(v) => args.add(v.clone()),
+            SqlValue::Bytes(v) => args.add(v.clone()),
+        }
+    }
+}
+
+/// Collects bind values into sqlx arguments for `query_with`/`query_as_with`
+pub fn to_arguments(values: &[SqlValue]) -> PgArguments {
+    let mut args = PgArguments::default();
+    for value in values {
+        value.add_to(&mut args);
+    }
+    args
+}
+
+impl From<bool> for SqlValue {
+    fn from(v: bool) -> Self {
+        SqlValue::Bool(v)
+    }
+}
+
+impl From<i16> for SqlValue {
+    fn from(v: i16) -> Self {
+        SqlValue::Int(v.into())
+    }
+}
+
+impl From<i32> for SqlValue {
+    fn from(v: i32) -> Self {
+        SqlValue::Int(v.into())
+    }
+}
+
+impl From<i64> for SqlValue {
+    fn from(v: i64) -> Self {
+        SqlValue::Int(v)
+    }
+}
+
+impl From<f32> for SqlValue {
+    fn from(v: f32) -> Self {
+        SqlValue::Float(v.into())
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(v: f64) -> Self {
+        SqlValue::Float(v)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(v: &str) -> Self {
+        SqlValue::Text(v.to_string())
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(v: String) -> Self {
+        SqlValue::Text(v)
+    }
+}
+
+impl From<Vec<u8>> for SqlValue {
+    fn from(v: Vec<u8>) -> Self {
+        SqlValue::Bytes(v)
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(SqlValue::Null, Into::into)
+    }
+}
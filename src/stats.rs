@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use deadpool::managed::{Object, PoolError};
+use sqlx::Error as SqlxError;
+
+use crate::{Pool, PoolManager};
+
+/// Number of recent acquire wait times kept for the percentiles in [`PoolStats`]
+pub const WAIT_SAMPLE_SIZE: usize = 1024;
+
+/// A snapshot of the pool's state
+#[derive(Clone, Debug, Default)]
+pub struct PoolStats {
+    /// Maximum number of connections the pool can hold
+    pub max_size: usize,
+    /// Number of connections currently held by the pool
+    pub size: usize,
+    /// Number of connections ready to be handed out
+    pub idle: usize,
+    /// Number of connections currently checked out
+    pub in_use: usize,
+    /// Number of callers waiting for a connection
+    pub waiting: usize,
+    /// Median of the recent acquire wait times
+    pub wait_p50: Duration,
+    /// 90th percentile of the recent acquire wait times
+    pub wait_p90: Duration,
+    /// 99th percentile of the recent acquire wait times
+    pub wait_p99: Duration,
+}
+
+/// The most recent acquire wait times of a pool, recorded by the helpers of
+/// this crate when they get a connection
+#[derive(Default)]
+pub(crate) struct AcquireStats {
+    waits: Mutex<VecDeque<Duration>>,
+}
+
+impl AcquireStats {
+    pub(crate) fn record_wait(&self, wait: Duration) {
+        let mut waits = self.waits.lock().unwrap();
+        if waits.len() == WAIT_SAMPLE_SIZE {
+            waits.pop_front();
+        }
+        waits.push_back(wait);
+    }
+
+    /// Returns the p50, p90 and p99 of the recorded wait times
+    pub(crate) fn percentiles(&self) -> (Duration, Duration, Duration) {
+        let mut waits: Vec<Duration> = self.waits.lock().unwrap().iter().copied().collect();
+        if waits.is_empty() {
+            return Default::default();
+        }
+        waits.sort_unstable();
+        let at = |p: usize| waits[(waits.len() - 1) * p / 100];
+        (at(50), at(90), at(99))
+    }
+}
+
+/// Gets a connection from the pool, recording how long the caller waited
+pub(crate) async fn timed_get(pool: &Pool) -> Result<Object<PoolManager>, PoolError<SqlxError>> {
+    if pool.manager().is_draining() {
+        return Err(PoolError::Closed);
+    }
+    let started = Instant::now();
+    let res = pool.get().await;
+    let wait = started.elapsed();
+    pool.manager().acquire_stats.record_wait(wait);
+    #[cfg(feature = "prometheus")]
+    crate::metrics::observe_acquire(wait);
+    #[cfg(feature = "tracing")]
+    match &res {
+        Ok(_) => tracing::debug!(wait_ms = wait.as_millis() as u64, "acquired connection"),
+        Err(e) => tracing::warn!(
+            wait_ms = wait.as_millis() as u64,
+            error = %crate::error::redact(&e.to_string()),
+            "failed to acquire connection"
+        ),
+    }
+    res
+}
+
+/// Takes a snapshot of the pool's state. The wait percentiles cover the
+/// connections acquired through the helpers of this crate.
+pub(crate) fn pool_stats(pool: &Pool) -> PoolStats {
+    let status = pool.status();
+    let (wait_p50, wait_p90, wait_p99) = pool.manager().acquire_stats.percentiles();
+    PoolStats {
+        max_size: status.max_size,
+        size: status.size,
+        idle: status.available,
+        in_use: status.size.saturating_sub(status.available),
+        waiting: status.waiting,
+        wait_p50,
+        wait_p90,
+        wait_p99,
+    }
+}
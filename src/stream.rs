@@ -0,0 +1,41 @@
+use async_graphql::FieldResult;
+use futures::stream::{BoxStream, StreamExt};
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::query::QueryAs;
+use sqlx::{FromRow, PgConnection, Postgres};
+
+use crate::match_result;
+
+/// Runs `query` with sqlx's `fetch` instead of `fetch_all` and yields the
+/// rows one at a time as they arrive, so exports and large list fields don't
+/// buffer the whole result set in memory. The connection stays borrowed
+/// until the stream is dropped.
+///
+/// Returns a stream of rows, with failures mapped to graphQL errors
+/// # Arguments
+/// * `conn` - the connection to run the query on
+/// * `query` - the query with its binds, built with `query_as`
+///
+/// Example usage
+/// ```ignore
+/// let mut conn = get_db_connection(ctx).await?;
+/// let mut rows = fetch_stream(
+///     &mut conn,
+///     query_as::<_, Order>("SELECT * FROM orders WHERE created_at > $1").bind(since),
+/// );
+/// while let Some(order) = rows.next().await {
+///     writer.write_record(&order?)?;
+/// }
+/// ```
+pub fn fetch_stream<'e, T>(
+    conn: &'e mut PgConnection,
+    query: QueryAs<'e, Postgres, T, PgArguments>,
+) -> BoxStream<'e, FieldResult<T>>
+where
+    T: for<'r> FromRow<'r, PgRow> + Send + Unpin + 'e,
+{
+    query
+        .fetch(conn)
+        .map(|row| match_result(row, "Failed to fetch row".to_string()))
+        .boxed()
+}
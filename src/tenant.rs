@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+#[cfg(feature = "async-graphql")]
+use async_graphql::{Context, FieldResult};
+#[cfg(feature = "async-graphql")]
+use deadpool::managed::Object;
+use sqlx::Error as SqlxError;
+#[cfg(feature = "async-graphql")]
+use sqlx::{query, query_scalar};
+
+#[cfg(feature = "async-graphql")]
+use crate::{acquire, error, get_db_connection, match_result, ScopedConnection};
+use crate::{Pool, PoolConfig, PoolManager};
+
+/// Extension code returned by [`get_schema_connection`] for a schema that is
+/// not in the [`TenantSchemas`] allowlist
+pub const UNKNOWN_TENANT_SCHEMA: &str = "UNKNOWN_TENANT_SCHEMA";
+
+/// Default number of tenant pools kept open by [`TenantPools`]
+pub const DEFAULT_MAX_TENANTS: usize = 64;
+
+type ManagerFactory = dyn Fn(&str) -> Result<PoolManager, SqlxError> + Send + Sync;
+
+/// The cached pools, least recently used first in `order`
+#[derive(Default)]
+struct TenantCache {
+    pools: HashMap<String, Pool>,
+    order: VecDeque<String>,
+}
+
+impl TenantCache {
+    fn touch(&mut self, tenant_id: &str) {
+        if let Some(pos) = self.order.iter().position(|id| id == tenant_id) {
+            let id = self.order.remove(pos).unwrap();
+            self.order.push_back(id);
+        }
+    }
+}
+
+/// Pools for a multi-tenant deployment where every tenant has its own
+/// database or credentials. A tenant's pool is created on first use from the
+/// manager returned by the factory and cached; when more than `max_tenants`
+/// pools are open, the least recently used one is dropped. Connections still
+/// checked out of a dropped pool stay usable.
+///
+/// Example usage
+/// ```ignore
+///  let tenants = TenantPools::new(|tenant_id| {
+///      PoolManager::new(&format!("postgres://app@db/{}", tenant_id))
+///  })
+///  .config(PoolConfig::new().max_size(4))
+///  .tenant_max_size("big-customer", 32)
+///  .max_tenants(100);
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     EmptyMutation::default(), EmptySubscription).data(tenants);
+/// ```
+pub struct TenantPools {
+    factory: Box<ManagerFactory>,
+    config: PoolConfig,
+    max_sizes: HashMap<String, usize>,
+    max_tenants: usize,
+    cache: Mutex<TenantCache>,
+}
+
+impl TenantPools {
+    /// Creates an empty registry that builds tenant managers with `factory`
+    pub fn new<F>(factory: F) -> Self
+    where
+        F: Fn(&str) -> Result<PoolManager, SqlxError> + Send + Sync + 'static,
+    {
+        Self {
+            factory: Box::new(factory),
+            config: PoolConfig::default(),
+            max_sizes: HashMap::new(),
+            max_tenants: DEFAULT_MAX_TENANTS,
+            cache: Mutex::new(TenantCache::default()),
+        }
+    }
+
+    /// Sets the config every tenant pool is built with
+    pub fn config(mut self, config: PoolConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Overrides the maximum number of connections for one tenant
+    pub fn tenant_max_size(mut self, tenant_id: &str, max_size: usize) -> Self {
+        self.max_sizes.insert(tenant_id.to_string(), max_size);
+        self
+    }
+
+    /// Sets how many tenant pools are kept open at most
+    pub fn max_tenants(mut self, max_tenants: usize) -> Self {
+        self.max_tenants = max_tenants.max(1);
+        self
+    }
+
+    /// Returns the pool of a tenant, creating it if it isn't cached
+    ///
+    /// Returns the pool or the sqlx error of the factory, or a Configuration
+    /// error if the pool could not be built
+    /// # Arguments
+    /// * `tenant_id` - the tenant whose pool to get
+    pub fn pool(&self, tenant_id: &str) -> Result<Pool, SqlxError> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(pool) = cache.pools.get(tenant_id).cloned() {
+            cache.touch(tenant_id);
+            return Ok(pool);
+        }
+        let manager = (self.factory)(tenant_id)?;
+        let mut config = self.config.clone();
+        if let Some(max_size) = self.max_sizes.get(tenant_id) {
+            config = config.max_size(*max_size);
+        }
+        let pool = config
+            .build(manager)
+            .map_err(|e| SqlxError::Configuration(e.to_string().into()))?;
+        while cache.pools.len() >= self.max_tenants {
+            match cache.order.pop_front() {
+                Some(evicted) => {
+                    cache.pools.remove(&evicted);
+                }
+                None => break,
+            }
+        }
+        cache.pools.insert(tenant_id.to_string(), pool.clone());
+        cache.order.push_back(tenant_id.to_string());
+        Ok(pool)
+    }
+
+    /// Drops the cached pool of a tenant, e.g. after its credentials changed
+    pub fn evict(&self, tenant_id: &str) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.pools.remove(tenant_id);
+        cache.order.retain(|id| id != tenant_id);
+    }
+
+    /// The tenants that currently have an open pool
+    pub fn tenants(&self) -> Vec<String> {
+        self.cache.lock().unwrap().order.iter().cloned().collect()
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// Extracts a connection to a tenant's database out of the TenantPools
+/// stored in the graphQL context
+///
+/// Returns a Result with the connection object or a graphQL error. If the
+/// context has no TenantPools, the error carries the `POOL_NOT_CONFIGURED`
+/// code.
+/// # Arguments
+/// * `ctx` - graphQL context where the TenantPools object is stored
+/// * `tenant_id` - the tenant of the request
+pub async fn get_tenant_connection(
+    ctx: &Context<'_>,
+    tenant_id: &str,
+) -> FieldResult<Object<PoolManager>> {
+    let tenants = ctx.data_opt::<TenantPools>().ok_or_else(|| {
+        error::coded_error(
+            "Tenant pools are not configured in the graphQL context".to_string(),
+            error::POOL_NOT_CONFIGURED,
+        )
+    })?;
+    let pool = match_result(
+        tenants.pool(tenant_id),
+        format!("Failed to create pool for tenant {}", tenant_id),
+    )?;
+    acquire(&pool).await
+}
+
+/// The allowlist of tenant schemas for single-database multi-tenancy, where
+/// every tenant has its own schema. Store it in the graphQL context and use
+/// [`get_schema_connection`].
+///
+/// Example usage
+/// ```ignore
+///  let schemas = TenantSchemas::new(["tenant_acme", "tenant_globex"]);
+///  async_graphql::Schema::build(QueryRoot::default(),
+///     EmptyMutation::default(), EmptySubscription)
+///     .data(db_pool)
+///     .data(schemas);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TenantSchemas {
+    allowed: HashSet<String>,
+    include_public: bool,
+}
+
+impl TenantSchemas {
+    /// Creates an allowlist of the given schemas
+    pub fn new<I, S>(schemas: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            allowed: schemas.into_iter().map(Into::into).collect(),
+            include_public: false,
+        }
+    }
+
+    /// Adds a schema to the allowlist, e.g. after a tenant was provisioned
+    pub fn allow(mut self, schema: &str) -> Self {
+        self.allowed.insert(schema.to_string());
+        self
+    }
+
+    /// Keeps `public` on the search_path after the tenant schema, for shared
+    /// tables and extensions
+    pub fn include_public(mut self, include_public: bool) -> Self {
+        self.include_public = include_public;
+        self
+    }
+
+    /// Whether `schema` is in the allowlist
+    pub fn is_allowed(&self, schema: &str) -> bool {
+        self.allowed.contains(schema)
+    }
+
+    /// The search_path for `schema`, with the schema quoted as an identifier
+    fn search_path(&self, schema: &str) -> String {
+        let quoted = format!("\"{}\"", schema.replace('"', "\"\""));
+        if self.include_public {
+            format!("{}, public", quoted)
+        } else {
+            quoted
+        }
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// Gets a connection out of the Pool in the graphQL context with the
+/// search_path set to a tenant's schema. The search_path the connection had
+/// before, e.g. the one set by [`crate::SessionConfig`], is restored before
+/// the connection is reused (see [`ScopedConnection`]).
+///
+/// Returns the connection or a graphQL error with the `UNKNOWN_TENANT_SCHEMA`
+/// code if the schema is not allowed, or the `POOL_NOT_CONFIGURED` code if
+/// the context has no TenantSchemas
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool and TenantSchemas are stored
+/// * `schema` - the schema of the tenant of the request
+///
+/// Example usage
+/// ```ignore
+/// let mut conn = get_schema_connection(ctx, &tenant.schema).await?;
+/// let rows = query_as::<_, Order>("SELECT * FROM orders")
+///     .fetch_all(&mut *conn)
+///     .await;
+/// ```
+pub async fn get_schema_connection(
+    ctx: &Context<'_>,
+    schema: &str,
+) -> FieldResult<ScopedConnection> {
+    let schemas = ctx.data_opt::<TenantSchemas>().ok_or_else(|| {
+        error::coded_error(
+            "Tenant schemas are not configured in the graphQL context".to_string(),
+            error::POOL_NOT_CONFIGURED,
+        )
+    })?;
+    if !schemas.is_allowed(schema) {
+        return Err(error::coded_error(
+            format!("Unknown tenant schema {}", schema),
+            UNKNOWN_TENANT_SCHEMA,
+        ));
+    }
+    let mut conn = get_db_connection(ctx).await?;
+    let previous = query_scalar::<_, String>("SELECT current_setting('search_path')")
+        .fetch_one(&mut *conn)
+        .await;
+    let previous = match_result(previous, "Failed to read search_path".to_string())?;
+    // standard_conforming_strings is on, so doubling quotes escapes the value
+    let reset_sql = format!(
+        "SELECT set_config('search_path', '{}', false)",
+        previous.replace('\'', "''")
+    );
+    let mut conn = ScopedConnection::new(conn, reset_sql);
+    let res = query("SELECT set_config('search_path', $1, false)")
+        .bind(schemas.search_path(schema))
+        .execute(&mut *conn)
+        .await;
+    match_result(res, format!("Failed to set search_path to {}", schema))?;
+    Ok(conn)
+}
@@ -0,0 +1,49 @@
+use std::future::Future;
+use std::time::Duration;
+
+use async_graphql::FieldResult;
+
+use crate::error::SqlxHelperError;
+
+/// Runs `fut` with a time limit and returns a graphQL error with the
+/// `QUERY_TIMEOUT` code when it doesn't complete in time, so a single slow
+/// statement can't hold a resolver and its connection indefinitely.
+///
+/// On timeout the future is dropped, which stops waiting for the query but
+/// doesn't cancel it on the server; the connection is only usable again once
+/// the server finished it. Pair it with a `statement_timeout` (see
+/// [`crate::SessionConfig::statement_timeout`]) slightly above `duration` so
+/// the server cancels the query as well.
+///
+/// Returns the output of `fut` or a graphQL error
+/// # Arguments
+/// * `duration` - how long to wait for `fut`
+/// * `fut` - the query to run, typically ending in [`crate::match_result`]
+///
+/// Example usage
+/// ```ignore
+/// let rows = with_timeout(Duration::from_secs(2), async {
+///     let rows = query_as::<_, MyData>("SELECT * FROM my_data")
+///         .fetch_all(&mut *conn)
+///         .await;
+///     match_result(rows, format!("Failed to get my_data"))
+/// })
+/// .await?;
+/// ```
+pub async fn with_timeout<T, F>(duration: Duration, fut: F) -> FieldResult<T>
+where
+    F: Future<Output = FieldResult<T>>,
+{
+    match tokio::time::timeout(duration, fut).await {
+        Ok(res) => res,
+        Err(_) => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(timeout_ms = duration.as_millis() as u64, "query timed out");
+            Err(SqlxHelperError::QueryTimeout {
+                message: "Query did not complete".to_string(),
+                timeout: duration,
+            }
+            .into_field_error())
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use sqlx::postgres::{PgConnectOptions, PgSslMode};
+
+/// A root certificate used to verify the server, given either as a file path
+/// or as PEM encoded bytes
+#[derive(Clone, Debug)]
+pub enum RootCert {
+    Path(PathBuf),
+    Pem(Vec<u8>),
+}
+
+/// TLS settings applied to every connection created by a [`crate::PoolManager`]
+///
+/// Only the server is authenticated: client certificates (`sslcert` and
+/// `sslkey`) are not supported, as the pinned sqlx revision can't present
+/// one. Servers requiring certificate authentication (`cert` in
+/// `pg_hba.conf`) can't be reached with this crate until sqlx is upgraded;
+/// use a password, or [`crate::CredentialsProvider`] tokens, over TLS instead.
+///
+/// Example usage
+/// ```ignore
+///  let tls = TlsConfig::verify_full(RootCert::Path("/etc/ssl/rds-ca.pem".into()));
+///  let mgr = PoolManager::new(&database_url)?.with_tls(tls);
+/// ```
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub ssl_mode: PgSslMode,
+    pub root_cert: Option<RootCert>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            ssl_mode: PgSslMode::Prefer,
+            root_cert: None,
+        }
+    }
+}
+
+impl TlsConfig {
+    /// Requires TLS and verifies both the certificate chain and the host name
+    pub fn verify_full(root_cert: RootCert) -> Self {
+        Self {
+            ssl_mode: PgSslMode::VerifyFull,
+            root_cert: Some(root_cert),
+        }
+    }
+
+    /// Requires TLS and verifies the certificate chain only
+    pub fn verify_ca(root_cert: RootCert) -> Self {
+        Self {
+            ssl_mode: PgSslMode::VerifyCa,
+            root_cert: Some(root_cert),
+        }
+    }
+
+    /// Requires TLS without verifying the server certificate
+    pub fn require() -> Self {
+        Self {
+            ssl_mode: PgSslMode::Require,
+            root_cert: None,
+        }
+    }
+
+    pub(crate) fn apply(&self, options: PgConnectOptions) -> PgConnectOptions {
+        let options = options.ssl_mode(self.ssl_mode);
+        match &self.root_cert {
+            Some(RootCert::Path(path)) => options.ssl_root_cert(path),
+            Some(RootCert::Pem(pem)) => options.ssl_root_cert_from_pem(pem.clone()),
+            None => options,
+        }
+    }
+}
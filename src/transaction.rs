@@ -0,0 +1,256 @@
+use std::time::Duration;
+
+#[cfg(feature = "async-graphql")]
+use async_graphql::{Context, FieldResult};
+use futures::future::BoxFuture;
+use sqlx::{Connection, Error as SqlxError, PgConnection, Postgres, Transaction};
+
+use crate::error::{is_retryable_transaction_error, pool_error_to_sqlx};
+#[cfg(feature = "async-graphql")]
+use crate::error::{map_sqlx_error, map_tx_error};
+#[cfg(feature = "async-graphql")]
+use crate::get_db_connection;
+use crate::stats::timed_get;
+use crate::Pool;
+
+/// Runs `f` inside a database transaction using a connection from `pool`.
+/// The transaction is committed if `f` returns Ok and rolled back if it
+/// returns Err. This is [`with_transaction`] for code without a graphQL
+/// context, e.g. workers and CLI tools.
+///
+/// Returns the value produced by `f`, or its error or the sqlx error of
+/// acquiring, beginning or committing converted into `E`
+/// # Arguments
+/// * `pool` - the pool to get the connection from
+/// * `f` - a closure that receives the open transaction
+///
+/// Example usage
+/// ```ignore
+/// run_transaction(&db_pool, |tx| {
+///     Box::pin(async move {
+///         query("DELETE FROM sessions WHERE expires_at < now()")
+///             .execute(&mut *tx)
+///             .await?;
+///         Ok::<_, sqlx::Error>(())
+///     })
+/// })
+/// .await?;
+/// ```
+pub async fn run_transaction<T, E, F>(pool: &Pool, f: F) -> Result<T, E>
+where
+    T: Send,
+    E: From<SqlxError>,
+    F: for<'c> FnOnce(&'c mut Transaction<'_, Postgres>) -> BoxFuture<'c, Result<T, E>>,
+{
+    let mut conn = timed_get(pool).await.map_err(pool_error_to_sqlx)?;
+    let mut tx = conn.begin().await?;
+    match f(&mut tx).await {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            let _ = tx.rollback().await;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+/// Runs `f` inside a database transaction using a connection from the Pool
+/// stored in the graphQL context. The transaction is committed if `f` returns
+/// Ok and rolled back if it returns Err.
+///
+/// Returns the value produced by `f` or a graphQL error
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool object is stored
+/// * `f` - a closure that receives the open transaction
+///
+/// Example usage
+/// ```ignore
+/// // somewhere in your mutation resolver
+/// with_transaction(ctx, |tx| {
+///     Box::pin(async move {
+///         let res = query("INSERT INTO my_data (name) VALUES ($1)")
+///             .bind(name)
+///             .execute(&mut *tx)
+///             .await;
+///         match_result(res, format!("Failed to insert my_data"))
+///     })
+/// })
+/// .await?;
+/// ```
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "sqlx_helpers.with_transaction", skip_all)
+)]
+pub async fn with_transaction<T, F>(ctx: &Context<'_>, f: F) -> FieldResult<T>
+where
+    T: Send,
+    F: for<'c> FnOnce(&'c mut Transaction<'_, Postgres>) -> BoxFuture<'c, FieldResult<T>>,
+{
+    let mut conn = get_db_connection(ctx).await?;
+    let mut tx = conn
+        .begin()
+        .await
+        .map_err(|e| map_tx_error(e, "Failed to begin transaction"))?;
+    match f(&mut tx).await {
+        Ok(value) => {
+            tx.commit()
+                .await
+                .map_err(|e| map_tx_error(e, "Failed to commit transaction"))?;
+            Ok(value)
+        }
+        Err(e) => {
+            // the resolver error is more useful to the caller than a rollback error
+            let _ = tx.rollback().await;
+            Err(e)
+        }
+    }
+}
+
+/// How often and how quickly [`retry_transaction`] replays a transaction and
+/// [`crate::execute_with_retry`] reruns a statement
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every further retry
+    pub base_delay: Duration,
+    /// Upper bound for the delay between two attempts
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay to wait after the given failed attempt (starting at 1)
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Replays the transaction on `conn` until it succeeds, fails with a non
+/// retryable error or the attempts of the policy are exhausted. Errors come
+/// with the number of attempts made.
+async fn retry_on<T, F>(
+    conn: &mut PgConnection,
+    policy: &RetryPolicy,
+    mut f: F,
+) -> Result<T, (SqlxError, u32)>
+where
+    T: Send,
+    F: for<'c> FnMut(&'c mut Transaction<'_, Postgres>) -> BoxFuture<'c, Result<T, SqlxError>>,
+{
+    let mut attempt = 1;
+    loop {
+        let res: Result<T, SqlxError> = async {
+            let mut tx = conn.begin().await?;
+            match f(&mut tx).await {
+                Ok(value) => tx.commit().await.map(|_| value),
+                Err(e) => {
+                    let _ = tx.rollback().await;
+                    Err(e)
+                }
+            }
+        }
+        .await;
+        match res {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < policy.max_attempts && is_retryable_transaction_error(&e) => {
+                #[cfg(feature = "tracing")]
+                tracing::info!(
+                    attempt,
+                    error = %crate::error::redact(&e.to_string()),
+                    "retrying transaction"
+                );
+                tokio::time::sleep(policy.delay_for(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err((e, attempt)),
+        }
+    }
+}
+
+/// Like [`run_transaction`], but replays the whole transaction on
+/// serialization failures and deadlocks (see [`retry_transaction`])
+///
+/// Returns the value produced by `f` or the last sqlx error
+/// # Arguments
+/// * `pool` - the pool to get the connection from
+/// * `policy` - the number of attempts and the backoff between them
+/// * `f` - a closure that receives the open transaction
+pub async fn run_retry_transaction<T, F>(
+    pool: &Pool,
+    policy: &RetryPolicy,
+    f: F,
+) -> Result<T, SqlxError>
+where
+    T: Send,
+    F: for<'c> FnMut(&'c mut Transaction<'_, Postgres>) -> BoxFuture<'c, Result<T, SqlxError>>,
+{
+    let mut conn = timed_get(pool).await.map_err(pool_error_to_sqlx)?;
+    retry_on(&mut conn, policy, f).await.map_err(|(e, _)| e)
+}
+
+/// Like [`with_transaction`], but replays the whole transaction when it fails
+/// with a serialization failure (SQLSTATE 40001) or a deadlock (40P01),
+/// including failures reported at commit time. The closure may run several
+/// times, so it must not have side effects outside the transaction.
+///
+/// Returns the value produced by `f` or a graphQL error once the attempts of
+/// the policy are exhausted or a non retryable error occurs
+/// # Arguments
+/// * `ctx` - graphQL context where the Pool object is stored
+/// * `policy` - the number of attempts and the backoff between them
+/// * `f` - a closure that receives the open transaction
+///
+/// Example usage
+/// ```ignore
+/// retry_transaction(ctx, &RetryPolicy::default(), |tx| {
+///     Box::pin(async move {
+///         query("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE")
+///             .execute(&mut *tx)
+///             .await?;
+///         query("UPDATE counters SET value = value + 1 WHERE id = $1")
+///             .bind(id)
+///             .execute(&mut *tx)
+///             .await?;
+///         Ok(())
+///     })
+/// })
+/// .await?;
+/// ```
+#[cfg(feature = "async-graphql")]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "sqlx_helpers.retry_transaction", skip_all)
+)]
+pub async fn retry_transaction<T, F>(
+    ctx: &Context<'_>,
+    policy: &RetryPolicy,
+    f: F,
+) -> FieldResult<T>
+where
+    T: Send,
+    F: for<'c> FnMut(&'c mut Transaction<'_, Postgres>) -> BoxFuture<'c, Result<T, SqlxError>>,
+{
+    let mut conn = get_db_connection(ctx).await?;
+    retry_on(&mut conn, policy, f)
+        .await
+        .map_err(|(e, attempts)| {
+            map_sqlx_error(
+                e,
+                &format!("Transaction failed after {} attempt(s)", attempts),
+            )
+        })
+}
@@ -0,0 +1,257 @@
+#[cfg(feature = "async-graphql")]
+use async_graphql::FieldResult;
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::{Error as SqlxError, FromRow, PgConnection};
+
+#[cfg(feature = "async-graphql")]
+use crate::match_result;
+use crate::{to_arguments, SqlValue};
+
+/// The unique index or constraint that detects a conflicting row
+#[derive(Clone, Debug)]
+enum ConflictTarget {
+    Columns(Vec<String>),
+    Constraint(String),
+}
+
+/// Builds an `INSERT ... ON CONFLICT ... DO UPDATE ... RETURNING` statement.
+/// Without columns to update the statement is `ON CONFLICT DO NOTHING`, which
+/// returns no row for a conflicting insert.
+///
+/// Write a column as `name::type`, e.g. `"archived_at::timestamptz"`, to cast
+/// its placeholder, which is needed for NULLs of non text columns. Table and
+/// column names are written into the SQL as given, so never pass user input
+/// as a name.
+///
+/// Example usage
+/// ```ignore
+/// let user: User = Upsert::new("users")
+///     .value("email", email.as_str())
+///     .value("name", name.as_str())
+///     .value("deleted_at::timestamptz", SqlValue::Null)
+///     .conflict_on(&["email"])
+///     .update(&["name", "deleted_at"])
+///     .returning("*")
+///     .fetch_one_mapped(&mut conn, format!("Failed to save user {}", email))
+///     .await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct Upsert {
+    table: String,
+    columns: Vec<(String, Option<String>)>,
+    values: Vec<SqlValue>,
+    target: Option<ConflictTarget>,
+    update: Vec<String>,
+    returning: Option<String>,
+}
+
+impl Upsert {
+    /// Starts an upsert into `table`
+    pub fn new(table: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            columns: Vec::new(),
+            values: Vec::new(),
+            target: None,
+            update: Vec::new(),
+            returning: None,
+        }
+    }
+
+    /// Adds a column and the value to insert into it
+    pub fn value(mut self, column: &str, value: impl Into<SqlValue>) -> Self {
+        let (name, cast) = match column.split_once("::") {
+            Some((name, cast)) => (name, Some(cast.to_string())),
+            None => (column, None),
+        };
+        self.columns.push((name.to_string(), cast));
+        self.values.push(value.into());
+        self
+    }
+
+    /// Sets the columns of the unique index that detects the conflict
+    pub fn conflict_on(mut self, columns: &[&str]) -> Self {
+        self.target = Some(ConflictTarget::Columns(
+            columns.iter().map(|c| c.to_string()).collect(),
+        ));
+        self
+    }
+
+    /// Sets the name of the constraint that detects the conflict
+    pub fn conflict_on_constraint(mut self, constraint: &str) -> Self {
+        self.target = Some(ConflictTarget::Constraint(constraint.to_string()));
+        self
+    }
+
+    /// Sets the columns overwritten with the inserted values on conflict
+    pub fn update(mut self, columns: &[&str]) -> Self {
+        self.update = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    /// Sets the `RETURNING` list, e.g. `"*"` or `"id, updated_at"`
+    pub fn returning(mut self, returning: &str) -> Self {
+        self.returning = Some(returning.to_string());
+        self
+    }
+
+    /// The statement, with the values as `$1..$n` in the order they were added
+    pub fn to_sql(&self) -> String {
+        let names: Vec<&str> = self.columns.iter().map(|(name, _)| name.as_str()).collect();
+        let params: Vec<String> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, (_, cast))| match cast {
+                Some(cast) => format!("${}::{}", i + 1, cast),
+                None => format!("${}", i + 1),
+            })
+            .collect();
+        let mut sql = format!(
+            "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT",
+            self.table,
+            names.join(", "),
+            params.join(", ")
+        );
+        match &self.target {
+            Some(ConflictTarget::Columns(columns)) => {
+                sql.push_str(&format!(" ({})", columns.join(", ")))
+            }
+            Some(ConflictTarget::Constraint(name)) => {
+                sql.push_str(&format!(" ON CONSTRAINT {}", name))
+            }
+            None => {}
+        }
+        if self.update.is_empty() {
+            sql.push_str(" DO NOTHING");
+        } else {
+            let set: Vec<String> = self
+                .update
+                .iter()
+                .map(|column| format!("{} = EXCLUDED.{}", column, column))
+                .collect();
+            sql.push_str(&format!(" DO UPDATE SET {}", set.join(", ")));
+        }
+        if let Some(returning) = &self.returning {
+            sql.push_str(&format!(" RETURNING {}", returning));
+        }
+        sql
+    }
+
+    /// The values as sqlx arguments
+    pub fn arguments(&self) -> PgArguments {
+        to_arguments(&self.values)
+    }
+
+    /// Runs the upsert
+    ///
+    /// Returns the number of inserted or updated rows or the sqlx error
+    pub async fn execute(&self, conn: &mut PgConnection) -> Result<u64, SqlxError> {
+        let res = sqlx::query_with(&self.to_sql(), self.arguments())
+            .execute(conn)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Runs the upsert and maps the `RETURNING` row into `T`
+    ///
+    /// Returns the row, `None` when a `DO NOTHING` upsert skipped the insert,
+    /// or the sqlx error
+    pub async fn fetch_optional<T>(&self, conn: &mut PgConnection) -> Result<Option<T>, SqlxError>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    {
+        sqlx::query_as_with(&self.to_sql(), self.arguments())
+            .fetch_optional(conn)
+            .await
+    }
+
+    /// Runs the upsert and maps the `RETURNING` row into `T`
+    ///
+    /// Returns the row or the sqlx error, `RowNotFound` when a `DO NOTHING`
+    /// upsert skipped the insert
+    pub async fn fetch_one<T>(&self, conn: &mut PgConnection) -> Result<T, SqlxError>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    {
+        sqlx::query_as_with(&self.to_sql(), self.arguments())
+            .fetch_one(conn)
+            .await
+    }
+
+    #[cfg(feature = "async-graphql")]
+    /// Like [`Upsert::fetch_one`], with failures mapped to a graphQL error by
+    /// [`crate::match_result`]
+    ///
+    /// # Arguments
+    /// * `conn` - the connection to run the upsert on
+    /// * `err_msg` - a custom error message that will be prepended if Err is returned
+    pub async fn fetch_one_mapped<T>(
+        &self,
+        conn: &mut PgConnection,
+        err_msg: String,
+    ) -> FieldResult<T>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    {
+        match_result(self.fetch_one(conn).await, err_msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_on_conflicting_columns() {
+        let upsert = Upsert::new("users")
+            .value("email", "a@example.com")
+            .value("name", "Ada")
+            .value("deleted_at::timestamptz", SqlValue::Null)
+            .conflict_on(&["email"])
+            .update(&["name", "deleted_at"])
+            .returning("*");
+        assert_eq!(
+            upsert.to_sql(),
+            "INSERT INTO users (email, name, deleted_at) VALUES ($1, $2, $3::timestamptz) \
+             ON CONFLICT (email) DO UPDATE SET name = EXCLUDED.name, \
+             deleted_at = EXCLUDED.deleted_at RETURNING *"
+        );
+        assert_eq!(
+            upsert.values,
+            vec![
+                SqlValue::Text("a@example.com".to_string()),
+                SqlValue::Text("Ada".to_string()),
+                SqlValue::Null
+            ]
+        );
+    }
+
+    #[test]
+    fn updates_on_constraint() {
+        let sql = Upsert::new("memberships")
+            .value("user_id", 1i64)
+            .value("role", "admin")
+            .conflict_on_constraint("memberships_user_id_key")
+            .update(&["role"])
+            .to_sql();
+        assert_eq!(
+            sql,
+            "INSERT INTO memberships (user_id, role) VALUES ($1, $2) \
+             ON CONFLICT ON CONSTRAINT memberships_user_id_key DO UPDATE SET role = EXCLUDED.role"
+        );
+    }
+
+    #[test]
+    fn does_nothing_without_columns_to_update() {
+        let sql = Upsert::new("tags")
+            .value("name", "rust")
+            .conflict_on(&["name"])
+            .returning("id")
+            .to_sql();
+        assert_eq!(
+            sql,
+            "INSERT INTO tags (name) VALUES ($1) ON CONFLICT (name) DO NOTHING RETURNING id"
+        );
+    }
+}
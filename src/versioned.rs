@@ -0,0 +1,85 @@
+use async_graphql::FieldResult;
+use sqlx::{Arguments, Encode, PgConnection, Postgres, Type};
+
+use crate::{error, match_result, to_arguments, SqlValue};
+
+/// Extension code returned by [`update_versioned`] when the row no longer has
+/// the expected version, i.e. someone else updated it in the meantime
+pub const STALE_VERSION: &str = "STALE_VERSION";
+
+/// The column holding the version of rows updated with [`update_versioned`]
+pub const VERSION_COLUMN: &str = "version";
+
+/// Updates the row of `table` with the given `id` only if its `version` is
+/// still `expected_version`, and bumps the version, so concurrent mutations
+/// of the same row can't silently overwrite each other.
+///
+/// Write a column as `name::type`, e.g. `"due_at::timestamptz"`, to cast its
+/// placeholder. Table and column names are written into the SQL as given, so
+/// never pass user input as a name.
+///
+/// Returns the new version, or a graphQL error with the `STALE_VERSION` code
+/// when no row has that id and version
+/// # Arguments
+/// * `conn` - the connection to run the update on
+/// * `table` - the table of the row, with an `id` and a bigint `version` column
+/// * `id` - the id of the row
+/// * `expected_version` - the version the client read
+/// * `set` - the columns to update and their new values
+///
+/// Example usage
+/// ```ignore
+/// let version = update_versioned(
+///     &mut conn,
+///     "documents",
+///     input.id,
+///     input.version,
+///     &[("title", input.title.into()), ("body", input.body.into())],
+/// )
+/// .await?;
+/// ```
+pub async fn update_versioned<I>(
+    conn: &mut PgConnection,
+    table: &str,
+    id: I,
+    expected_version: i64,
+    set: &[(&str, SqlValue)],
+) -> FieldResult<i64>
+where
+    I: for<'q> Encode<'q, Postgres> + Type<Postgres> + Send,
+{
+    let mut clauses: Vec<String> = set
+        .iter()
+        .enumerate()
+        .map(|(i, (column, _))| match column.split_once("::") {
+            Some((name, cast)) => format!("{} = ${}::{}", name, i + 1, cast),
+            None => format!("{} = ${}", column, i + 1),
+        })
+        .collect();
+    clauses.push(format!("{col} = {col} + 1", col = VERSION_COLUMN));
+    let sql = format!(
+        "UPDATE {} SET {} WHERE id = ${} AND {col} = ${} RETURNING {col}",
+        table,
+        clauses.join(", "),
+        set.len() + 1,
+        set.len() + 2,
+        col = VERSION_COLUMN
+    );
+    let values: Vec<SqlValue> = set.iter().map(|(_, value)| value.clone()).collect();
+    let mut args = to_arguments(&values);
+    args.add(id);
+    args.add(expected_version);
+    let version = sqlx::query_scalar_with::<_, i64, _>(&sql, args)
+        .fetch_optional(conn)
+        .await;
+    match match_result(version, format!("Failed to update {}", table))? {
+        Some(version) => Ok(version),
+        None => Err(error::coded_error(
+            format!(
+                "The {} row was modified or deleted since version {} was read",
+                table, expected_version
+            ),
+            STALE_VERSION,
+        )),
+    }
+}
@@ -0,0 +1,53 @@
+use warp::http::StatusCode;
+use warp::reject::{Reject, Rejection};
+use warp::{Filter, Reply};
+
+use crate::db_conn::acquire_conn;
+use crate::{DbConn, DbConnRejection, Pool};
+
+impl Reject for DbConnRejection {}
+
+/// A warp filter that yields a clone of the Pool, e.g. to hand it to an
+/// async_graphql schema or to handlers that acquire connections themselves
+pub fn with_pool(
+    pool: Pool,
+) -> impl Filter<Extract = (Pool,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || pool.clone())
+}
+
+/// A warp filter that gets a connection from the Pool for every request.
+/// Acquisition failures reject with a [`DbConnRejection`]; use
+/// [`recover_db_conn`] to turn them into responses.
+///
+/// Example usage
+/// ```ignore
+///  let webhook = warp::path("webhook")
+///      .and(warp::post())
+///      .and(with_db_conn(db_pool.clone()))
+///      .and(warp::body::bytes())
+///      .and_then(handle_webhook)
+///      .recover(recover_db_conn);
+/// ```
+pub fn with_db_conn(pool: Pool) -> impl Filter<Extract = (DbConn,), Error = Rejection> + Clone {
+    with_pool(pool).and_then(|pool: Pool| async move {
+        acquire_conn(&pool).await.map_err(warp::reject::custom)
+    })
+}
+
+/// Responds to a [`DbConnRejection`] with `503 Service Unavailable` and
+/// passes on any other rejection
+///
+/// # Arguments
+/// * `err` - the rejection to recover from
+pub async fn recover_db_conn(err: Rejection) -> Result<impl Reply, Rejection> {
+    match err.find::<DbConnRejection>() {
+        Some(rejection) => {
+            let status = match rejection {
+                DbConnRejection::NotConfigured => StatusCode::INTERNAL_SERVER_ERROR,
+                DbConnRejection::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            };
+            Ok(warp::reply::with_status(rejection.to_string(), status))
+        }
+        None => Err(err),
+    }
+}